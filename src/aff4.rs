@@ -0,0 +1,1415 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufWriter, Read, Seek, SeekFrom, Write};
+use std::ops::Range;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use flate2::Compression;
+use flate2::read::{DeflateDecoder, ZlibDecoder};
+use flate2::write::DeflateEncoder;
+
+use crate::bytes::as_bytes;
+use crate::crashdump::unix_time_parts;
+use crate::physmem::PhysicalMemory;
+use crate::result::{Result, Error};
+use crate::zdmp::CRC32_IEEE;
+
+pub const AFF4_NS:          &str = "http://aff4.org/Schema#";
+pub const RDF_NS:           &str = "http://www.w3.org/1999/02/22-rdf-syntax-ns#";
+pub const XSD_NS:           &str = "http://www.w3.org/2001/XMLSchema#";
+/// Namespace of the zdmp acquisition metadata, which AFF4 has no terms for.
+pub const ZDMP_NS:          &str = "https://github.com/comaeio/z2dmp#";
+
+pub const AFF4_DEFLATE:     &str = "https://tools.ietf.org/html/rfc1951";
+pub const AFF4_ZLIB:        &str = "https://www.ietf.org/rfc/rfc1950.txt";
+
+pub const AFF4_CHUNK_SIZE:          usize = 0x8000;
+pub const AFF4_CHUNKS_IN_SEGMENT:   usize = 1024;
+
+/// Bevy index entry: chunk offset (u64) and length (u32).
+const BEVY_INDEX_ENTRY_SIZE: usize = 12;
+/// Map entry: mapped offset, length, target offset (u64) and target index (u32).
+const MAP_ENTRY_SIZE: usize = 28;
+
+const ZIP_LOCAL_HEADER_SIGNATURE:   u32 = 0x0403_4b50;
+const ZIP_CENTRAL_HEADER_SIGNATURE: u32 = 0x0201_4b50;
+const ZIP_END_SIGNATURE:            u32 = 0x0605_4b50;
+const ZIP64_END_SIGNATURE:          u32 = 0x0606_4b50;
+const ZIP64_LOCATOR_SIGNATURE:      u32 = 0x0706_4b50;
+const ZIP64_EXTRA_FIELD:            u16 = 1;
+const ZIP_STORED:                   u16 = 0;
+const ZIP_DEFLATED:                 u16 = 8;
+/// 1980-01-01, the earliest DOS date.
+const ZIP_DOS_DATE:                 u16 = 0x21;
+/// Field value meaning "see the zip64 extra field".
+const ZIP64_LIMIT:                  u64 = 0xffff_ffff;
+
+#[repr(C, packed)]
+#[derive(Debug, Copy, Clone)]
+struct ZipLocalHeader {
+    signature:          u32,
+    version_needed:     u16,
+    flags:              u16,
+    compression:        u16,
+    mod_time:           u16,
+    mod_date:           u16,
+    crc32:              u32,
+    compressed_size:    u32,
+    uncompressed_size:  u32,
+    name_len:           u16,
+    extra_len:          u16,
+}
+
+#[repr(C, packed)]
+#[derive(Debug, Copy, Clone)]
+struct ZipCentralHeader {
+    signature:          u32,
+    version_made_by:    u16,
+    version_needed:     u16,
+    flags:              u16,
+    compression:        u16,
+    mod_time:           u16,
+    mod_date:           u16,
+    crc32:              u32,
+    compressed_size:    u32,
+    uncompressed_size:  u32,
+    name_len:           u16,
+    extra_len:          u16,
+    comment_len:        u16,
+    disk_start:         u16,
+    internal_attributes: u16,
+    external_attributes: u32,
+    local_header_offset: u32,
+}
+
+#[repr(C, packed)]
+#[derive(Debug, Copy, Clone)]
+struct ZipEnd {
+    signature:          u32,
+    disk:               u16,
+    directory_disk:     u16,
+    disk_entries:       u16,
+    entries:            u16,
+    directory_size:     u32,
+    directory_offset:   u32,
+    comment_len:        u16,
+}
+
+#[repr(C, packed)]
+#[derive(Debug, Copy, Clone)]
+struct Zip64End {
+    signature:          u32,
+    record_size:        u64,
+    version_made_by:    u16,
+    version_needed:     u16,
+    disk:               u32,
+    directory_disk:     u32,
+    disk_entries:       u64,
+    entries:            u64,
+    directory_size:     u64,
+    directory_offset:   u64,
+}
+
+#[repr(C, packed)]
+#[derive(Debug, Copy, Clone)]
+struct Zip64Locator {
+    signature:          u32,
+    disk:               u32,
+    end_offset:         u64,
+    disks:              u32,
+}
+
+/// A member written by `ZipWriter`.
+struct ZipMember {
+    name:       String,
+    offset:     u64,
+    size:       u64,
+    crc32:      u32,
+}
+
+/// Writer of stored zip members, with zip64 records when needed.
+struct ZipWriter<W> {
+    out:        W,
+    offset:     u64,
+    members:    Vec<ZipMember>,
+}
+
+impl<W: Write> ZipWriter<W> {
+    fn new(out: W) -> Self {
+        ZipWriter { out, offset: 0, members: Vec::new() }
+    }
+
+    /// Writer of the part of a zip file that starts at `offset`.
+    #[cfg(test)]
+    fn at(out: W, offset: u64) -> Self {
+        ZipWriter { out, offset, members: Vec::new() }
+    }
+
+    fn add(&mut self, name: &str, data: &[u8]) -> io::Result<()> {
+        let size = data.len() as u64;
+        let zip64 = size >= ZIP64_LIMIT;
+
+        let mut extra = Vec::new();
+        if zip64 {
+            extra.extend_from_slice(&ZIP64_EXTRA_FIELD.to_le_bytes());
+            extra.extend_from_slice(&16u16.to_le_bytes());
+            extra.extend_from_slice(&size.to_le_bytes());
+            extra.extend_from_slice(&size.to_le_bytes());
+        }
+
+        let crc32 = CRC32_IEEE.checksum(data);
+        let hdr = ZipLocalHeader {
+            signature: ZIP_LOCAL_HEADER_SIGNATURE,
+            version_needed: if zip64 { 45 } else { 20 },
+            flags: 0,
+            compression: ZIP_STORED,
+            mod_time: 0,
+            mod_date: ZIP_DOS_DATE,
+            crc32,
+            compressed_size: size.min(ZIP64_LIMIT) as u32,
+            uncompressed_size: size.min(ZIP64_LIMIT) as u32,
+            name_len: name.len() as u16,
+            extra_len: extra.len() as u16,
+        };
+
+        self.out.write_all(as_bytes(&hdr))?;
+        self.out.write_all(name.as_bytes())?;
+        self.out.write_all(&extra)?;
+        self.out.write_all(data)?;
+
+        self.members.push(ZipMember { name: name.to_string(), offset: self.offset, size, crc32 });
+        self.offset += (std::mem::size_of::<ZipLocalHeader>() + name.len() + extra.len()) as u64 + size;
+
+        Ok(())
+    }
+
+    /// Write the central directory, ending with `comment`.
+    fn finish(mut self, comment: &str) -> io::Result<W> {
+        let directory_offset = self.offset;
+        let mut directory = Vec::new();
+
+        for member in &self.members {
+            let mut extra = Vec::new();
+            if member.size >= ZIP64_LIMIT {
+                extra.extend_from_slice(&member.size.to_le_bytes());
+                extra.extend_from_slice(&member.size.to_le_bytes());
+            }
+            if member.offset >= ZIP64_LIMIT {
+                extra.extend_from_slice(&member.offset.to_le_bytes());
+            }
+            if !extra.is_empty() {
+                let mut field = ZIP64_EXTRA_FIELD.to_le_bytes().to_vec();
+                field.extend_from_slice(&(extra.len() as u16).to_le_bytes());
+                extra.splice(0..0, field);
+            }
+
+            let hdr = ZipCentralHeader {
+                signature: ZIP_CENTRAL_HEADER_SIGNATURE,
+                version_made_by: 45,
+                version_needed: if extra.is_empty() { 20 } else { 45 },
+                flags: 0,
+                compression: ZIP_STORED,
+                mod_time: 0,
+                mod_date: ZIP_DOS_DATE,
+                crc32: member.crc32,
+                compressed_size: member.size.min(ZIP64_LIMIT) as u32,
+                uncompressed_size: member.size.min(ZIP64_LIMIT) as u32,
+                name_len: member.name.len() as u16,
+                extra_len: extra.len() as u16,
+                comment_len: 0,
+                disk_start: 0,
+                internal_attributes: 0,
+                external_attributes: 0,
+                local_header_offset: member.offset.min(ZIP64_LIMIT) as u32,
+            };
+
+            directory.extend_from_slice(as_bytes(&hdr));
+            directory.extend_from_slice(member.name.as_bytes());
+            directory.extend_from_slice(&extra);
+        }
+
+        self.out.write_all(&directory)?;
+        let directory_size = directory.len() as u64;
+        let entries = self.members.len() as u64;
+
+        if entries >= 0xffff || directory_offset >= ZIP64_LIMIT || directory_size >= ZIP64_LIMIT {
+            let end = Zip64End {
+                signature: ZIP64_END_SIGNATURE,
+                record_size: (std::mem::size_of::<Zip64End>() - 12) as u64,
+                version_made_by: 45,
+                version_needed: 45,
+                disk: 0,
+                directory_disk: 0,
+                disk_entries: entries,
+                entries,
+                directory_size,
+                directory_offset,
+            };
+            let locator = Zip64Locator {
+                signature: ZIP64_LOCATOR_SIGNATURE,
+                disk: 0,
+                end_offset: directory_offset + directory_size,
+                disks: 1,
+            };
+            self.out.write_all(as_bytes(&end))?;
+            self.out.write_all(as_bytes(&locator))?;
+        }
+
+        let end = ZipEnd {
+            signature: ZIP_END_SIGNATURE,
+            disk: 0,
+            directory_disk: 0,
+            disk_entries: entries.min(0xffff) as u16,
+            entries: entries.min(0xffff) as u16,
+            directory_size: directory_size.min(ZIP64_LIMIT) as u32,
+            directory_offset: directory_offset.min(ZIP64_LIMIT) as u32,
+            comment_len: comment.len() as u16,
+        };
+        self.out.write_all(as_bytes(&end))?;
+        self.out.write_all(comment.as_bytes())?;
+
+        Ok(self.out)
+    }
+}
+
+/// Range of a map: `length` bytes at `map_offset` are stored at
+/// `target_offset` of target `target`.
+#[derive(Debug, Copy, Clone)]
+pub struct MapRange {
+    pub map_offset:     u64,
+    pub length:         u64,
+    pub target_offset:  u64,
+    pub target:         u32,
+}
+
+/// Identity and hashes of a written AFF4 image.
+#[derive(Debug, Clone)]
+pub struct Aff4Summary {
+    pub volume_urn:     String,
+    pub map_urn:        String,
+    pub image_urn:      String,
+    /// Size of the image stream.
+    pub stream_size:    u64,
+    pub md5:            String,
+    pub sha1:           String,
+}
+
+/// Writer of an AFF4 (standard v1.0) physical memory image.
+///
+/// The bytes written make up a deflate compressed `aff4:ImageStream`, and
+/// the `aff4:Map` of the ranges given to `map_range` places them at their
+/// physical addresses. Everything is streamed except the current bevy.
+pub struct Aff4Writer {
+    zip:            ZipWriter<BufWriter<File>>,
+    volume_urn:     String,
+    map_urn:        String,
+    image_urn:      String,
+    ranges:         Vec<MapRange>,
+    /// Extra (predicate, object) statements of the map, in turtle syntax.
+    properties:     Vec<(String, String)>,
+    chunk:          Vec<u8>,
+    bevy:           Vec<u8>,
+    bevy_index:     Vec<u8>,
+    bevy_number:    u64,
+    stream_size:    u64,
+    md5:            md5::Context,
+    sha1:           sha1_smol::Sha1,
+}
+
+impl Aff4Writer {
+    /// Create the volume at `path`. `seed` tells the URNs of different
+    /// images apart.
+    pub fn create(path: &Path, seed: &[u8]) -> Result<Self> {
+        let volume_urn = new_urn(seed, b"volume");
+        let mut zip = ZipWriter::new(BufWriter::new(File::create(path)?));
+
+        zip.add("container.description", volume_urn.as_bytes())?;
+        zip.add("version.txt", format!("major=1\nminor=0\ntool=z2dmp {}\n",
+            env!("CARGO_PKG_VERSION")).as_bytes())?;
+
+        Ok(Aff4Writer {
+            zip,
+            map_urn: new_urn(seed, b"map"),
+            image_urn: new_urn(seed, b"image"),
+            volume_urn,
+            ranges: Vec::new(),
+            properties: Vec::new(),
+            chunk: Vec::with_capacity(AFF4_CHUNK_SIZE),
+            bevy: Vec::new(),
+            bevy_index: Vec::new(),
+            bevy_number: 0,
+            stream_size: 0,
+            md5: md5::Context::new(),
+            sha1: sha1_smol::Sha1::new(),
+        })
+    }
+
+    /// Map `length` bytes of the stream at `stream_offset` to `physical_address`.
+    pub fn map_range(&mut self, physical_address: u64, stream_offset: u64, length: u64) {
+        self.ranges.push(MapRange {
+            map_offset: physical_address,
+            length,
+            target_offset: stream_offset,
+            target: 0,
+        });
+    }
+
+    /// Add a statement about the map, e.g. `("aff4:memoryPageTableEntryOffset",
+    /// typed_literal("4096", "xsd:long"))`.
+    pub fn property(&mut self, predicate: &str, object: String) {
+        self.properties.push((predicate.to_string(), object));
+    }
+
+    fn flush_chunk(&mut self) -> io::Result<()> {
+        self.md5.consume(&self.chunk);
+        self.sha1.update(&self.chunk);
+
+        let mut encoder = DeflateEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(&self.chunk)?;
+        let compressed = encoder.finish()?;
+
+        // Chunks that do not compress are stored as is, which readers tell
+        // by their length.
+        let data = match compressed.len() >= AFF4_CHUNK_SIZE {
+            true if self.chunk.len() == AFF4_CHUNK_SIZE => &self.chunk,
+            _ => &compressed,
+        };
+
+        self.bevy_index.extend_from_slice(&(self.bevy.len() as u64).to_le_bytes());
+        self.bevy_index.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        self.bevy.extend_from_slice(data);
+        self.stream_size += self.chunk.len() as u64;
+        self.chunk.clear();
+
+        if self.bevy_index.len() == AFF4_CHUNKS_IN_SEGMENT * BEVY_INDEX_ENTRY_SIZE {
+            self.flush_bevy()?;
+        }
+
+        Ok(())
+    }
+
+    fn flush_bevy(&mut self) -> io::Result<()> {
+        if self.bevy_index.is_empty() {
+            return Ok(());
+        }
+
+        let name = format!("{}/{:08}", member_name(&self.image_urn), self.bevy_number);
+        self.zip.add(&name, &self.bevy)?;
+        self.zip.add(&format!("{}.index", name), &self.bevy_index)?;
+
+        self.bevy.clear();
+        self.bevy_index.clear();
+        self.bevy_number += 1;
+
+        Ok(())
+    }
+
+    /// Write the last bevy, the map and the information graph.
+    pub fn finish(mut self) -> Result<Aff4Summary> {
+        if !self.chunk.is_empty() {
+            self.flush_chunk()?;
+        }
+        self.flush_bevy()?;
+
+        let mut map = Vec::with_capacity(self.ranges.len() * MAP_ENTRY_SIZE);
+        for range in &self.ranges {
+            map.extend_from_slice(&range.map_offset.to_le_bytes());
+            map.extend_from_slice(&range.length.to_le_bytes());
+            map.extend_from_slice(&range.target_offset.to_le_bytes());
+            map.extend_from_slice(&range.target.to_le_bytes());
+        }
+        let map_name = member_name(&self.map_urn);
+        self.zip.add(&format!("{}/map", map_name), &map)?;
+        self.zip.add(&format!("{}/idx", map_name), format!("{}\n", self.image_urn).as_bytes())?;
+
+        let summary = Aff4Summary {
+            volume_urn: self.volume_urn.clone(),
+            map_urn: self.map_urn.clone(),
+            image_urn: self.image_urn.clone(),
+            stream_size: self.stream_size,
+            md5: format!("{:x}", self.md5.clone().compute()),
+            sha1: self.sha1.digest().to_string(),
+        };
+
+        let turtle = self.turtle(&summary);
+        self.zip.add("information.turtle", turtle.as_bytes())?;
+
+        let mut out = self.zip.finish(&summary.volume_urn)?;
+        out.flush()?;
+
+        Ok(summary)
+    }
+
+    fn turtle(&self, summary: &Aff4Summary) -> String {
+        let created = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs());
+        let map_size = self.ranges.iter().map(|r| r.map_offset + r.length).max().unwrap_or(0);
+
+        let mut map_properties = String::new();
+        for (predicate, object) in &self.properties {
+            map_properties.push_str(&format!(" ;\n    {} {}", predicate, object));
+        }
+
+        format!("@prefix rdf: <{rdf}> .\n\
+            @prefix xsd: <{xsd}> .\n\
+            @prefix aff4: <{aff4}> .\n\
+            @prefix zdmp: <{zdmp}> .\n\
+            \n\
+            <{volume}> a aff4:ZipVolume ;\n    \
+                aff4:contains <{map}>, <{image}> ;\n    \
+                aff4:creationTime {created} ;\n    \
+                aff4:interface aff4:Volume .\n\
+            \n\
+            <{image}> a aff4:ImageStream ;\n    \
+                aff4:chunkSize \"{chunk_size}\"^^xsd:int ;\n    \
+                aff4:chunksInSegment \"{chunks}\"^^xsd:int ;\n    \
+                aff4:compressionMethod <{deflate}> ;\n    \
+                aff4:hash \"{md5}\"^^aff4:MD5, \"{sha1}\"^^aff4:SHA1 ;\n    \
+                aff4:size \"{stream_size}\"^^xsd:long ;\n    \
+                aff4:stored <{volume}> .\n\
+            \n\
+            <{map}> a aff4:Image, aff4:Map ;\n    \
+                aff4:category aff4:memoryPhysical ;\n    \
+                aff4:dataStream <{image}> ;\n    \
+                aff4:mapGapDefaultStream aff4:Zero ;\n    \
+                aff4:size \"{map_size}\"^^xsd:long ;\n    \
+                aff4:stored <{volume}>{map_properties} .\n",
+            rdf = RDF_NS, xsd = XSD_NS, aff4 = AFF4_NS, zdmp = ZDMP_NS,
+            volume = summary.volume_urn, map = summary.map_urn, image = summary.image_urn,
+            created = datetime_literal(created),
+            chunk_size = AFF4_CHUNK_SIZE, chunks = AFF4_CHUNKS_IN_SEGMENT,
+            deflate = AFF4_DEFLATE, md5 = summary.md5, sha1 = summary.sha1,
+            stream_size = summary.stream_size, map_size = map_size,
+            map_properties = map_properties)
+    }
+}
+
+impl Write for Aff4Writer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let len = (AFF4_CHUNK_SIZE - self.chunk.len()).min(buf.len());
+        self.chunk.extend_from_slice(&buf[..len]);
+
+        if self.chunk.len() == AFF4_CHUNK_SIZE {
+            self.flush_chunk()?;
+        }
+
+        Ok(len)
+    }
+
+    /// Chunks are only written once full, or by `finish`.
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Turtle string literal.
+pub fn literal(value: &str) -> String {
+    let mut out = String::from("\"");
+    for c in value.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// Turtle literal of type `datatype`, e.g. `"4096"^^xsd:long`.
+pub fn typed_literal(value: &str, datatype: &str) -> String {
+    format!("{}^^{}", literal(value), datatype)
+}
+
+/// `xsd:dateTime` literal of seconds since the Unix epoch.
+pub fn datetime_literal(secs: u64) -> String {
+    let (year, month, day, hour, minute, second) = unix_time_parts(secs);
+    let time = format!("{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z", year, month, day, hour, minute, second);
+    typed_literal(&time, "xsd:dateTime")
+}
+
+/// URN `aff4://<uuid>` of a new object, from the md5 of `seed`, `kind` and
+/// the current time, with the bits of a random (version 4) UUID.
+fn new_urn(seed: &[u8], kind: &[u8]) -> String {
+    let nanos = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_nanos());
+
+    let mut ctx = md5::Context::new();
+    ctx.consume(seed);
+    ctx.consume(kind);
+    ctx.consume(nanos.to_le_bytes());
+    let mut b = ctx.compute().0;
+    b[6] = (b[6] & 0x0f) | 0x40;
+    b[8] = (b[8] & 0x3f) | 0x80;
+
+    let hex: Vec<String> = b.iter().map(|b| format!("{:02x}", b)).collect();
+    format!("aff4://{}-{}-{}-{}-{}", hex[..4].concat(), hex[4..6].concat(),
+        hex[6..8].concat(), hex[8..10].concat(), hex[10..].concat())
+}
+
+/// Zip member name of the segments of `urn`.
+fn member_name(urn: &str) -> String {
+    urn.replacen("aff4://", "aff4%3A%2F%2F", 1)
+}
+
+/// A statement of the information graph, with expanded IRIs.
+#[derive(Debug, Clone)]
+pub struct Triple {
+    pub subject:    String,
+    pub predicate:  String,
+    /// IRI, or value of a literal.
+    pub object:     String,
+    /// Datatype IRI of a typed literal.
+    pub datatype:   Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Iri(String),
+    Literal(String),
+    /// Prefixed name, keyword or bare number.
+    Name(String),
+    Punct(char),
+}
+
+fn tokenize(text: &str) -> Result<Vec<Token>> {
+    let err = || Error::ContainerError("Invalid turtle".to_string());
+    let chars: Vec<char> = text.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+
+    while i < chars.len() {
+        match chars[i] {
+            c if c.is_whitespace() => i += 1,
+            '#' => while i < chars.len() && chars[i] != '\n' {
+                i += 1;
+            },
+            '<' => {
+                let len = chars[i + 1..].iter().position(|&c| c == '>').ok_or_else(err)?;
+                tokens.push(Token::Iri(chars[i + 1..i + 1 + len].iter().collect()));
+                i += len + 2;
+            },
+            '"' => {
+                let mut value = String::new();
+                i += 1;
+                loop {
+                    match chars.get(i) {
+                        Some('"') => break,
+                        Some('\\') => {
+                            value.push(match chars.get(i + 1).ok_or_else(err)? {
+                                'n' => '\n',
+                                'r' => '\r',
+                                't' => '\t',
+                                &c => c,
+                            });
+                            i += 2;
+                        },
+                        Some(&c) => {
+                            value.push(c);
+                            i += 1;
+                        },
+                        None => return Err(err()),
+                    }
+                }
+                tokens.push(Token::Literal(value));
+                i += 1;
+            },
+            '^' if chars.get(i + 1) == Some(&'^') => {
+                tokens.push(Token::Punct('^'));
+                i += 2;
+            },
+            c @ (';' | ',' | '.' | '[' | ']' | '(' | ')') => {
+                tokens.push(Token::Punct(c));
+                i += 1;
+            },
+            _ => {
+                let len = chars[i..].iter()
+                    .position(|&c| c.is_whitespace() || ";,<\"^[]()".contains(c))
+                    .unwrap_or(chars.len() - i);
+                let word: String = chars[i..i + len].iter().collect();
+
+                // Names cannot end with a dot, it ends the statement.
+                let name = word.trim_end_matches('.');
+                tokens.push(Token::Name(name.to_string()));
+                for _ in name.len()..word.len() {
+                    tokens.push(Token::Punct('.'));
+                }
+                i += len;
+            },
+        }
+    }
+
+    Ok(tokens)
+}
+
+/// Parse the subset of turtle used by AFF4 volumes: prefixes, IRIs, prefixed
+/// names, literals and `;`/`,` lists. Blank nodes and collections are not
+/// supported.
+pub fn parse_turtle(text: &str) -> Result<Vec<Triple>> {
+    let err = |msg: &str| Error::ContainerError(format!("Invalid turtle: {}", msg));
+    let mut tokens = tokenize(text)?.into_iter().peekable();
+    let mut prefixes: HashMap<String, String> = HashMap::new();
+    let mut triples = Vec::new();
+
+    let expand = |prefixes: &HashMap<String, String>, token: Token| -> Result<String> {
+        match token {
+            Token::Iri(iri) => Ok(iri),
+            Token::Name(name) if name == "a" => Ok(format!("{}type", RDF_NS)),
+            Token::Name(name) => {
+                let (prefix, local) = name.split_once(':')
+                    .ok_or_else(|| err(&format!("unexpected `{}`", name)))?;
+                let ns = prefixes.get(prefix)
+                    .ok_or_else(|| err(&format!("unknown prefix `{}`", prefix)))?;
+                Ok(format!("{}{}", ns, local))
+            },
+            token => Err(err(&format!("unexpected {:?}", token))),
+        }
+    };
+
+    while let Some(token) = tokens.next() {
+        match &token {
+            Token::Name(keyword) if keyword == "@prefix" || keyword.eq_ignore_ascii_case("prefix") => {
+                let (prefix, ns) = match (tokens.next(), tokens.next()) {
+                    (Some(Token::Name(prefix)), Some(Token::Iri(ns))) => (prefix, ns),
+                    _ => return Err(err("bad prefix")),
+                };
+                prefixes.insert(prefix.trim_end_matches(':').to_string(), ns);
+                if tokens.peek() == Some(&Token::Punct('.')) {
+                    tokens.next();
+                }
+                continue;
+            },
+            Token::Name(keyword) if keyword == "@base" => {
+                return Err(err("@base is not supported"));
+            },
+            _ => (),
+        }
+
+        let subject = expand(&prefixes, token)?;
+
+        'predicates: loop {
+            let predicate = expand(&prefixes, tokens.next().ok_or_else(|| err("missing predicate"))?)?;
+
+            loop {
+                let (object, datatype) = match tokens.next() {
+                    Some(Token::Literal(value)) => {
+                        let datatype = match tokens.peek() {
+                            Some(Token::Punct('^')) => {
+                                tokens.next();
+                                let token = tokens.next().ok_or_else(|| err("missing datatype"))?;
+                                Some(expand(&prefixes, token)?)
+                            },
+                            // Language tags are dropped.
+                            Some(Token::Name(tag)) if tag.starts_with('@') => {
+                                tokens.next();
+                                None
+                            },
+                            _ => None,
+                        };
+                        (value, datatype)
+                    },
+                    // Numbers and booleans.
+                    Some(Token::Name(name)) if !name.contains(':') && name != "a" => (name, None),
+                    Some(token) => (expand(&prefixes, token)?, None),
+                    None => return Err(err("missing object")),
+                };
+
+                triples.push(Triple {
+                    subject: subject.clone(),
+                    predicate: predicate.clone(),
+                    object,
+                    datatype,
+                });
+
+                match tokens.next() {
+                    Some(Token::Punct(',')) => continue,
+                    Some(Token::Punct(';')) => {
+                        while tokens.peek() == Some(&Token::Punct(';')) {
+                            tokens.next();
+                        }
+                        if tokens.peek() == Some(&Token::Punct('.')) {
+                            tokens.next();
+                            break 'predicates;
+                        }
+                        continue 'predicates;
+                    },
+                    Some(Token::Punct('.')) => break 'predicates,
+                    _ => return Err(err("unterminated statement")),
+                }
+            }
+        }
+    }
+
+    Ok(triples)
+}
+
+/// Location of a zip member.
+#[derive(Debug, Copy, Clone)]
+struct ZipEntry {
+    header_offset:  u64,
+    size:           u64,
+    compressed_size: u64,
+    compression:    u16,
+}
+
+/// An AFF4 zip volume and its information graph.
+pub struct Aff4Volume {
+    file:           File,
+    pub urn:        String,
+    entries:        HashMap<String, ZipEntry>,
+    pub triples:    Vec<Triple>,
+}
+
+impl Aff4Volume {
+    pub fn open(path: &Path) -> Result<Self> {
+        let mut file = File::open(path)?;
+        let entries = read_zip_directory(&mut file)?;
+
+        let mut volume = Aff4Volume { file, urn: String::new(), entries, triples: Vec::new() };
+
+        let description = volume.read_member("container.description")?;
+        volume.urn = String::from_utf8_lossy(&description).trim().to_string();
+
+        let turtle = volume.read_member("information.turtle")?;
+        volume.triples = parse_turtle(&String::from_utf8_lossy(&turtle))?;
+
+        Ok(volume)
+    }
+
+    pub fn has_member(&self, name: &str) -> bool {
+        self.entries.contains_key(name)
+    }
+
+    fn entry(&self, name: &str) -> Result<ZipEntry> {
+        self.entries.get(name).copied()
+            .ok_or_else(|| Error::ContainerError(format!("Missing AFF4 segment {}", name)))
+    }
+
+    /// Offset of the data of a member.
+    fn data_offset(&mut self, entry: &ZipEntry) -> Result<u64> {
+        self.file.seek(SeekFrom::Start(entry.header_offset))?;
+        let hdr = read_type!(&mut self.file, ZipLocalHeader)?;
+        if hdr.signature != ZIP_LOCAL_HEADER_SIGNATURE {
+            return Err(Error::ContainerError("Invalid zip local header".to_string()));
+        }
+
+        Ok(entry.header_offset + std::mem::size_of::<ZipLocalHeader>() as u64
+            + hdr.name_len as u64 + hdr.extra_len as u64)
+    }
+
+    /// Content of a stored or deflated member.
+    pub fn read_member(&mut self, name: &str) -> Result<Vec<u8>> {
+        let entry = self.entry(name)?;
+        let offset = self.data_offset(&entry)?;
+
+        let mut data = vec![0u8; entry.compressed_size as usize];
+        self.file.seek(SeekFrom::Start(offset))?;
+        self.file.read_exact(&mut data)?;
+
+        match entry.compression {
+            ZIP_STORED => Ok(data),
+            ZIP_DEFLATED => {
+                let mut out = Vec::with_capacity(entry.size as usize);
+                DeflateDecoder::new(data.as_slice()).read_to_end(&mut out)?;
+                Ok(out)
+            },
+            method => Err(Error::ContainerError(
+                format!("Unsupported zip compression {} of {}", method, name))),
+        }
+    }
+
+    /// `len` bytes at `offset` of a stored member.
+    fn read_member_range(&mut self, name: &str, offset: u64, len: usize) -> Result<Vec<u8>> {
+        let entry = self.entry(name)?;
+        if entry.compression != ZIP_STORED || offset + len as u64 > entry.size {
+            return Err(Error::ContainerError(format!("Bad read of AFF4 segment {}", name)));
+        }
+
+        let start = self.data_offset(&entry)? + offset;
+        let mut data = vec![0u8; len];
+        self.file.seek(SeekFrom::Start(start))?;
+        self.file.read_exact(&mut data)?;
+
+        Ok(data)
+    }
+
+    /// Statements about `subject` with `predicate`.
+    pub fn objects<'a>(&'a self, subject: &'a str, predicate: &'a str) -> impl Iterator<Item = &'a Triple> {
+        self.triples.iter().filter(move |t| t.subject == subject && t.predicate == predicate)
+    }
+
+    pub fn value(&self, subject: &str, predicate: &str) -> Option<&str> {
+        self.triples.iter()
+            .find(|t| t.subject == subject && t.predicate == predicate)
+            .map(|t| t.object.as_str())
+    }
+
+    /// Subjects of type `rdf_type`.
+    pub fn subjects_of_type(&self, rdf_type: &str) -> Vec<String> {
+        let type_iri = format!("{}type", RDF_NS);
+        self.triples.iter()
+            .filter(|t| t.predicate == type_iri && t.object == rdf_type)
+            .map(|t| t.subject.clone())
+            .collect()
+    }
+}
+
+/// Parse the zip central directory.
+fn read_zip_directory<R: Read + Seek>(file: &mut R) -> Result<HashMap<String, ZipEntry>> {
+    let err = || Error::ContainerError("Invalid zip volume".to_string());
+    let end_size = std::mem::size_of::<ZipEnd>() as u64;
+    let file_len = file.seek(SeekFrom::End(0))?;
+
+    // The end record is followed by a comment of up to 64 KB.
+    let tail_len = file_len.min(end_size + 0xffff);
+    let mut tail = vec![0u8; tail_len as usize];
+    file.seek(SeekFrom::Start(file_len - tail_len))?;
+    file.read_exact(&mut tail)?;
+
+    let end_pos = (0..tail.len().saturating_sub(end_size as usize - 1)).rev()
+        .find(|&i| tail[i..i + 4] == ZIP_END_SIGNATURE.to_le_bytes())
+        .ok_or_else(err)?;
+    let end = read_type!(&mut &tail[end_pos..], ZipEnd)?;
+
+    let mut entries = end.entries as u64;
+    let mut directory_offset = end.directory_offset as u64;
+    let mut directory_size = end.directory_size as u64;
+
+    if entries == 0xffff || directory_offset == ZIP64_LIMIT || directory_size == ZIP64_LIMIT {
+        let locator_size = std::mem::size_of::<Zip64Locator>();
+        let locator_pos = end_pos.checked_sub(locator_size).ok_or_else(err)?;
+        let locator = read_type!(&mut &tail[locator_pos..], Zip64Locator)?;
+        if locator.signature != ZIP64_LOCATOR_SIGNATURE {
+            return Err(err());
+        }
+
+        file.seek(SeekFrom::Start(locator.end_offset))?;
+        let end64 = read_type!(file, Zip64End)?;
+        if end64.signature != ZIP64_END_SIGNATURE {
+            return Err(err());
+        }
+        entries = end64.entries;
+        directory_offset = end64.directory_offset;
+        directory_size = end64.directory_size;
+    }
+
+    let mut directory = vec![0u8; directory_size as usize];
+    file.seek(SeekFrom::Start(directory_offset))?;
+    file.read_exact(&mut directory)?;
+
+    let mut members = HashMap::new();
+    let mut rdr = directory.as_slice();
+    for _ in 0..entries {
+        let hdr = read_type!(&mut rdr, ZipCentralHeader)?;
+        if hdr.signature != ZIP_CENTRAL_HEADER_SIGNATURE {
+            return Err(err());
+        }
+
+        let name_len = hdr.name_len as usize;
+        let extra_len = hdr.extra_len as usize;
+        let tail_len = name_len + extra_len + hdr.comment_len as usize;
+        if rdr.len() < tail_len {
+            return Err(err());
+        }
+        let name = String::from_utf8_lossy(&rdr[..name_len]).to_string();
+        let mut extra = &rdr[name_len..name_len + extra_len];
+        rdr = &rdr[tail_len..];
+
+        let mut size = hdr.uncompressed_size as u64;
+        let mut compressed_size = hdr.compressed_size as u64;
+        let mut header_offset = hdr.local_header_offset as u64;
+
+        // Zip64 values are only present for the fields that overflowed.
+        while extra.len() >= 4 {
+            let id = u16::from_le_bytes([extra[0], extra[1]]);
+            let len = (u16::from_le_bytes([extra[2], extra[3]]) as usize).min(extra.len() - 4);
+            if id == ZIP64_EXTRA_FIELD {
+                let mut values = extra[4..4 + len].chunks_exact(8)
+                    .map(|v| u64::from_le_bytes([v[0], v[1], v[2], v[3], v[4], v[5], v[6], v[7]]));
+                for field in [&mut size, &mut compressed_size, &mut header_offset] {
+                    if *field == ZIP64_LIMIT {
+                        *field = values.next().ok_or_else(err)?;
+                    }
+                }
+            }
+            extra = &extra[4 + len..];
+        }
+
+        members.insert(name, ZipEntry { header_offset, size, compressed_size, compression: hdr.compression });
+    }
+
+    Ok(members)
+}
+
+/// Compression of the chunks of an image stream.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum ChunkCompression {
+    Stored,
+    Deflate,
+    Zlib,
+}
+
+/// AFF4 physical memory image: an `aff4:Map` over an `aff4:ImageStream` of
+/// a volume, or the image stream alone when there is no map.
+pub struct Aff4Image {
+    volume:             Aff4Volume,
+    pub map_urn:        Option<String>,
+    pub image_urn:      String,
+    pub stream_size:    u64,
+    pub chunk_size:     usize,
+    pub chunks_in_segment: usize,
+    compression:        ChunkCompression,
+    /// Ranges stored in the image stream, sorted by address.
+    pub ranges:         Vec<MapRange>,
+    bevy_indexes:       HashMap<u64, Vec<u8>>,
+    /// Last decoded chunk.
+    chunk:              Option<(u64, Vec<u8>)>,
+}
+
+impl Aff4Image {
+    pub fn open(path: &Path) -> Result<Self> {
+        let volume = Aff4Volume::open(path)?;
+        let aff4 = |name: &str| format!("{}{}", AFF4_NS, name);
+        let err = |msg: &str| Error::ContainerError(msg.to_string());
+
+        let map_urn = volume.subjects_of_type(&aff4("Map")).into_iter().next();
+        let image_urn = match &map_urn {
+            Some(map) => volume.value(map, &aff4("dataStream"))
+                .ok_or_else(|| err("AFF4 map without a data stream"))?.to_string(),
+            None => volume.subjects_of_type(&aff4("ImageStream")).into_iter().next()
+                .ok_or_else(|| err("AFF4 volume without an image stream"))?,
+        };
+
+        let number = |predicate: &str| -> Result<u64> {
+            volume.value(&image_urn, &aff4(predicate))
+                .ok_or_else(|| err(&format!("AFF4 image stream without aff4:{}", predicate)))?
+                .parse::<u64>()
+                .map_err(Error::from)
+        };
+        let stream_size = number("size")?;
+        let chunk_size = number("chunkSize")? as usize;
+        let chunks_in_segment = number("chunksInSegment")? as usize;
+        if chunk_size == 0 || chunks_in_segment == 0 {
+            return Err(err("Invalid AFF4 chunk size"));
+        }
+
+        let compression = match volume.value(&image_urn, &aff4("compressionMethod")) {
+            Some(AFF4_DEFLATE) => ChunkCompression::Deflate,
+            Some(AFF4_ZLIB) => ChunkCompression::Zlib,
+            Some(method) if method == aff4("NullCompressor") => ChunkCompression::Stored,
+            None => ChunkCompression::Stored,
+            Some(method) => return Err(Error::ContainerError(
+                format!("Unsupported AFF4 compression {}", method))),
+        };
+
+        let mut image = Aff4Image {
+            volume,
+            map_urn,
+            image_urn,
+            stream_size,
+            chunk_size,
+            chunks_in_segment,
+            compression,
+            ranges: Vec::new(),
+            bevy_indexes: HashMap::new(),
+            chunk: None,
+        };
+
+        image.ranges = match image.map_urn.clone() {
+            Some(map) => image.read_map(&map)?,
+            None => vec![MapRange { map_offset: 0, length: stream_size, target_offset: 0, target: 0 }],
+        };
+
+        Ok(image)
+    }
+
+    /// Ranges of the map that point into the image stream. Other targets,
+    /// like `aff4:Zero`, read as gaps.
+    fn read_map(&mut self, map_urn: &str) -> Result<Vec<MapRange>> {
+        let name = member_name(map_urn);
+        let idx = self.volume.read_member(&format!("{}/idx", name))?;
+        let targets: Vec<String> = String::from_utf8_lossy(&idx).lines()
+            .map(|line| line.trim().to_string())
+            .collect();
+
+        let map = self.volume.read_member(&format!("{}/map", name))?;
+        let u64_at = |b: &[u8]| u64::from_le_bytes([b[0], b[1], b[2], b[3], b[4], b[5], b[6], b[7]]);
+
+        let mut ranges: Vec<MapRange> = map.chunks_exact(MAP_ENTRY_SIZE)
+            .map(|e| MapRange {
+                map_offset: u64_at(&e[0..]),
+                length: u64_at(&e[8..]),
+                target_offset: u64_at(&e[16..]),
+                target: u32::from_le_bytes([e[24], e[25], e[26], e[27]]),
+            })
+            .filter(|range| targets.get(range.target as usize) == Some(&self.image_urn))
+            .collect();
+        ranges.sort_by_key(|range| range.map_offset);
+
+        Ok(ranges)
+    }
+
+    /// Stored hashes of the image stream, as (algorithm, hex digest).
+    pub fn hashes(&self) -> Vec<(String, String)> {
+        self.volume.objects(&self.image_urn, &format!("{}hash", AFF4_NS))
+            .filter_map(|t| {
+                let algorithm = t.datatype.as_ref()?.strip_prefix(AFF4_NS)?;
+                Some((algorithm.to_string(), t.object.to_lowercase()))
+            })
+            .collect()
+    }
+
+    fn bevy_name(&self, bevy: u64) -> String {
+        format!("{}/{:08}", member_name(&self.image_urn), bevy)
+    }
+
+    /// Decoded chunk `n` of the image stream.
+    fn read_chunk(&mut self, n: u64) -> Result<&[u8]> {
+        if self.chunk.as_ref().map(|(i, _)| *i) != Some(n) {
+            let bevy = n / self.chunks_in_segment as u64;
+            let name = self.bevy_name(bevy);
+
+            if !self.bevy_indexes.contains_key(&bevy) {
+                let index = self.volume.read_member(&format!("{}.index", name))?;
+                self.bevy_indexes.insert(bevy, index);
+            }
+
+            let entry = (n % self.chunks_in_segment as u64) as usize * BEVY_INDEX_ENTRY_SIZE;
+            let e = self.bevy_indexes[&bevy].get(entry..entry + BEVY_INDEX_ENTRY_SIZE)
+                .ok_or_else(|| Error::ContainerError(format!("Missing AFF4 chunk {}", n)))?;
+            let offset = u64::from_le_bytes([e[0], e[1], e[2], e[3], e[4], e[5], e[6], e[7]]);
+            let len = u32::from_le_bytes([e[8], e[9], e[10], e[11]]) as usize;
+
+            let data = self.volume.read_member_range(&name, offset, len)?;
+            let data = match self.compression {
+                ChunkCompression::Stored => data,
+                _ if len == self.chunk_size => data,
+                ChunkCompression::Deflate => {
+                    let mut out = Vec::with_capacity(self.chunk_size);
+                    DeflateDecoder::new(data.as_slice()).read_to_end(&mut out)?;
+                    out
+                },
+                ChunkCompression::Zlib => {
+                    let mut out = Vec::with_capacity(self.chunk_size);
+                    ZlibDecoder::new(data.as_slice()).read_to_end(&mut out)?;
+                    out
+                },
+            };
+
+            self.chunk = Some((n, data));
+        }
+
+        Ok(self.chunk.as_ref().map(|(_, data)| data.as_slice()).unwrap_or(&[]))
+    }
+
+    /// Fill `buf` from the image stream at `offset`.
+    pub fn read_stream(&mut self, offset: u64, buf: &mut [u8]) -> Result<()> {
+        if offset.checked_add(buf.len() as u64).is_none_or(|end| end > self.stream_size) {
+            return Err(Error::ContainerError(
+                format!("Read @ 0x{:x} past the end of the AFF4 image stream", offset)));
+        }
+
+        let chunk_size = self.chunk_size as u64;
+        let mut done = 0;
+        while done < buf.len() {
+            let pos = offset + done as u64;
+            let chunk = self.read_chunk(pos / chunk_size)?;
+            let start = (pos % chunk_size) as usize;
+            let len = chunk.len().saturating_sub(start).min(buf.len() - done);
+            if len == 0 {
+                return Err(Error::ContainerError(format!("Short AFF4 chunk @ 0x{:x}", pos)));
+            }
+
+            buf[done..done + len].copy_from_slice(&chunk[start..start + len]);
+            done += len;
+        }
+
+        Ok(())
+    }
+
+    /// Hash the image stream and compare with its stored MD5 and SHA1.
+    /// Returns the checked algorithms and whether they match, or an error
+    /// when neither hash is stored.
+    pub fn verify(&mut self) -> Result<Vec<(String, bool)>> {
+        let hashes: Vec<(String, String)> = self.hashes().into_iter()
+            .filter(|(algorithm, _)| algorithm == "MD5" || algorithm == "SHA1")
+            .collect();
+        if hashes.is_empty() {
+            return Err(Error::ContainerError("AFF4 image stream without an MD5 or SHA1 hash".to_string()));
+        }
+
+        let mut md5 = md5::Context::new();
+        let mut sha1 = sha1_smol::Sha1::new();
+        let mut buf = vec![0u8; self.chunk_size];
+        let mut pos = 0;
+
+        while pos < self.stream_size {
+            let len = (self.stream_size - pos).min(self.chunk_size as u64) as usize;
+            self.read_stream(pos, &mut buf[..len])?;
+            md5.consume(&buf[..len]);
+            sha1.update(&buf[..len]);
+            pos += len as u64;
+        }
+
+        let md5 = format!("{:x}", md5.compute());
+        let sha1 = sha1.digest().to_string();
+
+        Ok(hashes.into_iter()
+            .map(|(algorithm, digest)| {
+                let ok = match algorithm.as_str() {
+                    "MD5" => digest == md5,
+                    _ => digest == sha1,
+                };
+                (algorithm, ok)
+            })
+            .collect())
+    }
+}
+
+impl PhysicalMemory for Aff4Image {
+    fn read_phys(&mut self, addr: u64, buf: &mut [u8]) -> Result<()> {
+        let mut done = 0;
+
+        while done < buf.len() {
+            let pos = addr + done as u64;
+            let index = self.ranges.partition_point(|r| r.map_offset + r.length <= pos);
+            let range = match self.ranges.get(index) {
+                Some(range) if range.map_offset <= pos => *range,
+                _ => return Err(Error::PhysicalAddressNotPresent(pos)),
+            };
+
+            let len = (range.map_offset + range.length - pos).min((buf.len() - done) as u64) as usize;
+            let offset = range.target_offset + pos - range.map_offset;
+            self.read_stream(offset, &mut buf[done..done + len])?;
+            done += len;
+        }
+
+        Ok(())
+    }
+
+    fn ranges(&self) -> Vec<Range<u64>> {
+        self.ranges.iter().map(|r| r.map_offset..r.map_offset + r.length).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::PathBuf;
+
+    use super::*;
+
+    /// Deterministic xorshift bytes.
+    fn noise(seed: u64, len: usize) -> Vec<u8> {
+        let mut x = 2 * seed + 1;
+        (0..len).map(|_| {
+            x ^= x << 13;
+            x ^= x >> 7;
+            x ^= x << 17;
+            x as u8
+        }).collect()
+    }
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("z2dmp-{}-{}", std::process::id(), name))
+    }
+
+    /// Write `runs` of (physical address, bytes) back to back in the stream,
+    /// in pieces that do not line up with the chunks.
+    fn write_volume(path: &Path, runs: &[(u64, Vec<u8>)]) -> Aff4Summary {
+        let mut writer = Aff4Writer::create(path, b"test").unwrap();
+
+        let mut stream_offset = 0;
+        for (address, data) in runs {
+            writer.map_range(*address, stream_offset, data.len() as u64);
+            for piece in data.chunks(0x1234) {
+                writer.write_all(piece).unwrap();
+            }
+            stream_offset += data.len() as u64;
+        }
+        writer.property("aff4:memoryPageTableEntryOffset", typed_literal("4096", "xsd:long"));
+
+        writer.finish().unwrap()
+    }
+
+    /// Read back the map and stream of the volume at `path` and compare them
+    /// with `runs`.
+    fn check_volume(path: &Path, runs: &[(u64, Vec<u8>)], summary: &Aff4Summary) {
+        let mut image = Aff4Image::open(path).unwrap();
+        let stream: Vec<u8> = runs.iter().flat_map(|(_, data)| data.iter().copied()).collect();
+
+        assert_eq!(image.map_urn.as_deref(), Some(summary.map_urn.as_str()));
+        assert_eq!(image.image_urn, summary.image_urn);
+        assert_eq!(image.stream_size, stream.len() as u64);
+
+        let mut stream_offset = 0;
+        assert_eq!(image.ranges.len(), runs.len());
+        for (range, (address, data)) in image.ranges.clone().iter().zip(runs) {
+            assert_eq!((range.map_offset, range.length, range.target_offset),
+                (*address, data.len() as u64, stream_offset));
+            stream_offset += data.len() as u64;
+
+            let mut buf = vec![0u8; data.len()];
+            image.read_phys(*address, &mut buf).unwrap();
+            assert!(buf == *data);
+        }
+
+        let mut buf = vec![0u8; stream.len()];
+        image.read_stream(0, &mut buf).unwrap();
+        assert!(buf == stream);
+        assert!(image.read_stream(stream.len() as u64, &mut [0u8; 1]).is_err());
+
+        let mut hashes = image.hashes();
+        hashes.sort();
+        assert_eq!(hashes, [("MD5".to_string(), summary.md5.clone()), ("SHA1".to_string(), summary.sha1.clone())]);
+        assert_eq!(image.verify().unwrap(), [("MD5".to_string(), true), ("SHA1".to_string(), true)]);
+    }
+
+    #[test]
+    fn round_trip_multi_run_map() {
+        let text = b"\x00\x00\x00\x00MmSt nt!PspCidTable ".repeat(0x5000);
+        let mut low = noise(1, 0x30000);
+        low.extend_from_slice(&text[..0x6f000]);
+        let runs = vec![
+            (0x1000, low),
+            (0x10_0000, noise(2, 0x20123)),
+            (0x7fff_0000, vec![0u8; 0x8000]),
+        ];
+
+        let path = temp_path("multi-run.aff4");
+        let summary = write_volume(&path, &runs);
+        check_volume(&path, &runs, &summary);
+
+        let mut image = Aff4Image::open(&path).unwrap();
+        assert!(matches!(image.read_phys(0, &mut [0u8; 1]), Err(Error::PhysicalAddressNotPresent(0))));
+        assert!(image.read_phys(0xa0000 - 1, &mut [0u8; 2]).is_err());
+
+        // A read across two chunks of the stream.
+        let mut buf = [0u8; 2];
+        image.read_phys(0x1000 + AFF4_CHUNK_SIZE as u64 - 1, &mut buf).unwrap();
+        assert!(buf[..] == runs[0].1[AFF4_CHUNK_SIZE - 1..AFF4_CHUNK_SIZE + 1]);
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn round_trip_several_bevies() {
+        let runs = vec![
+            (0, noise(3, 0x1234)),
+            (0x1_0000_0000, vec![0u8; AFF4_CHUNK_SIZE * AFF4_CHUNKS_IN_SEGMENT + 0x8765]),
+        ];
+
+        let path = temp_path("bevies.aff4");
+        let summary = write_volume(&path, &runs);
+        check_volume(&path, &runs, &summary);
+
+        let volume = Aff4Volume::open(&path).unwrap();
+        assert!(volume.has_member(&format!("{}/00000001.index", member_name(&summary.image_urn))));
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    /// Zip file whose bytes from `start` are in `inner`, and zeroes before.
+    struct ZipTail {
+        inner:  Vec<u8>,
+        start:  u64,
+        pos:    u64,
+    }
+
+    impl Read for ZipTail {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let len = match self.pos.checked_sub(self.start) {
+                Some(offset) => (&self.inner[(offset as usize).min(self.inner.len())..]).read(buf)?,
+                None => {
+                    let len = ((self.start - self.pos) as usize).min(buf.len());
+                    buf[..len].fill(0);
+                    len
+                },
+            };
+            self.pos += len as u64;
+            Ok(len)
+        }
+    }
+
+    impl Seek for ZipTail {
+        fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+            self.pos = match pos {
+                SeekFrom::Start(n) => n,
+                SeekFrom::End(n) => (self.start + self.inner.len() as u64).wrapping_add(n as u64),
+                SeekFrom::Current(n) => self.pos.wrapping_add(n as u64),
+            };
+            Ok(self.pos)
+        }
+    }
+
+    #[test]
+    fn zip64_offsets() {
+        // Members and central directory past 4 GB.
+        let start = 5 << 30;
+        let mut zip = ZipWriter::at(io::Cursor::new(Vec::new()), start);
+        zip.add("a", &noise(5, 0x100)).unwrap();
+        zip.add("b", &noise(6, 0x2345)).unwrap();
+        let inner = zip.finish("aff4://volume").unwrap().into_inner();
+
+        let mut file = ZipTail { inner, start, pos: 0 };
+        let entries = read_zip_directory(&mut file).unwrap();
+        assert_eq!(entries.len(), 2);
+
+        let local_header = std::mem::size_of::<ZipLocalHeader>() as u64;
+        for (name, offset, data) in [("a", start, noise(5, 0x100)), ("b", start + local_header + 1 + 0x100, noise(6, 0x2345))] {
+            let entry = entries[name];
+            assert_eq!((entry.header_offset, entry.size, entry.compressed_size), (offset, data.len() as u64, data.len() as u64));
+
+            let mut buf = vec![0u8; data.len()];
+            file.seek(SeekFrom::Start(offset + local_header + 1)).unwrap();
+            file.read_exact(&mut buf).unwrap();
+            assert!(buf == data);
+        }
+    }
+
+    #[test]
+    fn zip64_entry_count() {
+        let mut zip = ZipWriter::new(io::Cursor::new(Vec::new()));
+        for i in 0..0x10010u32 {
+            zip.add(&format!("{:05x}", i), &i.to_le_bytes()).unwrap();
+        }
+        let mut file = zip.finish("").unwrap();
+
+        let entries = read_zip_directory(&mut file).unwrap();
+        assert_eq!(entries.len(), 0x10010);
+        assert_eq!(entries["1000f"].header_offset, 0x1000f * (std::mem::size_of::<ZipLocalHeader>() as u64 + 5 + 4));
+    }
+
+    #[test]
+    fn verify_needs_hashes() {
+        let urn = "aff4://00000000-0000-4000-8000-000000000000";
+        let turtle = format!("@prefix aff4: <{}> .\n@prefix xsd: <{}> .\n\n\
+            <{}> a aff4:ImageStream ;\n    \
+                aff4:chunkSize \"16\"^^xsd:int ;\n    \
+                aff4:chunksInSegment \"4\"^^xsd:int ;\n    \
+                aff4:size \"24\"^^xsd:long .\n", AFF4_NS, XSD_NS, urn);
+        let mut index = Vec::new();
+        for (offset, len) in [(0u64, 16u32), (16, 8)] {
+            index.extend_from_slice(&offset.to_le_bytes());
+            index.extend_from_slice(&len.to_le_bytes());
+        }
+
+        let path = temp_path("no-hash.aff4");
+        let mut zip = ZipWriter::new(File::create(&path).unwrap());
+        zip.add("container.description", b"aff4://volume").unwrap();
+        zip.add("information.turtle", turtle.as_bytes()).unwrap();
+        zip.add(&format!("{}/00000000", member_name(urn)), &noise(4, 24)).unwrap();
+        zip.add(&format!("{}/00000000.index", member_name(urn)), &index).unwrap();
+        zip.finish("aff4://volume").unwrap();
+
+        let mut image = Aff4Image::open(&path).unwrap();
+        let mut buf = [0u8; 24];
+        image.read_stream(0, &mut buf).unwrap();
+        assert!(buf[..] == noise(4, 24)[..]);
+        assert!(image.hashes().is_empty());
+        assert!(image.verify().is_err());
+
+        fs::remove_file(&path).unwrap();
+    }
+}
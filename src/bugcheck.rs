@@ -0,0 +1,300 @@
+use std::fmt;
+
+use crate::crashdump::{CrashDump, IMAGE_FILE_MACHINE_I386, IMAGE_FILE_MACHINE_AMD64, IMAGE_FILE_MACHINE_ARM64};
+use crate::json::{self, JsonObject};
+use crate::kdbg::Kdbg;
+use crate::paging::AddressSpace;
+use crate::physmem::PhysicalMemory;
+use crate::result::{Result, Error};
+
+/// Bugcheck code of the dumps written by DumpIt (MANUALLY_INITIATED_CRASH1).
+pub const BUGCHECK_DUMPIT:              u32 = 0xdead_dead;
+/// Bugcheck code of Windows live kernel dumps.
+pub const BUGCHECK_LIVE_SYSTEM_DUMP:    u32 = 0x161;
+
+/// Names of common bugcheck codes.
+pub const BUGCHECK_NAMES: &[(u32, &str)] = &[
+    (0x01, "APC_INDEX_MISMATCH"),
+    (0x0a, "IRQL_NOT_LESS_OR_EQUAL"),
+    (0x1a, "MEMORY_MANAGEMENT"),
+    (0x1e, "KMODE_EXCEPTION_NOT_HANDLED"),
+    (0x24, "NTFS_FILE_SYSTEM"),
+    (0x2e, "DATA_BUS_ERROR"),
+    (0x33, "UNEXPECTED_INITIALIZATION_CALL"),
+    (0x3b, "SYSTEM_SERVICE_EXCEPTION"),
+    (0x3d, "INTERRUPT_EXCEPTION_NOT_HANDLED"),
+    (0x4e, "PFN_LIST_CORRUPT"),
+    (0x50, "PAGE_FAULT_IN_NONPAGED_AREA"),
+    (0x51, "REGISTRY_ERROR"),
+    (0x5c, "HAL_INITIALIZATION_FAILED"),
+    (0x6b, "PROCESS1_INITIALIZATION_FAILED"),
+    (0x74, "BAD_SYSTEM_CONFIG_INFO"),
+    (0x77, "KERNEL_STACK_INPAGE_ERROR"),
+    (0x7a, "KERNEL_DATA_INPAGE_ERROR"),
+    (0x7b, "INACCESSIBLE_BOOT_DEVICE"),
+    (0x7e, "SYSTEM_THREAD_EXCEPTION_NOT_HANDLED"),
+    (0x7f, "UNEXPECTED_KERNEL_MODE_TRAP"),
+    (0x9c, "MACHINE_CHECK_EXCEPTION"),
+    (0x9f, "DRIVER_POWER_STATE_FAILURE"),
+    (0xa0, "INTERNAL_POWER_ERROR"),
+    (0xbe, "ATTEMPTED_WRITE_TO_READONLY_MEMORY"),
+    (0xc1, "SPECIAL_POOL_DETECTED_MEMORY_CORRUPTION"),
+    (0xc2, "BAD_POOL_CALLER"),
+    (0xc4, "DRIVER_VERIFIER_DETECTED_VIOLATION"),
+    (0xc5, "DRIVER_CORRUPTED_EXPOOL"),
+    (0xc9, "DRIVER_VERIFIER_IOMANAGER_VIOLATION"),
+    (0xca, "PNP_DETECTED_FATAL_ERROR"),
+    (0xce, "DRIVER_UNLOADED_WITHOUT_CANCELLING_PENDING_OPERATIONS"),
+    (0xd1, "DRIVER_IRQL_NOT_LESS_OR_EQUAL"),
+    (0xd5, "DRIVER_PAGE_FAULT_IN_FREED_SPECIAL_POOL"),
+    (0xe2, "MANUALLY_INITIATED_CRASH"),
+    (0xef, "CRITICAL_PROCESS_DIED"),
+    (0xf4, "CRITICAL_OBJECT_TERMINATION"),
+    (0xf7, "DRIVER_OVERRAN_STACK_BUFFER"),
+    (0xfc, "ATTEMPTED_EXECUTE_OF_NOEXECUTE_MEMORY"),
+    (0xfe, "BUGCODE_USB_DRIVER"),
+    (0x101, "CLOCK_WATCHDOG_TIMEOUT"),
+    (0x109, "CRITICAL_STRUCTURE_CORRUPTION"),
+    (0x116, "VIDEO_TDR_FAILURE"),
+    (0x117, "VIDEO_TDR_TIMEOUT_DETECTED"),
+    (0x119, "VIDEO_SCHEDULER_INTERNAL_ERROR"),
+    (0x124, "WHEA_UNCORRECTABLE_ERROR"),
+    (0x133, "DPC_WATCHDOG_VIOLATION"),
+    (0x139, "KERNEL_SECURITY_CHECK_FAILURE"),
+    (0x13a, "KERNEL_MODE_HEAP_CORRUPTION"),
+    (0x144, "BUGCODE_USB3_DRIVER"),
+    (0x154, "UNEXPECTED_STORE_EXCEPTION"),
+    (0x15f, "CONNECTED_STANDBY_WATCHDOG_TIMEOUT_LIVEDUMP"),
+    (0x161, "LIVE_SYSTEM_DUMP"),
+    (0x16c, "INVALID_RUNDOWN_PROTECTION_FLAGS"),
+    (0x18b, "SECURE_KERNEL_ERROR"),
+    (0x1a1, "WIN32K_CALLOUT_WATCHDOG_LIVEDUMP"),
+    (0x1c8, "MANUALLY_INITIATED_POWER_BUTTON_HOLD"),
+    (0x1e4, "VIDEO_ENGINE_TIMEOUT_DETECTED"),
+    (0xc000021a, "WINLOGON_FATAL_ERROR"),
+    (0xdeaddead, "MANUALLY_INITIATED_CRASH1"),
+];
+
+/// `CONTEXT` registers: (name, offset, size).
+const CONTEXT_REGISTERS_X86: &[(&str, usize, usize)] = &[
+    ("eax", 0xb0, 4), ("ebx", 0xa4, 4), ("ecx", 0xac, 4), ("edx", 0xa8, 4),
+    ("esi", 0xa0, 4), ("edi", 0x9c, 4), ("ebp", 0xb4, 4), ("esp", 0xc4, 4),
+    ("eip", 0xb8, 4), ("efl", 0xc0, 4),
+    ("cs", 0xbc, 2), ("ss", 0xc8, 2), ("ds", 0x98, 2), ("es", 0x94, 2), ("fs", 0x90, 2), ("gs", 0x8c, 2),
+];
+
+const CONTEXT_REGISTERS_X64: &[(&str, usize, usize)] = &[
+    ("rax", 0x78, 8), ("rbx", 0x90, 8), ("rcx", 0x80, 8), ("rdx", 0x88, 8),
+    ("rsi", 0xa8, 8), ("rdi", 0xb0, 8), ("rbp", 0xa0, 8), ("rsp", 0x98, 8),
+    ("r8", 0xb8, 8), ("r9", 0xc0, 8), ("r10", 0xc8, 8), ("r11", 0xd0, 8),
+    ("r12", 0xd8, 8), ("r13", 0xe0, 8), ("r14", 0xe8, 8), ("r15", 0xf0, 8),
+    ("rip", 0xf8, 8), ("efl", 0x44, 4),
+    ("cs", 0x38, 2), ("ss", 0x42, 2), ("ds", 0x3a, 2), ("es", 0x3c, 2), ("fs", 0x3e, 2), ("gs", 0x40, 2),
+];
+
+const CONTEXT_REGISTERS_ARM64: &[(&str, usize, usize)] = &[
+    ("x0", 0x08, 8), ("x1", 0x10, 8), ("x2", 0x18, 8), ("x3", 0x20, 8),
+    ("x4", 0x28, 8), ("x5", 0x30, 8), ("x6", 0x38, 8), ("x7", 0x40, 8),
+    ("x8", 0x48, 8), ("x9", 0x50, 8), ("x10", 0x58, 8), ("x11", 0x60, 8),
+    ("x12", 0x68, 8), ("x13", 0x70, 8), ("x14", 0x78, 8), ("x15", 0x80, 8),
+    ("x16", 0x88, 8), ("x17", 0x90, 8), ("x18", 0x98, 8), ("x19", 0xa0, 8),
+    ("x20", 0xa8, 8), ("x21", 0xb0, 8), ("x22", 0xb8, 8), ("x23", 0xc0, 8),
+    ("x24", 0xc8, 8), ("x25", 0xd0, 8), ("x26", 0xd8, 8), ("x27", 0xe0, 8),
+    ("x28", 0xe8, 8), ("fp", 0xf0, 8), ("lr", 0xf8, 8), ("sp", 0x100, 8),
+    ("pc", 0x108, 8), ("cpsr", 0x04, 4),
+];
+
+/// Bytes of `CONTEXT` needed for the registers.
+pub const CONTEXT_READ_SIZE: usize = 0x110;
+
+/// Name of the bugcheck `code`.
+pub fn bugcheck_name(code: u32) -> Option<&'static str> {
+    BUGCHECK_NAMES.iter()
+        .find(|(c, _)| *c == code)
+        .map(|(_, name)| *name)
+}
+
+/// General purpose registers of a `CONTEXT`.
+#[derive(Debug, Clone)]
+pub struct CpuContext {
+    /// (name, value, size in bytes).
+    pub registers:  Vec<(&'static str, u64, usize)>,
+}
+
+impl CpuContext {
+    /// Decode the `CONTEXT` at the start of `buf` for `machine`.
+    pub fn parse(machine: u32, buf: &[u8]) -> Result<Self> {
+        let layout = match machine {
+            IMAGE_FILE_MACHINE_I386 => CONTEXT_REGISTERS_X86,
+            IMAGE_FILE_MACHINE_AMD64 => CONTEXT_REGISTERS_X64,
+            IMAGE_FILE_MACHINE_ARM64 => CONTEXT_REGISTERS_ARM64,
+            _ => return Err(Error::DumpParseError(
+                format!("Unexpected machine type: 0x{:x}", machine))),
+        };
+
+        let registers = layout.iter()
+            .map(|&(name, offset, size)| {
+                let bytes = buf.get(offset..offset + size).ok_or_else(|| Error::DumpParseError(
+                    format!("CONTEXT too short for {}", name)))?;
+
+                let mut value = [0u8; 8];
+                value[..size].copy_from_slice(bytes);
+                Ok((name, u64::from_le_bytes(value), size))
+            })
+            .collect::<Result<_>>()?;
+
+        Ok(CpuContext { registers })
+    }
+
+    pub fn to_json(&self) -> String {
+        self.registers.iter()
+            .fold(JsonObject::new(), |obj, &(name, value, _)| obj.hex(name, value))
+            .finish()
+    }
+}
+
+impl fmt::Display for CpuContext {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, (name, value, size)) in self.registers.iter().enumerate() {
+            if i > 0 {
+                write!(f, "{}", if i % 4 == 0 { "\n" } else { " " })?;
+            }
+            write!(f, "{:>4}={:0width$x}", name, value, width = size * 2)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Saved context of a processor, from its `KPRCB`.
+#[derive(Debug, Clone)]
+pub struct ProcessorContext {
+    pub number:     u32,
+    pub prcb:       u64,
+    /// Why the context is missing otherwise.
+    pub context:    std::result::Result<CpuContext, String>,
+}
+
+/// Bugcheck of a crash dump.
+#[derive(Debug, Clone)]
+pub struct BugCheck {
+    pub code:           u32,
+    pub name:           Option<&'static str>,
+    pub parameters:     [u64; 4],
+    /// Producer of a synthetic bugcheck, for dumps of a running system.
+    pub synthetic:      Option<&'static str>,
+    /// Context of the processor that crashed, from the dump header, unless
+    /// it is left filled with "PAGE".
+    pub context:        Option<CpuContext>,
+    pub processors:     Vec<ProcessorContext>,
+}
+
+impl BugCheck {
+    /// Decode the bugcheck from the dump header alone.
+    pub fn new(crashdump: &CrashDump) -> Self {
+        let code = crashdump.bug_check_code;
+        let synthetic = match code {
+            BUGCHECK_DUMPIT => Some("DumpIt"),
+            BUGCHECK_LIVE_SYSTEM_DUMP => Some("live kernel dump"),
+            _ => None,
+        };
+
+        BugCheck {
+            code,
+            name: bugcheck_name(code),
+            parameters: crashdump.bug_check_parameters,
+            synthetic,
+            context: match crashdump.context_record.starts_with(b"PAGEPAGE") {
+                true => None,
+                false => CpuContext::parse(crashdump.machine_image_type, &crashdump.context_record).ok(),
+            },
+            processors: Vec::new(),
+        }
+    }
+
+    /// Read the saved context of every processor from the `KPRCB`s of
+    /// `KiProcessorBlock`.
+    pub fn read_processors<M: PhysicalMemory>(
+        &mut self,
+        space: &mut AddressSpace<M>,
+        crashdump: &CrashDump,
+        kdbg: &Kdbg
+    ) -> Result<()> {
+        let context_offset = match kdbg.offset("OffsetPrcbProcStateContext") {
+            Some(offset) if offset != 0 => offset as u64,
+            _ => return Err(Error::DumpParseError(
+                "KDBG has no OffsetPrcbProcStateContext".to_string())),
+        };
+
+        let processor_block = kdbg.data.ki_processor_block;
+        let pointer_size = space.pointer_size() as u64;
+
+        for number in 0..crashdump.number_processors {
+            let prcb = match space.read_ptr(processor_block + number as u64 * pointer_size) {
+                Ok(prcb) => prcb,
+                Err(e) => {
+                    warn!("Unreadable KiProcessorBlock[{}]: {:?}", number, e);
+                    break;
+                },
+            };
+
+            let mut buf = vec![0u8; CONTEXT_READ_SIZE];
+            let context = space.read_virt(prcb + context_offset, &mut buf)
+                .map_err(|e| format!("{:?}", e))
+                .and_then(|_| CpuContext::parse(crashdump.machine_image_type, &buf)
+                    .map_err(|e| format!("{:?}", e)));
+
+            self.processors.push(ProcessorContext { number, prcb, context });
+        }
+
+        Ok(())
+    }
+
+    pub fn to_json(&self) -> String {
+        let processors = json::array(self.processors.iter().map(|p| {
+            let obj = JsonObject::new()
+                .num("number", p.number as u64)
+                .hex("prcb", p.prcb);
+
+            match &p.context {
+                Ok(context) => obj.raw("context", Some(&context.to_json())),
+                Err(e) => obj.raw("context", None).str("error", e),
+            }.finish()
+        }));
+
+        JsonObject::new()
+            .hex("code", self.code as u64)
+            .raw("name", self.name.map(json::escape).as_deref())
+            .raw("parameters", Some(&json::array(self.parameters.iter().map(|p| json::escape(&format!("0x{:x}", p))))))
+            .raw("synthetic", self.synthetic.map(json::escape).as_deref())
+            .raw("context", self.context.as_ref().map(|c| c.to_json()).as_deref())
+            .raw("processors", Some(&processors))
+            .finish()
+    }
+}
+
+impl fmt::Display for BugCheck {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "BugCheck {:x}, {{{:x}, {:x}, {:x}, {:x}}}", self.code,
+            self.parameters[0], self.parameters[1], self.parameters[2], self.parameters[3])?;
+        writeln!(f, "Name:      {}", self.name.unwrap_or("unknown"))?;
+
+        if let Some(producer) = self.synthetic {
+            writeln!(f, "Synthetic: yes, written by {} from a running system", producer)?;
+        }
+
+        if let Some(context) = &self.context {
+            writeln!(f, "\nContext (dump header):\n{}", context)?;
+        }
+
+        for processor in &self.processors {
+            write!(f, "\nProcessor {} (KPRCB 0x{:x}):\n", processor.number, processor.prcb)?;
+            match &processor.context {
+                Ok(context) => writeln!(f, "{}", context)?,
+                Err(e) => writeln!(f, "context unreadable: {}", e)?,
+            }
+        }
+
+        Ok(())
+    }
+}
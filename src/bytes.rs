@@ -5,7 +5,7 @@ macro_rules! read_type {
         // `size_of` and `transmute` cannot be easily used with generics.
         let mut buf = [0u8; std::mem::size_of::<$ty>()];
 
-        $rdr.read(&mut buf)?;
+        $rdr.read_exact(&mut buf)?;
 
         let hdr: $ty = unsafe { std::mem::transmute(buf) };
 
@@ -13,4 +13,9 @@ macro_rules! read_type {
 
         res
     }}
-}
\ No newline at end of file
+}
+
+/// View a `#[repr(C, packed)]` struct as its on-disk bytes.
+pub fn as_bytes<T: Copy>(val: &T) -> &[u8] {
+    unsafe { std::slice::from_raw_parts(val as *const T as *const u8, std::mem::size_of::<T>()) }
+}
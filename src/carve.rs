@@ -0,0 +1,336 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+use crate::json::{self, JsonObject};
+use crate::paging::AddressSpace;
+use crate::pe::{self, PeHeader, PeSection, IMAGE_DOS_SIGNATURE};
+use crate::physmem::PhysicalMemory;
+use crate::result::Result;
+use crate::zdmp::PAGE_SIZE;
+
+/// Largest image carved, in case of a corrupted `SizeOfImage`.
+pub const MAX_IMAGE_SIZE: u32 = 0x1000_0000;
+
+/// Name of the manifest written next to the carved images.
+pub const MANIFEST_NAME: &str = "manifest.json";
+
+/// Layout of a carved file.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum PeLayout {
+    /// Sections moved back to their raw data offsets.
+    File,
+    /// Sections left at their virtual addresses, with the section headers
+    /// patched to match.
+    Memory,
+}
+
+impl PeLayout {
+    pub fn name(&self) -> &'static str {
+        match self {
+            PeLayout::File => "file",
+            PeLayout::Memory => "memory",
+        }
+    }
+}
+
+/// A PE image written to disk.
+#[derive(Debug, Clone)]
+pub struct CarvedImage {
+    /// Physical or virtual address of the headers.
+    pub address:        u64,
+    pub is_virtual:     bool,
+    /// Loaded module name, when known.
+    pub name:           Option<String>,
+    pub size:           u32,
+    pub machine:        u16,
+    pub timestamp:      u32,
+    pub checksum:       u32,
+    pub imphash:        Option<String>,
+    /// Pages of the image that were not in the dump, written as zeroes.
+    pub missing_pages:  u64,
+    pub layout:         PeLayout,
+    pub file:           String,
+}
+
+impl CarvedImage {
+    pub fn to_json(&self) -> String {
+        JsonObject::new()
+            .hex("address", self.address)
+            .str("space", if self.is_virtual { "virtual" } else { "physical" })
+            .raw("name", self.name.as_deref().map(json::escape).as_deref())
+            .hex("size", self.size as u64)
+            .hex("machine", self.machine as u64)
+            .num("timestamp", self.timestamp as u64)
+            .hex("checksum", self.checksum as u64)
+            .raw("imphash", self.imphash.as_deref().map(json::escape).as_deref())
+            .num("missing_pages", self.missing_pages)
+            .str("layout", self.layout.name())
+            .str("file", &self.file)
+            .finish()
+    }
+}
+
+/// Carve the images whose headers start a physical page. Only the headers
+/// are known to be at the found address, the rest of the image is read from
+/// the following physical pages.
+pub fn carve_physical<M: PhysicalMemory>(mem: &mut M, out_dir: &Path) -> Result<Vec<CarvedImage>> {
+    fs::create_dir_all(out_dir)?;
+
+    let mut images = Vec::new();
+    for range in mem.ranges() {
+        for addr in range.step_by(PAGE_SIZE) {
+            if !starts_with_mz(|a, buf| mem.read_phys(a, buf), addr) {
+                continue;
+            }
+
+            let image = carve(|a, buf| mem.read_phys(a, buf), addr, false, None, out_dir)?;
+            images.extend(image);
+        }
+    }
+
+    write_manifest(&images, out_dir)?;
+    Ok(images)
+}
+
+/// Carve the images whose headers start a valid page of `space`. `names`
+/// maps image bases to module names.
+pub fn carve_virtual<M: PhysicalMemory>(
+    space: &mut AddressSpace<M>,
+    names: &HashMap<u64, String>,
+    out_dir: &Path
+) -> Result<Vec<CarvedImage>> {
+    fs::create_dir_all(out_dir)?;
+
+    let mut images = Vec::new();
+    let mut next_va = 0;
+
+    for mapping in space.mappings()? {
+        for offset in (0..mapping.size).step_by(PAGE_SIZE) {
+            let va = mapping.va + offset;
+            if va < next_va || !starts_with_mz(|a, buf| space.mem.read_phys(a, buf), mapping.phys + offset) {
+                continue;
+            }
+
+            let name = names.get(&va).map(|n| n.as_str());
+            if let Some(image) = carve(|a, buf| space.read_virt(a, buf), va, true, name, out_dir)? {
+                next_va = va + image.size as u64;
+                images.push(image);
+            }
+        }
+    }
+
+    write_manifest(&images, out_dir)?;
+    Ok(images)
+}
+
+fn starts_with_mz(mut read: impl FnMut(u64, &mut [u8]) -> Result<()>, addr: u64) -> bool {
+    let mut magic = [0u8; 2];
+    read(addr, &mut magic).is_ok() && u16::from_le_bytes(magic) == IMAGE_DOS_SIGNATURE
+}
+
+/// Read and write the image at `addr` if it has valid headers.
+fn carve(
+    mut read: impl FnMut(u64, &mut [u8]) -> Result<()>,
+    addr: u64,
+    is_virtual: bool,
+    name: Option<&str>,
+    out_dir: &Path
+) -> Result<Option<CarvedImage>> {
+    let mut header = vec![0u8; PAGE_SIZE];
+    if read(addr, &mut header).is_err() {
+        return Ok(None);
+    }
+
+    let hdr = match PeHeader::parse(&header) {
+        Ok(hdr) if hdr.size_of_image as usize >= PAGE_SIZE && hdr.size_of_image <= MAX_IMAGE_SIZE => hdr,
+        _ => return Ok(None),
+    };
+
+    let size = hdr.size_of_image as usize;
+    let mut image = vec![0u8; size];
+    image[..PAGE_SIZE].copy_from_slice(&header);
+
+    let mut missing_pages = 0;
+    for offset in (PAGE_SIZE..size).step_by(PAGE_SIZE) {
+        let len = PAGE_SIZE.min(size - offset);
+        if read(addr + offset as u64, &mut image[offset..offset + len]).is_err() {
+            image[offset..offset + len].fill(0);
+            missing_pages += 1;
+        }
+    }
+
+    let imphash = pe::imphash(&hdr.imports(&image));
+    let (data, layout) = rebuild(&hdr, &image);
+
+    let file = match name {
+        Some(name) => format!("{:x}_{}", addr, sanitize(name)),
+        None => format!("{:x}.{}", addr, hdr.extension()),
+    };
+    fs::write(out_dir.join(&file), data)?;
+
+    Ok(Some(CarvedImage {
+        address: addr,
+        is_virtual,
+        name: name.map(|n| n.to_string()),
+        size: hdr.size_of_image,
+        machine: hdr.machine,
+        timestamp: hdr.time_date_stamp,
+        checksum: hdr.checksum,
+        imphash,
+        missing_pages,
+        layout,
+        file,
+    }))
+}
+
+/// Convert a mapped image to the file layout when the section table allows
+/// it, or patch the section table to the memory layout.
+fn rebuild(hdr: &PeHeader, image: &[u8]) -> (Vec<u8>, PeLayout) {
+    let sections = hdr.sections(image);
+
+    if has_file_layout(hdr, &sections) {
+        let end = sections.iter()
+            .map(|s| s.pointer_to_raw_data as usize + s.size_of_raw_data as usize)
+            .max()
+            .unwrap_or(0)
+            .max(hdr.size_of_headers as usize);
+
+        let mut out = vec![0u8; end];
+        let headers = (hdr.size_of_headers as usize).min(image.len());
+        out[..headers].copy_from_slice(&image[..headers]);
+
+        // Sections past the end of the image have nothing to copy.
+        for section in &sections {
+            let src = section.virtual_address as usize;
+            if src >= image.len() {
+                continue;
+            }
+            let len = (section.size_of_raw_data as usize).min(image.len() - src);
+            let dst = section.pointer_to_raw_data as usize;
+            out[dst..dst + len].copy_from_slice(&image[src..src + len]);
+        }
+
+        return (out, PeLayout::File);
+    }
+
+    let mut out = image.to_vec();
+    for section in &sections {
+        let size = section.virtual_size.max(section.size_of_raw_data)
+            .min((image.len() as u32).saturating_sub(section.virtual_address));
+        let off = section.header_offset;
+        out[off + 16..off + 20].copy_from_slice(&size.to_le_bytes());
+        out[off + 20..off + 24].copy_from_slice(&section.virtual_address.to_le_bytes());
+    }
+
+    // FileAlignment = SectionAlignment.
+    let opt = hdr.nt_offset as usize + 0x18;
+    out[opt + 0x24..opt + 0x28].copy_from_slice(&hdr.section_alignment.to_le_bytes());
+
+    (out, PeLayout::Memory)
+}
+
+/// Whether the raw data of the sections is aligned, after the headers, and
+/// does not overlap.
+fn has_file_layout(hdr: &PeHeader, sections: &[PeSection]) -> bool {
+    let alignment = hdr.file_alignment;
+    if sections.is_empty() || !alignment.is_power_of_two() {
+        return false;
+    }
+
+    let mut raw: Vec<(u32, u32)> = sections.iter()
+        .filter(|s| s.size_of_raw_data != 0)
+        .map(|s| (s.pointer_to_raw_data, s.size_of_raw_data))
+        .collect();
+    raw.sort();
+
+    let mut end = hdr.size_of_headers;
+    for (pointer, size) in raw {
+        if pointer % alignment != 0 || pointer < end || pointer > MAX_IMAGE_SIZE || size > MAX_IMAGE_SIZE {
+            return false;
+        }
+        end = match pointer.checked_add(size) {
+            Some(end) => end,
+            None => return false,
+        };
+    }
+
+    end <= MAX_IMAGE_SIZE
+}
+
+/// Module name usable as a file name.
+fn sanitize(name: &str) -> String {
+    name.chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '.' || c == '_' || c == '-' { c } else { '_' })
+        .collect()
+}
+
+fn write_manifest(images: &[CarvedImage], out_dir: &Path) -> Result<()> {
+    let manifest = json::array(images.iter().map(|image| image.to_json()));
+    fs::write(out_dir.join(MANIFEST_NAME), manifest + "\n")?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SECTION_TABLE: usize = 0x80 + 0x18 + 0xf0;
+
+    /// A mapped PE32+ image of `size_of_image` bytes with one section.
+    fn image(size_of_image: u32, virtual_address: u32, pointer_to_raw_data: u32) -> Vec<u8> {
+        let mut image = vec![0u8; size_of_image as usize];
+        let mut put = |off: usize, bytes: &[u8]| image[off..off + bytes.len()].copy_from_slice(bytes);
+
+        put(0, &IMAGE_DOS_SIGNATURE.to_le_bytes());
+        put(0x3c, &0x80u32.to_le_bytes());
+        put(0x80, b"PE\0\0");
+        put(0x84, &0x8664u16.to_le_bytes());
+        put(0x86, &1u16.to_le_bytes());
+        put(0x94, &0xf0u16.to_le_bytes());
+        put(0x98, &0x20bu16.to_le_bytes());
+        put(0x98 + 0x20, &0x1000u32.to_le_bytes());
+        put(0x98 + 0x24, &0x200u32.to_le_bytes());
+        put(0x98 + 0x38, &size_of_image.to_le_bytes());
+        put(0x98 + 0x3c, &0x400u32.to_le_bytes());
+
+        put(SECTION_TABLE, b".text");
+        put(SECTION_TABLE + 8, &0x1000u32.to_le_bytes());
+        put(SECTION_TABLE + 12, &virtual_address.to_le_bytes());
+        put(SECTION_TABLE + 16, &0x200u32.to_le_bytes());
+        put(SECTION_TABLE + 20, &pointer_to_raw_data.to_le_bytes());
+        image
+    }
+
+    #[test]
+    fn rebuild_file_layout() {
+        let mut image = image(0x2000, 0x1000, 0x400);
+        image[0x1000..0x1200].fill(0xcc);
+        let hdr = PeHeader::parse(&image).unwrap();
+
+        let (out, layout) = rebuild(&hdr, &image);
+        assert_eq!(layout, PeLayout::File);
+        assert_eq!(out.len(), 0x600);
+        assert!(out[0x400..0x600].iter().all(|&b| b == 0xcc));
+    }
+
+    #[test]
+    fn rebuild_section_past_the_image() {
+        let image = image(0x2000, 0x9000, 0x400);
+        let hdr = PeHeader::parse(&image).unwrap();
+
+        let (out, layout) = rebuild(&hdr, &image);
+        assert_eq!(layout, PeLayout::File);
+        assert!(out[0x400..].iter().all(|&b| b == 0));
+    }
+
+    #[test]
+    fn rebuild_overflowing_raw_data() {
+        let image = image(0x2000, 0x1000, 0xffff_f000);
+        let hdr = PeHeader::parse(&image).unwrap();
+
+        let (out, layout) = rebuild(&hdr, &image);
+        assert_eq!(layout, PeLayout::Memory);
+        assert_eq!(out.len(), image.len());
+    }
+}
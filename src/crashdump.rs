@@ -0,0 +1,631 @@
+use std::fmt;
+use std::io::{Read, Seek};
+
+use crate::paging::PagingMode;
+use crate::reader::ZdmpReader;
+use crate::result::{Result, Error};
+use crate::zdmp::PAGE_SIZE;
+
+pub const DUMP_SIGNATURE:               u32 = 0x4547_4150;  // EGAP
+pub const DUMP_VALID_DUMP:              u32 = 0x504d_5544;  // PMUD
+pub const DUMP_VALID_DUMP64:            u32 = 0x3436_5544;  // 46UD
+pub const DUMP_SUMMARY_SIGNATURE:       u32 = 0x504d_4453;  // PMDS
+pub const DUMP_FULL_BITMAP_SIGNATURE:   u32 = 0x504d_4446;  // PMDF
+
+pub const DUMP_HEADER32_SIZE:           usize = 0x1000;
+pub const DUMP_HEADER64_SIZE:           usize = 0x2000;
+pub const BITMAP_DUMP_HEADER_SIZE:      usize = 0x38;
+
+pub const IMAGE_FILE_MACHINE_I386:      u32 = 0x014c;
+pub const IMAGE_FILE_MACHINE_AMD64:     u32 = 0x8664;
+pub const IMAGE_FILE_MACHINE_ARM64:     u32 = 0xaa64;
+
+pub const DUMP_TYPE_FULL:               u32 = 1;
+pub const DUMP_TYPE_SUMMARY:            u32 = 2;
+pub const DUMP_TYPE_HEADER:             u32 = 3;
+pub const DUMP_TYPE_TRIAGE:             u32 = 4;
+pub const DUMP_TYPE_BITMAP_FULL:        u32 = 5;
+pub const DUMP_TYPE_BITMAP_KERNEL:      u32 = 6;
+pub const DUMP_TYPE_AUTOMATIC:          u32 = 7;
+
+/// Windows 32-bit crash dump header ("PAGEDUMP").
+#[repr(C, packed)]
+#[derive(Debug, Copy, Clone)]
+pub struct DumpHeader32 {
+    pub signature:                      u32,
+    pub valid_dump:                     u32,
+    pub major_version:                  u32,
+    pub minor_version:                  u32,
+    pub directory_table_base:           u32,
+    pub pfn_data_base:                  u32,
+    pub ps_loaded_module_list:          u32,
+    pub ps_active_process_head:         u32,
+    pub machine_image_type:             u32,
+    pub number_processors:              u32,
+    pub bug_check_code:                 u32,
+    pub bug_check_parameters:           [u32; 4],
+    pub version_user:                   [u8; 32],
+    pub pae_enabled:                    u8,
+    pub kd_secondary_version:           u8,
+    pub spare3:                         [u8; 2],
+    pub kd_debugger_data_block:         u32,
+    pub physical_memory_block_buffer:   [u8; 0x2bc],
+    pub context_record:                 [u8; 0x4b0],
+    pub exception:                      [u8; 0x50],
+    pub comment:                        [u8; 0x80],
+    pub attributes:                     u32,
+    pub boot_id:                        u32,
+    pub reserved0:                      [u8; 0x6e0],
+    pub dump_type:                      u32,
+    pub mini_dump_fields:               u32,
+    pub secondary_data_state:           u32,
+    pub product_type:                   u32,
+    pub suite_mask:                     u32,
+    pub writer_status:                  u32,
+    pub required_dump_space:            u64,
+    pub reserved2:                      [u8; 16],
+    pub system_up_time:                 u64,
+    pub system_time:                    u64,
+    pub reserved3:                      [u8; 56],
+}
+
+/// Windows 64-bit crash dump header ("PAGEDU64").
+#[repr(C, packed)]
+#[derive(Debug, Copy, Clone)]
+pub struct DumpHeader64 {
+    pub signature:                      u32,
+    pub valid_dump:                     u32,
+    pub major_version:                  u32,
+    pub minor_version:                  u32,
+    pub directory_table_base:           u64,
+    pub pfn_data_base:                  u64,
+    pub ps_loaded_module_list:          u64,
+    pub ps_active_process_head:         u64,
+    pub machine_image_type:             u32,
+    pub number_processors:              u32,
+    pub bug_check_code:                 u32,
+    pub _pad0:                          u32,
+    pub bug_check_parameters:           [u64; 4],
+    pub version_user:                   [u8; 32],
+    pub kd_debugger_data_block:         u64,
+    pub physical_memory_block_buffer:   [u8; 0x2c0],
+    pub context_record:                 [u8; 0xbb8],
+    pub exception:                      [u8; 0x98],
+    pub dump_type:                      u32,
+    pub _pad1:                          u32,
+    pub required_dump_space:            u64,
+    pub system_time:                    u64,
+    pub comment:                        [u8; 0x80],
+    pub system_up_time:                 u64,
+    pub mini_dump_fields:               u32,
+    pub secondary_data_state:           u32,
+    pub product_type:                   u32,
+    pub suite_mask:                     u32,
+    pub writer_status:                  u32,
+    pub unused1:                        u8,
+    pub kd_secondary_version:           u8,
+    pub unused:                         [u8; 2],
+    pub attributes:                     u32,
+    pub boot_id:                        u32,
+    pub reserved1:                      [u8; 0xfa8],
+}
+
+/// Header of the page bitmap following a 64-bit bitmap dump header
+/// ("SDMP" or "FDMP"). Bit `n` of the bitmap is set when physical page `n`
+/// is stored; stored pages are back to back from `first_page`.
+#[repr(C, packed)]
+#[derive(Debug, Copy, Clone)]
+pub struct BitmapDumpHeader {
+    pub signature:                      u32,
+    pub valid_dump:                     u32,
+    pub reserved:                       [u8; 0x18],
+    /// File offset of the first stored page.
+    pub first_page:                     u64,
+    pub total_present_pages:            u64,
+    /// Number of bits in the bitmap.
+    pub pages:                          u64,
+}
+
+impl BitmapDumpHeader {
+    pub fn signature_name(&self) -> &'static str {
+        match self.signature {
+            DUMP_SUMMARY_SIGNATURE => "SDMP",
+            DUMP_FULL_BITMAP_SIGNATURE => "FDMP",
+            _ => "unknown",
+        }
+    }
+
+    /// Size of the bitmap in bytes.
+    pub fn bitmap_size(&self) -> u64 {
+        self.pages.div_ceil(8)
+    }
+}
+
+/// A run of physical pages and where it is stored in the dump.
+#[derive(Debug, Copy, Clone)]
+pub struct PhysicalMemoryRun {
+    pub base_page:      u64,
+    pub page_count:     u64,
+    pub file_offset:    u64,
+}
+
+impl PhysicalMemoryRun {
+    pub fn base_address(&self) -> u64 {
+        self.base_page * PAGE_SIZE as u64
+    }
+
+    pub fn size(&self) -> u64 {
+        self.page_count * PAGE_SIZE as u64
+    }
+}
+
+/// Crash dump embedded in a zdmp.
+///
+/// The fields of the 32-bit and 64-bit headers are widened to 64 bits.
+#[derive(Debug, Clone)]
+pub struct CrashDump {
+    pub is_64bit:                   bool,
+    pub major_version:              u32,
+    pub minor_version:              u32,
+    pub directory_table_base:       u64,
+    pub pfn_data_base:              u64,
+    pub ps_loaded_module_list:      u64,
+    pub ps_active_process_head:     u64,
+    pub machine_image_type:         u32,
+    pub number_processors:          u32,
+    pub bug_check_code:             u32,
+    pub bug_check_parameters:       [u64; 4],
+    pub kd_debugger_data_block:     u64,
+    pub pae_enabled:                bool,
+    pub dump_type:                  u32,
+    pub required_dump_space:        u64,
+    pub system_time:                u64,
+    /// `CONTEXT` of the processor that crashed.
+    pub context_record:             Vec<u8>,
+    pub comment:                    String,
+    /// Size of the header pages before the page data.
+    pub header_size:                u64,
+    /// Page bitmap header of bitmap dumps.
+    pub bitmap:                     Option<BitmapDumpHeader>,
+    pub runs:                       Vec<PhysicalMemoryRun>,
+}
+
+impl CrashDump {
+    /// Parse the crash dump header at the start of `buf`.
+    pub fn new(buf: &[u8]) -> Result<Self> {
+        match buf.get(..8) {
+            Some(b"PAGEDU64") => CrashDump::from_header64(buf),
+            Some(b"PAGEDUMP") => CrashDump::from_header32(buf),
+            _ => Err(Error::DumpParseError(
+                "Unexpected crash dump signature".to_string())),
+        }
+    }
+
+    fn from_header32(buf: &[u8]) -> Result<Self> {
+        let mut rdr = buf;
+        let hdr = read_type!(&mut rdr, DumpHeader32)?;
+
+        if hdr.signature != DUMP_SIGNATURE || hdr.valid_dump != DUMP_VALID_DUMP {
+            return Err(Error::DumpParseError(
+                format!("Unexpected crash dump signature: 0x{:x} 0x{:x}",
+                    { hdr.signature }, { hdr.valid_dump })));
+        }
+
+        let params = hdr.bug_check_parameters;
+        let header_size = DUMP_HEADER32_SIZE as u64;
+
+        Ok(CrashDump {
+            is_64bit: false,
+            major_version: hdr.major_version,
+            minor_version: hdr.minor_version,
+            directory_table_base: hdr.directory_table_base as u64,
+            pfn_data_base: hdr.pfn_data_base as u64,
+            ps_loaded_module_list: hdr.ps_loaded_module_list as u64,
+            ps_active_process_head: hdr.ps_active_process_head as u64,
+            machine_image_type: hdr.machine_image_type,
+            number_processors: hdr.number_processors,
+            bug_check_code: hdr.bug_check_code,
+            bug_check_parameters: [params[0] as u64, params[1] as u64,
+                params[2] as u64, params[3] as u64],
+            kd_debugger_data_block: hdr.kd_debugger_data_block as u64,
+            pae_enabled: hdr.pae_enabled != 0,
+            dump_type: hdr.dump_type,
+            required_dump_space: hdr.required_dump_space,
+            system_time: hdr.system_time,
+            context_record: hdr.context_record.to_vec(),
+            comment: c_string(&hdr.comment),
+            header_size,
+            bitmap: None,
+            runs: parse_runs32(&hdr.physical_memory_block_buffer, header_size)?,
+        })
+    }
+
+    fn from_header64(buf: &[u8]) -> Result<Self> {
+        let mut rdr = buf;
+        let hdr = read_type!(&mut rdr, DumpHeader64)?;
+
+        if hdr.signature != DUMP_SIGNATURE || hdr.valid_dump != DUMP_VALID_DUMP64 {
+            return Err(Error::DumpParseError(
+                format!("Unexpected crash dump signature: 0x{:x} 0x{:x}",
+                    { hdr.signature }, { hdr.valid_dump })));
+        }
+
+        let mut header_size = DUMP_HEADER64_SIZE as u64;
+        let mut runs = parse_runs64(&hdr.physical_memory_block_buffer, header_size)?;
+
+        // Bitmap dumps describe their pages with the bitmap following the
+        // header rather than with the run descriptors.
+        let bitmap = match parse_bitmap_header(&buf[DUMP_HEADER64_SIZE..]) {
+            Some(bitmap) => Some(bitmap),
+            None if matches!(hdr.dump_type, DUMP_TYPE_BITMAP_FULL | DUMP_TYPE_BITMAP_KERNEL)
+                && buf.len() >= DUMP_HEADER64_SIZE + BITMAP_DUMP_HEADER_SIZE => {
+                return Err(Error::DumpParseError(
+                    "Bitmap dump without an SDMP/FDMP bitmap header".to_string()));
+            },
+            None => None,
+        };
+
+        if let Some(bitmap) = &bitmap {
+            header_size = bitmap.first_page;
+            let bits = buf.get(DUMP_HEADER64_SIZE + BITMAP_DUMP_HEADER_SIZE..).unwrap_or(&[]);
+            runs = parse_bitmap_runs(bitmap, bits)?;
+        }
+
+        Ok(CrashDump {
+            is_64bit: true,
+            major_version: hdr.major_version,
+            minor_version: hdr.minor_version,
+            directory_table_base: hdr.directory_table_base,
+            pfn_data_base: hdr.pfn_data_base,
+            ps_loaded_module_list: hdr.ps_loaded_module_list,
+            ps_active_process_head: hdr.ps_active_process_head,
+            machine_image_type: hdr.machine_image_type,
+            number_processors: hdr.number_processors,
+            bug_check_code: hdr.bug_check_code,
+            bug_check_parameters: hdr.bug_check_parameters,
+            kd_debugger_data_block: hdr.kd_debugger_data_block,
+            pae_enabled: false,
+            dump_type: hdr.dump_type,
+            required_dump_space: hdr.required_dump_space,
+            system_time: hdr.system_time,
+            context_record: hdr.context_record.to_vec(),
+            comment: c_string(&hdr.comment),
+            header_size,
+            bitmap,
+            runs,
+        })
+    }
+
+    /// Parse the crash dump header from the first decompressed pages,
+    /// including the page bitmap of bitmap dumps.
+    pub fn from_reader<R: Read + Seek>(reader: &mut ZdmpReader<R>) -> Result<Self> {
+        let size = reader.uncompressed_size();
+        let len = ((DUMP_HEADER64_SIZE + BITMAP_DUMP_HEADER_SIZE) as u64).min(size);
+        let head = reader.read_head(len as usize)?;
+        let crashdump = CrashDump::new(&head)?;
+
+        let bitmap = match crashdump.bitmap {
+            Some(bitmap) => bitmap,
+            None => return Ok(crashdump),
+        };
+
+        let len = (DUMP_HEADER64_SIZE + BITMAP_DUMP_HEADER_SIZE) as u64 + bitmap.bitmap_size();
+        if len > size {
+            warn!("Page bitmap ends @ 0x{:x}, after the end of the dump @ 0x{:x}", len, size);
+        }
+
+        let head = reader.read_head(len.min(size) as usize)?;
+        CrashDump::new(&head)
+    }
+
+    /// Paging structures of the dumped system.
+    pub fn paging_mode(&self) -> PagingMode {
+        match (self.is_64bit, self.pae_enabled) {
+            _ if self.machine_image_type == IMAGE_FILE_MACHINE_ARM64 => PagingMode::Arm64,
+            (true, _) => PagingMode::X64,
+            (false, true) => PagingMode::X86Pae,
+            (false, false) => PagingMode::X86,
+        }
+    }
+
+    pub fn machine_name(&self) -> &'static str {
+        match self.machine_image_type {
+            IMAGE_FILE_MACHINE_I386 => "x86",
+            IMAGE_FILE_MACHINE_AMD64 => "x64",
+            IMAGE_FILE_MACHINE_ARM64 => "ARM64",
+            _ => "unknown",
+        }
+    }
+
+    pub fn dump_type_name(&self) -> &'static str {
+        match self.dump_type {
+            DUMP_TYPE_FULL => "full",
+            DUMP_TYPE_SUMMARY => "kernel (summary)",
+            DUMP_TYPE_HEADER => "header only",
+            DUMP_TYPE_TRIAGE => "triage",
+            DUMP_TYPE_BITMAP_FULL => "full (bitmap)",
+            DUMP_TYPE_BITMAP_KERNEL => "kernel (bitmap)",
+            DUMP_TYPE_AUTOMATIC => "automatic",
+            _ => "unknown",
+        }
+    }
+
+    /// Total number of physical pages in the runs.
+    pub fn page_count(&self) -> u64 {
+        self.runs.iter().map(|r| r.page_count).sum()
+    }
+
+    /// Physical address of the dump file offset `offset`, if it is in a run.
+    pub fn physical_address(&self, offset: u64) -> Option<u64> {
+        self.runs.iter()
+            .find(|run| (run.file_offset..run.file_offset + run.size()).contains(&offset))
+            .map(|run| run.base_address() + offset - run.file_offset)
+    }
+}
+
+#[cfg(test)]
+impl CrashDump {
+    /// Header of a `machine` dump of kernel `build`, without memory.
+    pub(crate) fn empty(machine: u32, build: u32) -> Self {
+        CrashDump {
+            is_64bit: machine != IMAGE_FILE_MACHINE_I386,
+            major_version: 15,
+            minor_version: build,
+            directory_table_base: 0,
+            pfn_data_base: 0,
+            ps_loaded_module_list: 0,
+            ps_active_process_head: 0,
+            machine_image_type: machine,
+            number_processors: 1,
+            bug_check_code: 0,
+            bug_check_parameters: [0; 4],
+            kd_debugger_data_block: 0,
+            pae_enabled: false,
+            dump_type: 0,
+            required_dump_space: 0,
+            system_time: 0,
+            context_record: Vec::new(),
+            comment: String::new(),
+            header_size: 0,
+            bitmap: None,
+            runs: Vec::new(),
+        }
+    }
+}
+
+/// Text of a NUL terminated, fixed size byte string. Unused header fields
+/// are filled with "PAGE".
+fn c_string(buf: &[u8]) -> String {
+    if buf.starts_with(b"PAGEPAGE") {
+        return String::new();
+    }
+
+    let len = buf.iter().position(|&b| b == 0).unwrap_or(buf.len());
+    String::from_utf8_lossy(&buf[..len]).to_string()
+}
+
+/// Parse a `_PHYSICAL_MEMORY_DESCRIPTOR32` whose pages are stored back to
+/// back from `data_offset`.
+fn parse_runs32(buf: &[u8], data_offset: u64) -> Result<Vec<PhysicalMemoryRun>> {
+    let u32_at = |off: usize| u32::from_le_bytes([buf[off], buf[off + 1], buf[off + 2], buf[off + 3]]);
+
+    let number_of_runs = u32_at(0);
+
+    // Unused descriptors are filled with the "PAGE" signature.
+    if number_of_runs == DUMP_SIGNATURE {
+        return Ok(Vec::new());
+    }
+
+    let max_runs = (buf.len() - 0x8) / 0x8;
+    let mut runs = Vec::new();
+    let mut file_offset = data_offset;
+
+    for i in 0..(number_of_runs as usize).min(max_runs) {
+        let base_page = u32_at(0x8 + i * 0x8) as u64;
+        let page_count = u32_at(0xc + i * 0x8) as u64;
+
+        let run = checked_run(base_page, page_count, file_offset)?;
+        file_offset += run.size();
+        runs.push(run);
+    }
+
+    Ok(runs)
+}
+
+/// Parse a `_PHYSICAL_MEMORY_DESCRIPTOR64` whose pages are stored back to
+/// back from `data_offset`.
+fn parse_runs64(buf: &[u8], data_offset: u64) -> Result<Vec<PhysicalMemoryRun>> {
+    let u64_at = |off: usize| {
+        let mut v = [0u8; 8];
+        v.copy_from_slice(&buf[off..off + 8]);
+        u64::from_le_bytes(v)
+    };
+
+    let number_of_runs = u32::from_le_bytes([buf[0], buf[1], buf[2], buf[3]]);
+
+    // Unused descriptors are filled with the "PAGE" signature.
+    if number_of_runs == DUMP_SIGNATURE {
+        return Ok(Vec::new());
+    }
+
+    let max_runs = (buf.len() - 0x10) / 0x10;
+    let mut runs = Vec::new();
+    let mut file_offset = data_offset;
+
+    for i in 0..(number_of_runs as usize).min(max_runs) {
+        let base_page = u64_at(0x10 + i * 0x10);
+        let page_count = u64_at(0x18 + i * 0x10);
+
+        let run = checked_run(base_page, page_count, file_offset)?;
+        file_offset += run.size();
+        runs.push(run);
+    }
+
+    Ok(runs)
+}
+
+/// Parse the bitmap dump header at the start of `buf`, if there is one.
+fn parse_bitmap_header(buf: &[u8]) -> Option<BitmapDumpHeader> {
+    let read = |mut rdr: &[u8]| read_type!(&mut rdr, BitmapDumpHeader);
+    let bitmap = read(buf).ok()?;
+
+    match (bitmap.signature, bitmap.valid_dump) {
+        (DUMP_SUMMARY_SIGNATURE, DUMP_VALID_DUMP)
+        | (DUMP_FULL_BITMAP_SIGNATURE, DUMP_VALID_DUMP) => Some(bitmap),
+        _ => None,
+    }
+}
+
+/// Runs of the pages set in the page bitmap `bits`, stored back to back from
+/// the first page offset. A truncated bitmap gives the runs it covers.
+fn parse_bitmap_runs(bitmap: &BitmapDumpHeader, bits: &[u8]) -> Result<Vec<PhysicalMemoryRun>> {
+    let bits = &bits[..bits.len().min(bitmap.bitmap_size() as usize)];
+    let mut runs: Vec<PhysicalMemoryRun> = Vec::new();
+    let mut file_offset = bitmap.first_page;
+
+    for (i, &byte) in bits.iter().enumerate() {
+        if byte == 0 {
+            continue;
+        }
+
+        for bit in 0..8 {
+            let page = i as u64 * 8 + bit;
+            if byte & (1 << bit) == 0 || page >= bitmap.pages {
+                continue;
+            }
+
+            match runs.last_mut() {
+                Some(run) if run.base_page + run.page_count == page => run.page_count += 1,
+                _ => runs.push(checked_run(page, 1, file_offset)?),
+            }
+            file_offset = file_offset.checked_add(PAGE_SIZE as u64).ok_or_else(|| Error::DumpParseError(
+                format!("Page bitmap first page offset 0x{:x} overflows", { bitmap.first_page })))?;
+        }
+    }
+
+    Ok(runs)
+}
+
+/// A run whose physical range and pages in the file fit in 64 bits.
+fn checked_run(base_page: u64, page_count: u64, file_offset: u64) -> Result<PhysicalMemoryRun> {
+    let page_size = PAGE_SIZE as u64;
+    let fits = base_page.checked_add(page_count)
+        .and_then(|end_page| end_page.checked_mul(page_size))
+        .zip(page_count.checked_mul(page_size).and_then(|size| file_offset.checked_add(size)))
+        .is_some();
+
+    if !fits {
+        return Err(Error::DumpParseError(format!(
+            "Physical memory run of 0x{:x} pages @ page 0x{:x}, file offset 0x{:x} overflows",
+            page_count, base_page, file_offset)));
+    }
+
+    Ok(PhysicalMemoryRun { base_page, page_count, file_offset })
+}
+
+/// Format a FILETIME (100ns intervals since 1601) as UTC.
+pub fn format_filetime(filetime: u64) -> String {
+    const FILETIME_UNIX_EPOCH: u64 = 116_444_736_000_000_000;
+
+    if filetime < FILETIME_UNIX_EPOCH {
+        return format!("0x{:x}", filetime);
+    }
+
+    let secs = (filetime - FILETIME_UNIX_EPOCH) / 10_000_000;
+    format_unix_time(secs)
+}
+
+/// Format seconds since the Unix epoch as UTC.
+pub fn format_unix_time(secs: u64) -> String {
+    let (year, month, day, hour, minute, second) = unix_time_parts(secs);
+    format!("{:04}-{:02}-{:02} {:02}:{:02}:{:02} UTC", year, month, day, hour, minute, second)
+}
+
+/// UTC (year, month, day, hour, minute, second) of seconds since the Unix
+/// epoch.
+pub fn unix_time_parts(secs: u64) -> (i64, i64, i64, u64, u64, u64) {
+    let days = (secs / 86400) as i64;
+    let rem = secs % 86400;
+
+    // Days to civil date, from Howard Hinnant's `civil_from_days`.
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    (year, month, day, rem / 3600, rem / 60 % 60, rem % 60)
+}
+
+impl fmt::Display for CrashDump {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "OS build:               {} ({})", self.minor_version,
+            match self.major_version { 0xf => "free", 0xc => "checked", _ => "unknown" })?;
+        writeln!(f, "Machine:                0x{:x} ({})", self.machine_image_type, self.machine_name())?;
+        if !self.is_64bit {
+            writeln!(f, "PAE enabled:            {}", self.pae_enabled)?;
+        }
+        writeln!(f, "Processors:             {}", self.number_processors)?;
+        writeln!(f, "DirectoryTableBase:     0x{:x}", self.directory_table_base)?;
+        writeln!(f, "PfnDataBase:            0x{:x}", self.pfn_data_base)?;
+        writeln!(f, "PsLoadedModuleList:     0x{:x}", self.ps_loaded_module_list)?;
+        writeln!(f, "PsActiveProcessHead:    0x{:x}", self.ps_active_process_head)?;
+        writeln!(f, "KdDebuggerDataBlock:    0x{:x}", self.kd_debugger_data_block)?;
+
+        let params = self.bug_check_parameters;
+        writeln!(f, "BugCheck:               0x{:x} (0x{:x}, 0x{:x}, 0x{:x}, 0x{:x})",
+            self.bug_check_code, params[0], params[1], params[2], params[3])?;
+        writeln!(f, "SystemTime:             {}", format_filetime(self.system_time))?;
+        writeln!(f, "DumpType:               {} ({})", self.dump_type, self.dump_type_name())?;
+        writeln!(f, "RequiredDumpSpace:      0x{:x}", self.required_dump_space)?;
+        if let Some(bitmap) = &self.bitmap {
+            writeln!(f, "Page bitmap:            {}, {} of {} pages present, data @ 0x{:x}",
+                bitmap.signature_name(), { bitmap.total_present_pages }, { bitmap.pages },
+                { bitmap.first_page })?;
+        }
+        write!(f, "Physical memory runs:   {} ({} pages)", self.runs.len(), self.page_count())?;
+
+        for run in &self.runs {
+            write!(f, "\n  0x{:012x} - 0x{:012x} @ file offset 0x{:x}",
+                run.base_address(), run.base_address() + run.size(), run.file_offset)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A `_PHYSICAL_MEMORY_DESCRIPTOR64` of `runs` (base page, page count).
+    fn descriptor64(runs: &[(u64, u64)]) -> Vec<u8> {
+        let mut buf = vec![0u8; 0x10];
+        buf[..4].copy_from_slice(&(runs.len() as u32).to_le_bytes());
+        for &(base_page, page_count) in runs {
+            buf.extend_from_slice(&base_page.to_le_bytes());
+            buf.extend_from_slice(&page_count.to_le_bytes());
+        }
+        buf
+    }
+
+    #[test]
+    fn runs_are_stored_back_to_back() {
+        let runs = parse_runs64(&descriptor64(&[(1, 0x9f), (0x100, 0x10)]), 0x2000).unwrap();
+
+        assert_eq!(runs.len(), 2);
+        assert_eq!(runs[1].file_offset, 0x2000 + 0x9f * PAGE_SIZE as u64);
+        assert_eq!(runs[1].base_address(), 0x100000);
+    }
+
+    #[test]
+    fn reject_overflowing_runs() {
+        assert!(parse_runs64(&descriptor64(&[(u64::MAX - 1, 4)]), 0x2000).is_err());
+        assert!(parse_runs64(&descriptor64(&[(0, u64::MAX / 0x800)]), 0x2000).is_err());
+        assert!(parse_runs64(&descriptor64(&[(0, 0x10), (0x10, 0x10)]), u64::MAX - 0x10000).is_err());
+    }
+}
@@ -0,0 +1,553 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use flate2::Compression;
+use flate2::write::ZlibEncoder;
+
+use crate::bytes::as_bytes;
+use crate::crashdump::unix_time_parts;
+use crate::result::{Result, Error};
+
+pub const EWF_SIGNATURE:            [u8; 8] = *b"EVF\x09\x0d\x0a\xff\x00";
+
+pub const EWF_BYTES_PER_SECTOR:     u32 = 512;
+pub const EWF_SECTORS_PER_CHUNK:    u32 = 64;
+pub const EWF_CHUNK_SIZE:           usize = (EWF_BYTES_PER_SECTOR * EWF_SECTORS_PER_CHUNK) as usize;
+/// Largest number of chunks of a table, as written by EnCase 6.
+pub const EWF_MAX_TABLE_ENTRIES:    usize = 16375;
+
+pub const EWF_MEDIA_TYPE_MEMORY:    u8 = 0x10;
+pub const EWF_MEDIA_FLAG_IMAGE:     u8 = 0x01;
+pub const EWF_COMPRESSION_FAST:     u8 = 1;
+
+/// Table entry flag of a compressed chunk.
+const EWF_CHUNK_COMPRESSED:         u32 = 0x8000_0000;
+
+/// Header of an EWF segment file.
+#[repr(C, packed)]
+#[derive(Debug, Copy, Clone)]
+pub struct EwfFileHeader {
+    pub signature:                  [u8; 8],
+    pub fields_start:               u8,
+    pub segment_number:             u16,
+    pub fields_end:                 u16,
+}
+
+/// Descriptor at the start of every section.
+#[repr(C, packed)]
+#[derive(Debug, Copy, Clone)]
+pub struct EwfSectionDescriptor {
+    pub section_type:               [u8; 16],
+    /// File offset of the next section descriptor.
+    pub next_offset:                u64,
+    /// Size of the section, descriptor included.
+    pub section_size:               u64,
+    pub padding:                    [u8; 40],
+    pub checksum:                   u32,
+}
+
+/// Data of the "volume" section (EnCase 5 and later).
+#[repr(C, packed)]
+#[derive(Debug, Copy, Clone)]
+pub struct EwfVolume {
+    pub media_type:                 u8,
+    pub unknown1:                   [u8; 3],
+    pub number_of_chunks:           u32,
+    pub sectors_per_chunk:          u32,
+    pub bytes_per_sector:           u32,
+    pub number_of_sectors:          u64,
+    pub chs_cylinders:              u32,
+    pub chs_heads:                  u32,
+    pub chs_sectors:                u32,
+    pub media_flags:                u8,
+    pub unknown2:                   [u8; 3],
+    pub palm_volume_start_sector:   u32,
+    pub unknown3:                   [u8; 4],
+    pub smart_logs_start_sector:    u32,
+    pub compression_level:          u8,
+    pub unknown4:                   [u8; 3],
+    pub error_granularity:          u32,
+    pub unknown5:                   [u8; 4],
+    pub guid:                       [u8; 16],
+    pub unknown6:                   [u8; 963],
+    pub signature:                  [u8; 5],
+    pub checksum:                   u32,
+}
+
+/// Header of the "table" and "table2" sections, followed by the chunk
+/// offsets relative to `base_offset` and their checksum.
+#[repr(C, packed)]
+#[derive(Debug, Copy, Clone)]
+pub struct EwfTableHeader {
+    pub number_of_entries:          u32,
+    pub padding1:                   u32,
+    pub base_offset:                u64,
+    pub padding2:                   u32,
+    pub checksum:                   u32,
+}
+
+/// Data of the "digest" section.
+#[repr(C, packed)]
+#[derive(Debug, Copy, Clone)]
+pub struct EwfDigest {
+    pub md5:                        [u8; 16],
+    pub sha1:                       [u8; 20],
+    pub padding:                    [u8; 40],
+    pub checksum:                   u32,
+}
+
+/// Data of the "hash" section.
+#[repr(C, packed)]
+#[derive(Debug, Copy, Clone)]
+pub struct EwfHash {
+    pub md5:                        [u8; 16],
+    pub unknown:                    [u8; 16],
+    pub checksum:                   u32,
+}
+
+/// Case metadata of the "header" section.
+#[derive(Debug, Default, Clone)]
+pub struct EwfCaseInfo {
+    pub case_number:        String,
+    pub evidence_number:    String,
+    pub description:        String,
+    pub examiner:           String,
+    pub notes:              String,
+    pub os_version:         String,
+    /// Seconds since the Unix epoch.
+    pub acquisition_time:   Option<u64>,
+}
+
+impl EwfCaseInfo {
+    /// Header text in the EnCase 4 layout: a category line, a line of
+    /// field identifiers and a line of tab separated values.
+    fn header_text(&self, system_time: u64) -> String {
+        let date = |secs: u64| {
+            let (year, month, day, hour, minute, second) = unix_time_parts(secs);
+            format!("{} {} {} {} {} {}", year, month, day, hour, minute, second)
+        };
+        // Tabs and line breaks would break the layout.
+        let field = |s: &str| s.replace(['\t', '\r', '\n'], " ");
+
+        format!("1\r\nmain\r\nc\tn\ta\te\tt\tav\tov\tm\tu\tp\r\n\
+            {}\t{}\t{}\t{}\t{}\tz2dmp {}\t{}\t{}\t{}\t0\r\n\r\n",
+            field(&self.case_number), field(&self.evidence_number), field(&self.description),
+            field(&self.examiner), field(&self.notes),
+            env!("CARGO_PKG_VERSION"), field(&self.os_version),
+            date(self.acquisition_time.unwrap_or(system_time)), date(system_time))
+    }
+}
+
+/// Hashes and size of a written EWF image.
+#[derive(Debug, Clone)]
+pub struct EwfSummary {
+    pub chunks:         u64,
+    /// Bytes of the media, padding to a whole sector included.
+    pub media_size:     u64,
+    pub file_size:      u64,
+    pub md5:            String,
+    pub sha1:           String,
+}
+
+/// Writer of a single segment EWF (E01) image of `media_size` bytes.
+///
+/// The bytes written are split into zlib compressed chunks, stored in
+/// "sectors" sections of at most `EWF_MAX_TABLE_ENTRIES` chunks, each
+/// followed by its "table" and "table2". The "digest", "hash" and "done"
+/// sections are written by `finish`.
+///
+/// The media is padded with zeroes up to a whole sector. The MD5 and SHA1
+/// cover the padded media, as declared by the "volume" section.
+pub struct EwfWriter {
+    out:                BufWriter<File>,
+    /// Offset of the next section.
+    offset:             u64,
+    media_size:         u64,
+    /// `media_size` rounded up to a whole sector.
+    sectors_size:       u64,
+    number_of_chunks:   u64,
+    chunks_written:     u64,
+    chunk:              Vec<u8>,
+    /// Offset of the current "sectors" section, the base of its table.
+    sectors_offset:     u64,
+    table:              Vec<u32>,
+    md5:                md5::Context,
+    sha1:               sha1_smol::Sha1,
+}
+
+impl EwfWriter {
+    pub fn create(path: &Path, media_size: u64, case: &EwfCaseInfo) -> Result<Self> {
+        let sector_size = EWF_BYTES_PER_SECTOR as u64;
+        let sectors_size = media_size.div_ceil(sector_size) * sector_size;
+        if sectors_size != media_size {
+            warn!("EWF media of 0x{:x} bytes padded to 0x{:x}, the hashes cover the padding.",
+                media_size, sectors_size);
+        }
+        let number_of_chunks = sectors_size.div_ceil(EWF_CHUNK_SIZE as u64);
+        if number_of_chunks > u32::MAX as u64 {
+            return Err(Error::ContainerError("Media too large for an EWF volume".to_string()));
+        }
+
+        let mut writer = EwfWriter {
+            out: BufWriter::new(File::create(path)?),
+            offset: 0,
+            media_size,
+            sectors_size,
+            number_of_chunks,
+            chunks_written: 0,
+            chunk: Vec::with_capacity(EWF_CHUNK_SIZE),
+            sectors_offset: 0,
+            table: Vec::new(),
+            md5: md5::Context::new(),
+            sha1: sha1_smol::Sha1::new(),
+        };
+
+        let file_header = EwfFileHeader {
+            signature: EWF_SIGNATURE,
+            fields_start: 1,
+            segment_number: 1,
+            fields_end: 0,
+        };
+        writer.out.write_all(as_bytes(&file_header))?;
+        writer.offset = std::mem::size_of::<EwfFileHeader>() as u64;
+
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs());
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::best());
+        encoder.write_all(case.header_text(now).as_bytes())?;
+        writer.write_section(b"header", &encoder.finish()?)?;
+
+        let mut volume = EwfVolume {
+            media_type: EWF_MEDIA_TYPE_MEMORY,
+            unknown1: [0; 3],
+            number_of_chunks: number_of_chunks as u32,
+            sectors_per_chunk: EWF_SECTORS_PER_CHUNK,
+            bytes_per_sector: EWF_BYTES_PER_SECTOR,
+            number_of_sectors: sectors_size / sector_size,
+            chs_cylinders: 0,
+            chs_heads: 0,
+            chs_sectors: 0,
+            media_flags: EWF_MEDIA_FLAG_IMAGE,
+            unknown2: [0; 3],
+            palm_volume_start_sector: 0,
+            unknown3: [0; 4],
+            smart_logs_start_sector: 0,
+            compression_level: EWF_COMPRESSION_FAST,
+            unknown4: [0; 3],
+            error_granularity: EWF_SECTORS_PER_CHUNK,
+            unknown5: [0; 4],
+            guid: new_guid(now),
+            unknown6: [0; 963],
+            signature: [0; 5],
+            checksum: 0,
+        };
+        volume.checksum = adler32(&as_bytes(&volume)[..std::mem::size_of::<EwfVolume>() - 4]);
+        writer.write_section(b"volume", as_bytes(&volume))?;
+
+        if number_of_chunks > 0 {
+            writer.start_sectors()?;
+        }
+
+        Ok(writer)
+    }
+
+    /// Write a section of `data`, followed by the next section.
+    fn write_section(&mut self, section_type: &[u8], data: &[u8]) -> io::Result<()> {
+        let size = (std::mem::size_of::<EwfSectionDescriptor>() + data.len()) as u64;
+        let descriptor = section_descriptor(section_type, self.offset + size, size);
+
+        self.out.write_all(as_bytes(&descriptor))?;
+        self.out.write_all(data)?;
+        self.offset += size;
+
+        Ok(())
+    }
+
+    /// Leave room for the descriptor of a "sectors" section, written once
+    /// its chunks are.
+    fn start_sectors(&mut self) -> io::Result<()> {
+        self.sectors_offset = self.offset;
+        self.out.write_all(&[0u8; std::mem::size_of::<EwfSectionDescriptor>()])?;
+        self.offset += std::mem::size_of::<EwfSectionDescriptor>() as u64;
+        Ok(())
+    }
+
+    fn write_chunk(&mut self) -> io::Result<()> {
+        self.md5.consume(&self.chunk);
+        self.sha1.update(&self.chunk);
+
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::fast());
+        encoder.write_all(&self.chunk)?;
+        let compressed = encoder.finish()?;
+
+        let entry = (self.offset - self.sectors_offset) as u32;
+        if compressed.len() < self.chunk.len() {
+            self.table.push(entry | EWF_CHUNK_COMPRESSED);
+            self.out.write_all(&compressed)?;
+            self.offset += compressed.len() as u64;
+        } else {
+            // Uncompressed chunks carry their Adler-32.
+            self.table.push(entry);
+            self.out.write_all(&self.chunk)?;
+            self.out.write_all(&adler32(&self.chunk).to_le_bytes())?;
+            self.offset += self.chunk.len() as u64 + 4;
+        }
+
+        self.chunk.clear();
+        self.chunks_written += 1;
+
+        if self.table.len() == EWF_MAX_TABLE_ENTRIES || self.chunks_written == self.number_of_chunks {
+            self.finish_sectors()?;
+            if self.chunks_written < self.number_of_chunks {
+                self.start_sectors()?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Write the descriptor of the current "sectors" section and its tables.
+    fn finish_sectors(&mut self) -> io::Result<()> {
+        let size = self.offset - self.sectors_offset;
+        let descriptor = section_descriptor(b"sectors", self.offset, size);
+        self.out.seek(SeekFrom::Start(self.sectors_offset))?;
+        self.out.write_all(as_bytes(&descriptor))?;
+        self.out.seek(SeekFrom::Start(self.offset))?;
+
+        let mut header = EwfTableHeader {
+            number_of_entries: self.table.len() as u32,
+            padding1: 0,
+            base_offset: self.sectors_offset,
+            padding2: 0,
+            checksum: 0,
+        };
+        header.checksum = adler32(&as_bytes(&header)[..std::mem::size_of::<EwfTableHeader>() - 4]);
+
+        let mut table = as_bytes(&header).to_vec();
+        let entries: Vec<u8> = self.table.iter().flat_map(|e| e.to_le_bytes()).collect();
+        table.extend_from_slice(&entries);
+        table.extend_from_slice(&adler32(&entries).to_le_bytes());
+
+        self.write_section(b"table", &table)?;
+        self.write_section(b"table2", &table)?;
+        self.table.clear();
+
+        Ok(())
+    }
+
+    /// Pad the media with zeroes up to a whole sector, then write the hashes.
+    pub fn finish(mut self) -> Result<EwfSummary> {
+        let mut written = self.chunks_written * EWF_CHUNK_SIZE as u64 + self.chunk.len() as u64;
+        while written < self.sectors_size {
+            let len = (EWF_CHUNK_SIZE - self.chunk.len()).min((self.sectors_size - written) as usize);
+            self.chunk.resize(self.chunk.len() + len, 0);
+            written += len as u64;
+
+            if self.chunk.len() == EWF_CHUNK_SIZE {
+                self.write_chunk()?;
+            }
+        }
+        if !self.chunk.is_empty() {
+            self.write_chunk()?;
+        }
+
+        let md5 = self.md5.clone().compute().0;
+        let sha1 = self.sha1.digest().bytes();
+
+        let mut digest = EwfDigest { md5, sha1, padding: [0; 40], checksum: 0 };
+        digest.checksum = adler32(&as_bytes(&digest)[..std::mem::size_of::<EwfDigest>() - 4]);
+        self.write_section(b"digest", as_bytes(&digest))?;
+
+        let mut hash = EwfHash { md5, unknown: [0; 16], checksum: 0 };
+        hash.checksum = adler32(&as_bytes(&hash)[..std::mem::size_of::<EwfHash>() - 4]);
+        self.write_section(b"hash", as_bytes(&hash))?;
+
+        // The last section points to itself.
+        let descriptor = section_descriptor(b"done", self.offset,
+            std::mem::size_of::<EwfSectionDescriptor>() as u64);
+        self.out.write_all(as_bytes(&descriptor))?;
+        self.offset += std::mem::size_of::<EwfSectionDescriptor>() as u64;
+        self.out.flush()?;
+
+        let hex = |bytes: &[u8]| bytes.iter().map(|b| format!("{:02x}", b)).collect::<String>();
+        Ok(EwfSummary {
+            chunks: self.chunks_written,
+            media_size: self.sectors_size,
+            file_size: self.offset,
+            md5: hex(&md5),
+            sha1: hex(&sha1),
+        })
+    }
+}
+
+impl Write for EwfWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.chunks_written * EWF_CHUNK_SIZE as u64 + self.chunk.len() as u64;
+        if written + buf.len() as u64 > self.media_size {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "Write past the end of the EWF media"));
+        }
+
+        let len = (EWF_CHUNK_SIZE - self.chunk.len()).min(buf.len());
+        self.chunk.extend_from_slice(&buf[..len]);
+
+        if self.chunk.len() == EWF_CHUNK_SIZE {
+            self.write_chunk()?;
+        }
+
+        Ok(len)
+    }
+
+    /// Chunks are only written once full, or by `finish`.
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+fn section_descriptor(section_type: &[u8], next_offset: u64, section_size: u64) -> EwfSectionDescriptor {
+    let mut descriptor = EwfSectionDescriptor {
+        section_type: [0; 16],
+        next_offset,
+        section_size,
+        padding: [0; 40],
+        checksum: 0,
+    };
+    descriptor.section_type[..section_type.len()].copy_from_slice(section_type);
+    descriptor.checksum = adler32(&as_bytes(&descriptor)[..std::mem::size_of::<EwfSectionDescriptor>() - 4]);
+    descriptor
+}
+
+/// Set identifier of the image, from the md5 of the current time.
+fn new_guid(now: u64) -> [u8; 16] {
+    let nanos = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_nanos());
+    let mut ctx = md5::Context::new();
+    ctx.consume(now.to_le_bytes());
+    ctx.consume(nanos.to_le_bytes());
+    ctx.compute().0
+}
+
+/// Adler-32 checksum of `data`.
+pub fn adler32(data: &[u8]) -> u32 {
+    const MOD_ADLER: u32 = 65521;
+
+    let (mut a, mut b) = (1u32, 0u32);
+    // 5552 bytes is the most that can be summed before `b` overflows.
+    for block in data.chunks(5552) {
+        for &byte in block {
+            a += byte as u32;
+            b += a;
+        }
+        a %= MOD_ADLER;
+        b %= MOD_ADLER;
+    }
+
+    (b << 16) | a
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::io::Read;
+    use std::mem::size_of;
+
+    use flate2::read::ZlibDecoder;
+
+    use super::*;
+
+    /// Deterministic xorshift bytes.
+    fn noise(seed: u64, len: usize) -> Vec<u8> {
+        let mut x = 2 * seed + 1;
+        (0..len).map(|_| {
+            x ^= x << 13;
+            x ^= x >> 7;
+            x ^= x << 17;
+            x as u8
+        }).collect()
+    }
+
+    /// Media of an EWF image, as declared by its "volume" section and read
+    /// through its tables, with its "hash" and "digest" sections.
+    fn read_media(file: &[u8]) -> Result<(Vec<u8>, EwfHash, EwfDigest)> {
+        let mut media = Vec::new();
+        let (mut volume, mut hash, mut digest) = (None, None, None);
+        let mut sectors_end = 0;
+
+        let mut offset = size_of::<EwfFileHeader>();
+        loop {
+            let descriptor = read_type!(&mut &file[offset..], EwfSectionDescriptor)?;
+            let data = &file[offset + size_of::<EwfSectionDescriptor>()..];
+            let section_type = descriptor.section_type;
+            let name = &section_type[..section_type.iter().position(|&b| b == 0).unwrap()];
+
+            match name {
+                b"volume" => volume = Some(read_type!(&mut &data[..], EwfVolume)?),
+                b"sectors" => sectors_end = offset + descriptor.section_size as usize,
+                b"table" => {
+                    let header = read_type!(&mut &data[..], EwfTableHeader)?;
+                    let entries: Vec<usize> = data[size_of::<EwfTableHeader>()..]
+                        .chunks_exact(4)
+                        .take(header.number_of_entries as usize)
+                        .map(|e| u32::from_le_bytes([e[0], e[1], e[2], e[3]]) as usize)
+                        .collect();
+
+                    for (i, &entry) in entries.iter().enumerate() {
+                        let start = header.base_offset as usize + (entry & !(EWF_CHUNK_COMPRESSED as usize));
+                        let end = match entries.get(i + 1) {
+                            Some(next) => header.base_offset as usize + (next & !(EWF_CHUNK_COMPRESSED as usize)),
+                            None => sectors_end,
+                        };
+
+                        if entry & EWF_CHUNK_COMPRESSED as usize != 0 {
+                            ZlibDecoder::new(&file[start..end]).read_to_end(&mut media)?;
+                        } else {
+                            let chunk = &file[start..end - 4];
+                            assert_eq!(adler32(chunk).to_le_bytes(), file[end - 4..end]);
+                            media.extend_from_slice(chunk);
+                        }
+                    }
+                },
+                b"hash" => hash = Some(read_type!(&mut &data[..], EwfHash)?),
+                b"digest" => digest = Some(read_type!(&mut &data[..], EwfDigest)?),
+                b"done" => break,
+                _ => (),
+            }
+            offset = descriptor.next_offset as usize;
+        }
+
+        let volume = volume.unwrap();
+        assert_eq!(media.len() as u64, volume.number_of_sectors * volume.bytes_per_sector as u64);
+        assert_eq!(volume.number_of_chunks as usize, media.len().div_ceil(EWF_CHUNK_SIZE));
+
+        Ok((media, hash.unwrap(), digest.unwrap()))
+    }
+
+    #[test]
+    fn hashes_cover_the_declared_media() {
+        // An incompressible chunk, then a compressible one that does not end
+        // on a sector.
+        let mut data = noise(1, EWF_CHUNK_SIZE);
+        data.extend((0..1000).map(|i| (i * 7 % 251) as u8));
+        let path = std::env::temp_dir().join(format!("z2dmp-{}-padding.E01", std::process::id()));
+
+        let mut image = EwfWriter::create(&path, data.len() as u64, &EwfCaseInfo::default()).unwrap();
+        image.write_all(&data).unwrap();
+        assert!(image.write_all(&[0]).is_err());
+        let summary = image.finish().unwrap();
+
+        let (media, hash, digest) = read_media(&fs::read(&path).unwrap()).unwrap();
+        assert_eq!(media.len(), EWF_CHUNK_SIZE + 1024);
+        assert!(media[..data.len()] == data[..]);
+        assert!(media[data.len()..].iter().all(|&b| b == 0));
+
+        let md5 = md5::compute(&media).0;
+        let sha1 = sha1_smol::Sha1::from(&media).digest().bytes();
+        assert_eq!(({ hash.md5 }, { digest.md5 }, { digest.sha1 }), (md5, md5, sha1));
+
+        assert_eq!(summary.chunks, 2);
+        assert_eq!(summary.media_size, media.len() as u64);
+        assert_eq!(summary.md5, format!("{:x}", md5::compute(&media)));
+        assert_eq!(summary.sha1, sha1_smol::Sha1::from(&media).digest().to_string());
+
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,1012 @@
+use std::convert::TryFrom;
+use std::fs::File;
+use std::io::{self, BufWriter, Read, Seek, SeekFrom, Write};
+use std::mem;
+use std::path::Path;
+
+use crate::aff4::{self, Aff4Writer, Aff4Summary};
+use crate::bytes::as_bytes;
+use crate::crashdump::{CrashDump, PhysicalMemoryRun, DumpHeader64, BitmapDumpHeader,
+    DUMP_HEADER32_SIZE, DUMP_HEADER64_SIZE, BITMAP_DUMP_HEADER_SIZE, DUMP_FULL_BITMAP_SIGNATURE,
+    DUMP_VALID_DUMP, DUMP_TYPE_BITMAP_FULL, IMAGE_FILE_MACHINE_I386, IMAGE_FILE_MACHINE_AMD64,
+    IMAGE_FILE_MACHINE_ARM64};
+use crate::ewf::{EwfCaseInfo, EwfSummary, EwfWriter};
+use crate::reader::ZdmpReader;
+use crate::seekable::{SeekableSummary, SeekableWriter, SEEKABLE_DEFAULT_LEVEL};
+use crate::result::{Result, Error};
+use crate::zdmp::{BLOCK_FLAG_ZERO_RUN, PAGE_SIZE, ZdmpMetadata};
+
+pub const LIME_MAGIC:   u32 = 0x4c69_4d45;  // EMiL
+pub const LIME_VERSION: u32 = 1;
+
+/// Header of a LiME range, followed by its `end - start + 1` bytes.
+#[repr(C, packed)]
+#[derive(Debug, Copy, Clone)]
+pub struct LimeHeader {
+    pub magic:      u32,
+    pub version:    u32,
+    pub start:      u64,
+    /// Last address of the range, inclusive.
+    pub end:        u64,
+    pub reserved:   [u8; 8],
+}
+
+pub const ET_CORE:      u16 = 4;
+pub const EM_NONE:      u16 = 0;
+pub const EM_386:       u16 = 3;
+pub const EM_X86_64:    u16 = 62;
+pub const EM_AARCH64:   u16 = 183;
+pub const PT_LOAD:      u32 = 1;
+pub const PT_NOTE:      u32 = 4;
+pub const PF_RWX:       u32 = 7;
+/// `e_phnum` of ELF files whose segment count is in `sh_info` of the first
+/// section header.
+pub const PN_XNUM:      u16 = 0xffff;
+
+/// Owner name of the notes of ELF cores.
+pub const ELF_NOTE_NAME:        &[u8] = b"Z2DMP\0";
+/// Note holding the crash dump header.
+pub const NT_Z2DMP_DUMP_HEADER: u32 = 1;
+/// Note holding the `CONTEXT` of the processor that crashed.
+pub const NT_Z2DMP_CONTEXT:     u32 = 2;
+
+#[repr(C, packed)]
+#[derive(Debug, Copy, Clone)]
+pub struct Elf64Ehdr {
+    pub e_ident:        [u8; 16],
+    pub e_type:         u16,
+    pub e_machine:      u16,
+    pub e_version:      u32,
+    pub e_entry:        u64,
+    pub e_phoff:        u64,
+    pub e_shoff:        u64,
+    pub e_flags:        u32,
+    pub e_ehsize:       u16,
+    pub e_phentsize:    u16,
+    pub e_phnum:        u16,
+    pub e_shentsize:    u16,
+    pub e_shnum:        u16,
+    pub e_shstrndx:     u16,
+}
+
+#[repr(C, packed)]
+#[derive(Debug, Copy, Clone)]
+pub struct Elf64Phdr {
+    pub p_type:         u32,
+    pub p_flags:        u32,
+    pub p_offset:       u64,
+    pub p_vaddr:        u64,
+    pub p_paddr:        u64,
+    pub p_filesz:       u64,
+    pub p_memsz:        u64,
+    pub p_align:        u64,
+}
+
+#[repr(C, packed)]
+#[derive(Debug, Copy, Clone)]
+pub struct Elf64Shdr {
+    pub sh_name:        u32,
+    pub sh_type:        u32,
+    pub sh_flags:       u64,
+    pub sh_addr:        u64,
+    pub sh_offset:      u64,
+    pub sh_size:        u64,
+    pub sh_link:        u32,
+    pub sh_info:        u32,
+    pub sh_addralign:   u64,
+    pub sh_entsize:     u64,
+}
+
+/// Counts of a raw export.
+#[derive(Debug, Default, Copy, Clone)]
+pub struct RawExportStats {
+    pub runs:           u64,
+    pub bytes_written:  u64,
+    pub image_size:     u64,
+}
+
+/// Write the physical memory of the crash dump in the zdmp at `in_path` as a
+/// flat image at `out_path`, where file offset = physical address.
+///
+/// The zdmp is decoded in a single pass. Physical addresses outside the runs,
+/// and zero runs, are left as holes.
+pub fn export_raw(in_path: &Path, out_path: &Path, force: bool) -> Result<RawExportStats> {
+    let mut reader = ZdmpReader::open(in_path, force)?;
+    let crashdump = CrashDump::from_reader(&mut reader)?;
+
+    if crashdump.runs.is_empty() {
+        return Err(Error::DumpParseError(
+            "Crash dump has no physical memory runs".to_string()));
+    }
+
+    let mut out_file = File::create(out_path)?;
+    let mut stats = RawExportStats { runs: crashdump.runs.len() as u64, ..Default::default() };
+    let mut run_index = 0;
+
+    for_each_block(&mut reader, runs_end(&crashdump), |pos, data| {
+        if let DumpData::Bytes(data) = data {
+            stats.bytes_written += write_runs(&mut out_file, &crashdump.runs, &mut run_index, pos, data)?;
+        }
+        Ok(())
+    })?;
+
+    // Bitmap dumps may leave out the zero pages at the end of memory.
+    let bitmap_size = crashdump.bitmap.map_or(0, |bitmap| bitmap.pages * PAGE_SIZE as u64);
+    stats.image_size = crashdump.runs.iter()
+        .map(|run| run.base_address() + run.size())
+        .max()
+        .unwrap_or(0)
+        .max(bitmap_size);
+    out_file.set_len(stats.image_size)?;
+
+    Ok(stats)
+}
+
+/// Counts of a LiME export.
+#[derive(Debug, Default, Copy, Clone)]
+pub struct LimeExportStats {
+    pub ranges:         u64,
+    pub bytes_written:  u64,
+}
+
+/// Write the physical memory runs of the crash dump in the zdmp at `in_path`
+/// as LiME ranges at `out_path`, one range per run.
+///
+/// The zdmp is decoded in a single pass and the ranges are written in dump
+/// order. Zero runs are written out, and runs past the end of a truncated
+/// dump are padded with zeroes.
+pub fn export_lime(in_path: &Path, out_path: &Path, force: bool) -> Result<LimeExportStats> {
+    let mut reader = ZdmpReader::open(in_path, force)?;
+    let crashdump = CrashDump::from_reader(&mut reader)?;
+
+    // LiME ranges are inclusive, so empty runs have no range.
+    let mut runs: Vec<PhysicalMemoryRun> = crashdump.runs.iter()
+        .filter(|run| run.page_count != 0)
+        .copied()
+        .collect();
+    runs.sort_by_key(|run| run.file_offset);
+
+    if runs.is_empty() {
+        return Err(Error::DumpParseError(
+            "Crash dump has no physical memory runs".to_string()));
+    }
+
+    let write_header = |out: &mut BufWriter<File>, run: &PhysicalMemoryRun| {
+        let hdr = LimeHeader {
+            magic: LIME_MAGIC,
+            version: LIME_VERSION,
+            start: run.base_address(),
+            end: run.base_address() + run.size() - 1,
+            reserved: [0u8; 8],
+        };
+        out.write_all(as_bytes(&hdr))?;
+        Ok(())
+    };
+
+    let mut out = BufWriter::new(File::create(out_path)?);
+    let mut stream = RunStream::new(&runs);
+
+    for_each_block(&mut reader, runs_end(&crashdump), |pos, data| {
+        stream.write(&mut out, pos, data, write_header)
+    })?;
+    stream.finish(&mut out, write_header)?;
+    out.flush()?;
+
+    Ok(LimeExportStats { ranges: runs.len() as u64, bytes_written: stream.bytes_written })
+}
+
+/// Counts of an ELF core export.
+#[derive(Debug, Default, Copy, Clone)]
+pub struct ElfExportStats {
+    pub segments:       u64,
+    pub bytes_written:  u64,
+    pub file_size:      u64,
+}
+
+/// Write the physical memory runs of the crash dump in the zdmp at `in_path`
+/// as an ELF64 core at `out_path`, with a `PT_LOAD` segment per run whose
+/// virtual and physical addresses are the physical address of the run.
+///
+/// With `notes`, a `PT_NOTE` segment keeps the crash dump header and the
+/// `CONTEXT` of the processor that crashed. The zdmp is decoded in a single
+/// pass, like LiME exports.
+pub fn export_elf(in_path: &Path, out_path: &Path, force: bool, notes: bool) -> Result<ElfExportStats> {
+    let mut reader = ZdmpReader::open(in_path, force)?;
+    let crashdump = CrashDump::from_reader(&mut reader)?;
+
+    if crashdump.runs.is_empty() {
+        return Err(Error::DumpParseError(
+            "Crash dump has no physical memory runs".to_string()));
+    }
+
+    let mut runs = crashdump.runs.clone();
+    runs.sort_by_key(|run| run.file_offset);
+
+    let mut note = Vec::new();
+    if notes {
+        let header_size = if crashdump.is_64bit { DUMP_HEADER64_SIZE } else { DUMP_HEADER32_SIZE };
+        let head = reader.read_head(header_size)?;
+        push_elf_note(&mut note, NT_Z2DMP_DUMP_HEADER, &head);
+        push_elf_note(&mut note, NT_Z2DMP_CONTEXT, &crashdump.context_record);
+    }
+
+    let phnum = runs.len() + if notes { 1 } else { 0 };
+    let machine = match crashdump.machine_image_type {
+        IMAGE_FILE_MACHINE_I386 => EM_386,
+        IMAGE_FILE_MACHINE_AMD64 => EM_X86_64,
+        IMAGE_FILE_MACHINE_ARM64 => EM_AARCH64,
+        _ => EM_NONE,
+    };
+    let (ehdr, shdr) = elf_header(machine, phnum)?;
+
+    let headers_size = ehdr.e_phoff + (phnum * mem::size_of::<Elf64Phdr>()) as u64;
+    let note_offset = headers_size + shdr.map_or(0, |_| mem::size_of::<Elf64Shdr>() as u64);
+    let page_size = PAGE_SIZE as u64;
+    let data_offset = (note_offset + note.len() as u64).div_ceil(page_size) * page_size;
+
+    let mut out = BufWriter::new(File::create(out_path)?);
+    out.write_all(as_bytes(&ehdr))?;
+
+    if notes {
+        let phdr = Elf64Phdr {
+            p_type: PT_NOTE,
+            p_flags: 0,
+            p_offset: note_offset,
+            p_vaddr: 0,
+            p_paddr: 0,
+            p_filesz: note.len() as u64,
+            p_memsz: 0,
+            p_align: 4,
+        };
+        out.write_all(as_bytes(&phdr))?;
+    }
+
+    let mut offset = data_offset;
+    for run in &runs {
+        let phdr = Elf64Phdr {
+            p_type: PT_LOAD,
+            p_flags: PF_RWX,
+            p_offset: offset,
+            p_vaddr: run.base_address(),
+            p_paddr: run.base_address(),
+            p_filesz: run.size(),
+            p_memsz: run.size(),
+            p_align: page_size,
+        };
+        out.write_all(as_bytes(&phdr))?;
+        offset += run.size();
+    }
+
+    if let Some(shdr) = shdr {
+        out.write_all(as_bytes(&shdr))?;
+    }
+
+    out.write_all(&note)?;
+    write_zeroes(&mut out, data_offset - note_offset - note.len() as u64)?;
+
+    let mut stream = RunStream::new(&runs);
+    for_each_block(&mut reader, runs_end(&crashdump), |pos, data| {
+        stream.write(&mut out, pos, data, |_, _| Ok(()))
+    })?;
+    stream.finish(&mut out, |_, _| Ok(()))?;
+    out.flush()?;
+
+    Ok(ElfExportStats {
+        segments: phnum as u64,
+        bytes_written: stream.bytes_written,
+        file_size: offset,
+    })
+}
+
+/// ELF header of a core with `phnum` program headers following it. With
+/// `PN_XNUM` or more, the count is in the returned section header, to be
+/// written after the program headers.
+fn elf_header(machine: u16, phnum: usize) -> Result<(Elf64Ehdr, Option<Elf64Shdr>)> {
+    let ehsize = mem::size_of::<Elf64Ehdr>();
+    let phentsize = mem::size_of::<Elf64Phdr>();
+    let shentsize = mem::size_of::<Elf64Shdr>();
+    let elf_u16 = |n: usize| u16::try_from(n).map_err(|_| Error::DumpWriteError(
+        format!("ELF header field {} does not fit in 16 bits", n)));
+
+    let shdr = match phnum < PN_XNUM as usize {
+        true => None,
+        false => Some(Elf64Shdr {
+            sh_name: 0,
+            sh_type: 0,
+            sh_flags: 0,
+            sh_addr: 0,
+            sh_offset: 0,
+            sh_size: 0,
+            sh_link: 0,
+            sh_info: u32::try_from(phnum).map_err(|_| Error::DumpWriteError(
+                format!("{} ELF program headers do not fit in 32 bits", phnum)))?,
+            sh_addralign: 0,
+            sh_entsize: 0,
+        }),
+    };
+
+    let mut ident = [0u8; 16];
+    // ELFCLASS64, ELFDATA2LSB, EV_CURRENT.
+    ident[..7].copy_from_slice(b"\x7fELF\x02\x01\x01");
+
+    let ehdr = Elf64Ehdr {
+        e_ident: ident,
+        e_type: ET_CORE,
+        e_machine: machine,
+        e_version: 1,
+        e_entry: 0,
+        e_phoff: ehsize as u64,
+        e_shoff: shdr.map_or(0, |_| (ehsize + phnum * phentsize) as u64),
+        e_flags: 0,
+        e_ehsize: elf_u16(ehsize)?,
+        e_phentsize: elf_u16(phentsize)?,
+        e_phnum: match shdr {
+            Some(_) => PN_XNUM,
+            None => elf_u16(phnum)?,
+        },
+        e_shentsize: shdr.map_or(Ok(0), |_| elf_u16(shentsize))?,
+        e_shnum: shdr.map_or(0, |_| 1),
+        e_shstrndx: 0,
+    };
+
+    Ok((ehdr, shdr))
+}
+
+/// Append an ELF note of the `ELF_NOTE_NAME` owner to `buf`.
+fn push_elf_note(buf: &mut Vec<u8>, note_type: u32, desc: &[u8]) {
+    buf.extend_from_slice(&(ELF_NOTE_NAME.len() as u32).to_le_bytes());
+    buf.extend_from_slice(&(desc.len() as u32).to_le_bytes());
+    buf.extend_from_slice(&note_type.to_le_bytes());
+    buf.extend_from_slice(ELF_NOTE_NAME);
+    buf.resize(buf.len().next_multiple_of(4), 0);
+    buf.extend_from_slice(desc);
+    buf.resize(buf.len().next_multiple_of(4), 0);
+}
+
+/// Write the physical memory runs of the crash dump in the zdmp at `in_path`
+/// as an AFF4 memory image at `out_path`.
+///
+/// The runs are stored back to back in the image stream and placed at their
+/// physical addresses by the map, which also carries the DirectoryTableBase
+/// and the zdmp acquisition metadata. The zdmp is decoded in a single pass.
+pub fn export_aff4(in_path: &Path, out_path: &Path, force: bool) -> Result<Aff4Summary> {
+    let mut reader = ZdmpReader::open(in_path, force)?;
+    let crashdump = CrashDump::from_reader(&mut reader)?;
+
+    if crashdump.runs.is_empty() {
+        return Err(Error::DumpParseError(
+            "Crash dump has no physical memory runs".to_string()));
+    }
+
+    let mut runs = crashdump.runs.clone();
+    runs.sort_by_key(|run| run.file_offset);
+
+    let header_size = if crashdump.is_64bit { DUMP_HEADER64_SIZE } else { DUMP_HEADER32_SIZE };
+    let head = reader.read_head(header_size)?;
+    let mut image = Aff4Writer::create(out_path, &head)?;
+
+    let mut stream_offset = 0;
+    for run in &runs {
+        image.map_range(run.base_address(), stream_offset, run.size());
+        stream_offset += run.size();
+    }
+
+    image.property("aff4:memoryPageTableEntryOffset",
+        aff4::typed_literal(&crashdump.directory_table_base.to_string(), "xsd:long"));
+    image.property("zdmp:machine", aff4::literal(crashdump.machine_name()));
+    image.property("zdmp:osBuild",
+        aff4::typed_literal(&crashdump.minor_version.to_string(), "xsd:int"));
+    for meta in &reader.metadata {
+        match meta {
+            ZdmpMetadata::Hostname(s) => image.property("zdmp:hostname", aff4::literal(s)),
+            ZdmpMetadata::AcquisitionTime(t) =>
+                image.property("zdmp:acquisitionTime", aff4::datetime_literal(*t)),
+            ZdmpMetadata::ToolVersion(s) => image.property("zdmp:acquisitionTool", aff4::literal(s)),
+            ZdmpMetadata::CaseNotes(s) => image.property("zdmp:caseNotes", aff4::literal(s)),
+            ZdmpMetadata::Unknown(..) => (),
+        }
+    }
+
+    let mut stream = RunStream::new(&runs);
+    for_each_block(&mut reader, runs_end(&crashdump), |pos, data| {
+        stream.write(&mut image, pos, data, |_, _| Ok(()))
+    })?;
+    stream.finish(&mut image, |_, _| Ok(()))?;
+
+    image.finish()
+}
+
+/// Write the decoded crash dump of the zdmp at `in_path` as an EWF (E01)
+/// image at `out_path`, in a single pass.
+///
+/// Case fields left empty in `case` are taken from the zdmp metadata: the
+/// case notes, the acquisition time and the hostname as description.
+pub fn export_ewf(in_path: &Path, out_path: &Path, force: bool, case: &EwfCaseInfo)
+-> Result<EwfSummary> {
+    let mut reader = ZdmpReader::open(in_path, force)?;
+
+    let mut case = case.clone();
+    for meta in &reader.metadata {
+        match meta {
+            ZdmpMetadata::Hostname(s) if case.description.is_empty() => case.description = s.clone(),
+            ZdmpMetadata::AcquisitionTime(t) if case.acquisition_time.is_none() =>
+                case.acquisition_time = Some(*t),
+            ZdmpMetadata::CaseNotes(s) if case.notes.is_empty() => case.notes = s.clone(),
+            _ => (),
+        }
+    }
+    if case.os_version.is_empty() {
+        if let Ok(crashdump) = CrashDump::from_reader(&mut reader) {
+            case.os_version = format!("Windows build {} ({})", crashdump.minor_version,
+                crashdump.machine_name());
+        }
+    }
+
+    let media_size = reader.uncompressed_size();
+    let mut image = EwfWriter::create(out_path, media_size, &case)?;
+
+    for_each_block(&mut reader, media_size, |_, data| {
+        match data {
+            DumpData::Bytes(bytes) => image.write_all(bytes)?,
+            DumpData::Zeroes(len) => write_zeroes(&mut image, len)?,
+        }
+        Ok(())
+    })?;
+
+    image.finish()
+}
+
+/// Write the decoded crash dump of the zdmp at `in_path` in the zstd
+/// seekable format at `out_path`, in a single pass.
+pub fn export_dmp_zst(in_path: &Path, out_path: &Path, force: bool) -> Result<SeekableSummary> {
+    let mut reader = ZdmpReader::open(in_path, force)?;
+    let mut out = SeekableWriter::create(out_path, SEEKABLE_DEFAULT_LEVEL)?;
+
+    let dump_size = reader.uncompressed_size();
+    for_each_block(&mut reader, dump_size, |_, data| {
+        match data {
+            DumpData::Bytes(bytes) => out.write_all(bytes)?,
+            DumpData::Zeroes(len) => write_zeroes(&mut out, len)?,
+        }
+        Ok(())
+    })?;
+
+    out.finish()
+}
+
+/// Bytes of the decoded dump passed to the `for_each_block` callback.
+enum DumpData<'a> {
+    Bytes(&'a [u8]),
+    /// A zero run of this many bytes.
+    Zeroes(u64),
+}
+
+impl DumpData<'_> {
+    fn len(&self) -> u64 {
+        match self {
+            DumpData::Bytes(data) => data.len() as u64,
+            DumpData::Zeroes(len) => *len,
+        }
+    }
+}
+
+/// Dump offset of the end of the last run.
+fn runs_end(crashdump: &CrashDump) -> u64 {
+    crashdump.runs.iter()
+        .map(|run| run.file_offset + run.size())
+        .max()
+        .unwrap_or(0)
+}
+
+/// Decode the zdmp in a single pass, calling `f` with the dump offset and
+/// the data of each block. Warns when the dump ends before `dump_end`.
+/// Returns the size of the decoded dump.
+fn for_each_block<R: Read + Seek>(
+    reader: &mut ZdmpReader<R>,
+    dump_end: u64,
+    mut f: impl FnMut(u64, DumpData) -> Result<()>
+) -> Result<u64> {
+    let mut block = Vec::with_capacity(reader.block_size());
+    let mut pos = 0;
+
+    while let Some(rec) = reader.next_record()? {
+        let left = reader.uncompressed_size().saturating_sub(pos);
+        let len = (rec.block_count * reader.block_size() as u64).min(left);
+
+        if rec.flags & BLOCK_FLAG_ZERO_RUN != 0 {
+            f(pos, DumpData::Zeroes(len))?;
+            pos += len;
+            continue;
+        }
+
+        reader.decode_record(&rec, &mut block)?;
+
+        for _ in 0..rec.block_count {
+            let left = reader.uncompressed_size().saturating_sub(pos);
+            let len = block.len().min(left as usize);
+
+            f(pos, DumpData::Bytes(&block[..len]))?;
+            pos += len as u64;
+        }
+    }
+
+    if pos < dump_end {
+        warn!("Dump ends @ 0x{:x}, before the end of the data @ 0x{:x}", pos, dump_end);
+    }
+
+    Ok(pos)
+}
+
+/// Writes the bytes of runs sorted by file offset back to back, from the
+/// blocks of a single pass. `start_run` is called before the first byte of
+/// each run.
+struct RunStream<'a> {
+    runs:           &'a [PhysicalMemoryRun],
+    index:          usize,
+    /// Bytes of `runs[index]` already written.
+    done:           u64,
+    bytes_written:  u64,
+}
+
+impl<'a> RunStream<'a> {
+    fn new(runs: &'a [PhysicalMemoryRun]) -> Self {
+        RunStream { runs, index: 0, done: 0, bytes_written: 0 }
+    }
+
+    fn write<W: Write>(
+        &mut self,
+        out: &mut W,
+        pos: u64,
+        data: DumpData,
+        mut start_run: impl FnMut(&mut W, &PhysicalMemoryRun) -> Result<()>
+    ) -> Result<()> {
+        let end = pos + data.len();
+
+        while let Some(run) = self.runs.get(self.index) {
+            let start = run.file_offset + self.done;
+            if start >= end {
+                break;
+            }
+
+            if self.done == 0 {
+                start_run(out, run)?;
+            }
+
+            // Bytes before `pos` only go missing with overlapping runs.
+            let stop = end.min(run.file_offset + run.size());
+            let from = start.max(pos).min(stop);
+            write_zeroes(out, from - start)?;
+            match data {
+                DumpData::Bytes(data) =>
+                    out.write_all(&data[(from - pos) as usize..(stop - pos) as usize])?,
+                DumpData::Zeroes(_) => write_zeroes(out, stop - from)?,
+            }
+
+            self.done += stop - start;
+            self.bytes_written += stop - start;
+            if self.done < run.size() {
+                break;
+            }
+
+            self.index += 1;
+            self.done = 0;
+        }
+
+        Ok(())
+    }
+
+    /// Pad the runs left with zeroes.
+    fn finish<W: Write>(
+        &mut self,
+        out: &mut W,
+        mut start_run: impl FnMut(&mut W, &PhysicalMemoryRun) -> Result<()>
+    ) -> Result<()> {
+        while let Some(run) = self.runs.get(self.index) {
+            if self.done == 0 {
+                start_run(out, run)?;
+            }
+
+            write_zeroes(out, run.size() - self.done)?;
+            self.bytes_written += run.size() - self.done;
+            self.index += 1;
+            self.done = 0;
+        }
+
+        Ok(())
+    }
+}
+
+fn write_zeroes(out: &mut impl Write, len: u64) -> Result<()> {
+    io::copy(&mut io::repeat(0).take(len), out)?;
+    Ok(())
+}
+
+/// Write the parts of the dump bytes `data` at `pos` that belong to a run at
+/// their physical address. Returns the number of bytes written.
+///
+/// `runs` are sorted by file offset and `pos` only grows between calls;
+/// `run_index` is the first run not yet behind `pos`.
+fn write_runs(
+    out: &mut (impl Write + Seek),
+    runs: &[PhysicalMemoryRun],
+    run_index: &mut usize,
+    pos: u64,
+    data: &[u8]
+) -> Result<u64> {
+    let end = pos + data.len() as u64;
+    let mut written = 0;
+
+    while *run_index < runs.len()
+        && runs[*run_index].file_offset + runs[*run_index].size() <= pos {
+        *run_index += 1;
+    }
+
+    for run in &runs[*run_index..] {
+        if run.file_offset >= end {
+            break;
+        }
+
+        let start = pos.max(run.file_offset);
+        let stop = end.min(run.file_offset + run.size());
+        if start >= stop {
+            continue;
+        }
+
+        out.seek(SeekFrom::Start(run.base_address() + start - run.file_offset))?;
+        out.write_all(&data[(start - pos) as usize..(stop - pos) as usize])?;
+        written += stop - start;
+    }
+
+    Ok(written)
+}
+
+/// Writes the non-zero pages of runs sorted by file offset, from the blocks
+/// of a single pass, and sets their bits in the page bitmap. Pages split
+/// across blocks are buffered.
+struct BitmapPages<'a, W> {
+    out:            W,
+    runs:           &'a [PhysicalMemoryRun],
+    run_index:      usize,
+    bitmap:         &'a mut [u8],
+    present_pages:  u64,
+    /// The page holding the dump offset of the next block, filled up to it.
+    page:           Vec<u8>,
+}
+
+impl<W: Write> BitmapPages<'_, W> {
+    fn write(&mut self, start: u64, data: DumpData) -> Result<()> {
+        let page_size = PAGE_SIZE as u64;
+        let end = start + data.len();
+        let mut pos = start;
+
+        while pos < end {
+            let in_page = (pos % page_size) as usize;
+            let len = (PAGE_SIZE - in_page).min((end - pos) as usize);
+
+            match data {
+                // Whole zero pages are left out.
+                DumpData::Zeroes(_) if len == PAGE_SIZE => {
+                    pos += (end - pos) / page_size * page_size;
+                    continue;
+                },
+                DumpData::Zeroes(_) => self.page[in_page..in_page + len].fill(0),
+                DumpData::Bytes(bytes) => {
+                    let bytes = &bytes[(pos - start) as usize..][..len];
+                    if len == PAGE_SIZE {
+                        self.write_page(pos, bytes)?;
+                    } else {
+                        self.page[in_page..in_page + len].copy_from_slice(bytes);
+                    }
+                },
+            }
+
+            pos += len as u64;
+            // A buffered page is complete.
+            if len < PAGE_SIZE && in_page + len == PAGE_SIZE {
+                let page = mem::take(&mut self.page);
+                self.write_page(pos - page_size, &page)?;
+                self.page = page;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Write the page left partly filled at the end of the dump `end`,
+    /// padded with zeroes.
+    fn finish(&mut self, end: u64) -> Result<()> {
+        let in_page = (end % PAGE_SIZE as u64) as usize;
+        if in_page == 0 {
+            return Ok(());
+        }
+
+        let mut page = mem::take(&mut self.page);
+        page[in_page..].fill(0);
+        self.write_page(end - in_page as u64, &page)
+    }
+
+    /// Write the page at the dump offset `offset` if it belongs to a run and
+    /// is not zero.
+    fn write_page(&mut self, offset: u64, data: &[u8]) -> Result<()> {
+        let runs = self.runs;
+        while self.run_index < runs.len()
+            && runs[self.run_index].file_offset + runs[self.run_index].size() <= offset {
+            self.run_index += 1;
+        }
+        let run = match runs.get(self.run_index) {
+            Some(run) if run.file_offset <= offset => run,
+            _ => return Ok(()),
+        };
+
+        if data.iter().all(|&b| b == 0) {
+            return Ok(());
+        }
+
+        self.out.write_all(data)?;
+
+        let pfn = run.base_page + (offset - run.file_offset) / PAGE_SIZE as u64;
+        self.bitmap[(pfn / 8) as usize] |= 1 << (pfn % 8);
+        self.present_pages += 1;
+        Ok(())
+    }
+}
+
+/// Counts of a bitmap dump export.
+#[derive(Debug, Default, Copy, Clone)]
+pub struct BitmapExportStats {
+    /// Pages covered by the bitmap.
+    pub pages:          u64,
+    pub present_pages:  u64,
+    /// Pages of the runs left out, being zero or missing from the zdmp.
+    pub zero_pages:     u64,
+    pub file_size:      u64,
+}
+
+/// Write the crash dump in the zdmp at `in_path` as a full bitmap ("FDMP")
+/// crash dump at `out_path`, keeping only the non-zero pages.
+///
+/// The zdmp is decoded in a single pass; the header and bitmap are written
+/// last. Data between the header and the first run is not kept.
+pub fn export_bitmap_dmp(in_path: &Path, out_path: &Path, force: bool) -> Result<BitmapExportStats> {
+    let mut reader = ZdmpReader::open(in_path, force)?;
+    let crashdump = CrashDump::from_reader(&mut reader)?;
+
+    if !crashdump.is_64bit {
+        return Err(Error::DumpParseError(
+            "Bitmap dumps can only be written for 64-bit crash dumps".to_string()));
+    }
+    if crashdump.runs.is_empty() {
+        return Err(Error::DumpParseError(
+            "Crash dump has no physical memory runs".to_string()));
+    }
+    // Pages follow the bitmap order, so the runs must too.
+    if crashdump.runs.windows(2).any(|w| w[0].base_page + w[0].page_count > w[1].base_page
+        || w[0].file_offset > w[1].file_offset) {
+        return Err(Error::DumpParseError(
+            "Physical memory runs are not in physical address order".to_string()));
+    }
+
+    let head = reader.read_head(DUMP_HEADER64_SIZE)?;
+    let mut hdr = read_type!(&mut head.as_slice(), DumpHeader64)?;
+
+    let mut stats = BitmapExportStats {
+        pages: crashdump.runs.iter().map(|run| run.base_page + run.page_count).max().unwrap_or(0),
+        ..Default::default()
+    };
+    let mut bitmap = vec![0u8; stats.pages.div_ceil(8) as usize];
+    let page_size = PAGE_SIZE as u64;
+    let first_page = (DUMP_HEADER64_SIZE + BITMAP_DUMP_HEADER_SIZE) as u64 + bitmap.len() as u64;
+    let first_page = first_page.div_ceil(page_size) * page_size;
+
+    let mut out_file = File::create(out_path)?;
+    out_file.seek(SeekFrom::Start(first_page))?;
+    let mut out = BufWriter::new(out_file);
+
+    let mut pages = BitmapPages {
+        out: &mut out,
+        runs: &crashdump.runs,
+        run_index: 0,
+        bitmap: &mut bitmap,
+        present_pages: 0,
+        page: vec![0u8; PAGE_SIZE],
+    };
+
+    let end = for_each_block(&mut reader, runs_end(&crashdump), |pos, data| pages.write(pos, data))?;
+    pages.finish(end)?;
+    stats.present_pages = pages.present_pages;
+
+    stats.zero_pages = crashdump.page_count() - stats.present_pages;
+    stats.file_size = first_page + stats.present_pages * page_size;
+    hdr.dump_type = DUMP_TYPE_BITMAP_FULL;
+    hdr.required_dump_space = stats.file_size;
+
+    let bitmap_hdr = BitmapDumpHeader {
+        signature: DUMP_FULL_BITMAP_SIGNATURE,
+        valid_dump: DUMP_VALID_DUMP,
+        reserved: [0u8; 0x18],
+        first_page,
+        total_present_pages: stats.present_pages,
+        pages: stats.pages,
+    };
+
+    out.seek(SeekFrom::Start(0))?;
+    out.write_all(as_bytes(&hdr))?;
+    out.write_all(as_bytes(&bitmap_hdr))?;
+    out.write_all(&bitmap)?;
+    out.flush()?;
+
+    Ok(stats)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::PathBuf;
+
+    use super::*;
+    use crate::aff4::{Aff4Image, Aff4Volume, ZDMP_NS};
+    use crate::crashdump::{DUMP_SIGNATURE, DUMP_VALID_DUMP64, DUMP_TYPE_FULL};
+    use crate::physmem::PhysicalMemory;
+    use crate::writer::ZdmpWriter;
+
+    /// Deterministic xorshift bytes.
+    fn noise(seed: u64, len: usize) -> Vec<u8> {
+        let mut x = 2 * seed + 1;
+        (0..len).map(|_| {
+            x ^= x << 13;
+            x ^= x >> 7;
+            x ^= x << 17;
+            x as u8
+        }).collect()
+    }
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("z2dmp-{}-{}", std::process::id(), name))
+    }
+
+    /// x64 full dump of build 19041 with `runs` (base page, page count),
+    /// followed by their pages.
+    fn full_dump(runs: &[(u64, u64)]) -> Vec<u8> {
+        let mut dump = vec![0u8; DUMP_HEADER64_SIZE];
+        dump[0..4].copy_from_slice(&DUMP_SIGNATURE.to_le_bytes());
+        dump[4..8].copy_from_slice(&DUMP_VALID_DUMP64.to_le_bytes());
+        dump[0xc..0x10].copy_from_slice(&19041u32.to_le_bytes());
+        dump[0x10..0x18].copy_from_slice(&0x1aa000u64.to_le_bytes());
+        dump[0x30..0x34].copy_from_slice(&IMAGE_FILE_MACHINE_AMD64.to_le_bytes());
+        dump[0xf98..0xf9c].copy_from_slice(&DUMP_TYPE_FULL.to_le_bytes());
+
+        // _PHYSICAL_MEMORY_DESCRIPTOR64
+        let pages: u64 = runs.iter().map(|&(_, count)| count).sum();
+        dump[0x88..0x8c].copy_from_slice(&(runs.len() as u32).to_le_bytes());
+        dump[0x90..0x98].copy_from_slice(&pages.to_le_bytes());
+        for (i, &(base_page, page_count)) in runs.iter().enumerate() {
+            let desc = 0x98 + i * 0x10;
+            dump[desc..desc + 8].copy_from_slice(&base_page.to_le_bytes());
+            dump[desc + 8..desc + 0x10].copy_from_slice(&page_count.to_le_bytes());
+        }
+
+        dump.extend_from_slice(&noise(7, pages as usize * PAGE_SIZE));
+        dump
+    }
+
+    /// Output and bitmap of `BitmapPages` fed `dump` in blocks of
+    /// `block_size`, with the all-zero blocks passed as zero runs.
+    fn bitmap_pages(runs: &[PhysicalMemoryRun], dump: &[u8], block_size: usize) -> (Vec<u8>, Vec<u8>, u64) {
+        let mut out = Vec::new();
+        let mut bitmap = vec![0u8; 8];
+        let mut pages = BitmapPages {
+            out: &mut out,
+            runs,
+            run_index: 0,
+            bitmap: &mut bitmap,
+            present_pages: 0,
+            page: vec![0u8; PAGE_SIZE],
+        };
+
+        for (i, block) in dump.chunks(block_size).enumerate() {
+            let pos = (i * block_size) as u64;
+            let data = match block.iter().all(|&b| b == 0) {
+                true => DumpData::Zeroes(block.len() as u64),
+                false => DumpData::Bytes(block),
+            };
+            pages.write(pos, data).unwrap();
+        }
+        pages.finish(dump.len() as u64).unwrap();
+
+        let present_pages = pages.present_pages;
+        (out, bitmap, present_pages)
+    }
+
+    #[test]
+    fn elf_header_segment_counts() {
+        let (ehdr, shdr) = elf_header(EM_X86_64, 3).unwrap();
+        assert_eq!({ ehdr.e_phnum }, 3);
+        assert_eq!({ ehdr.e_shnum }, 0);
+        assert!(shdr.is_none());
+
+        let (ehdr, shdr) = elf_header(EM_X86_64, 70_000).unwrap();
+        assert_eq!({ ehdr.e_phnum }, PN_XNUM);
+        assert_eq!({ ehdr.e_shnum }, 1);
+        assert_eq!({ ehdr.e_shentsize } as usize, mem::size_of::<Elf64Shdr>());
+        assert_eq!({ ehdr.e_shoff }, 64 + 70_000 * 56);
+        assert_eq!({ shdr.unwrap().sh_info }, 70_000);
+    }
+
+    #[test]
+    fn bitmap_pages_split_across_blocks() {
+        let runs = [
+            PhysicalMemoryRun { base_page: 1, page_count: 12, file_offset: 0x2000 },
+            PhysicalMemoryRun { base_page: 0x20, page_count: 9, file_offset: 0xe000 },
+        ];
+        let mut dump = vec![0u8; 0x16800];
+        for page in [2, 3, 6, 10, 14, 17, 21, 22] {
+            dump[page * PAGE_SIZE + 0x17] = page as u8;
+            dump[page * PAGE_SIZE + 0x7f0] = 0xcc;
+        }
+
+        let (out, bitmap, present_pages) = bitmap_pages(&runs, &dump, 0x10000);
+        assert_eq!(present_pages, 8);
+        assert_eq!(out.len(), 8 * PAGE_SIZE);
+        assert_eq!(bitmap[0], 0b0010_0110);
+        assert_eq!(bitmap[1], 0b0000_0010);
+        assert_eq!(bitmap[4], 0b1000_1001);
+        assert_eq!(bitmap[5], 0b0000_0001);
+
+        for block_size in [0x1800, 0x200, 0x3000] {
+            assert_eq!(bitmap_pages(&runs, &dump, block_size), (out.clone(), bitmap.clone(), present_pages));
+        }
+    }
+
+    #[test]
+    fn round_trip_aff4() {
+        // Runs out of address order in the dump.
+        let runs = [(0x100, 3), (0x1, 2), (0x40, 1)];
+        let dump = full_dump(&runs);
+        let metadata = vec![
+            ZdmpMetadata::Hostname("lab-01".to_string()),
+            ZdmpMetadata::AcquisitionTime(1_700_000_000),
+            ZdmpMetadata::CaseNotes("case \"7\"".to_string()),
+        ];
+
+        let in_path = temp_path("export.zdmp");
+        let out_path = temp_path("export.aff4");
+        let mut writer = ZdmpWriter::create(&in_path, 0x4000, &metadata).unwrap();
+        for block in dump.chunks(0x4000) {
+            writer.write_block(block).unwrap();
+        }
+        writer.finish().unwrap();
+
+        let summary = export_aff4(&in_path, &out_path, false).unwrap();
+        assert_eq!(summary.stream_size, 6 * PAGE_SIZE as u64);
+
+        // Sorted by address, pointing at the runs in dump order.
+        let mut image = Aff4Image::open(&out_path).unwrap();
+        let ranges: Vec<_> = image.ranges.iter()
+            .map(|r| (r.map_offset, r.length, r.target_offset))
+            .collect();
+        assert_eq!(ranges, [(0x1000, 0x2000, 0x3000), (0x40000, 0x1000, 0x5000), (0x100000, 0x3000, 0)]);
+
+        let mut file_offset = DUMP_HEADER64_SIZE;
+        for &(base_page, page_count) in &runs {
+            let len = page_count as usize * PAGE_SIZE;
+            let mut buf = vec![0u8; len];
+            image.read_phys(base_page * PAGE_SIZE as u64, &mut buf).unwrap();
+            assert!(buf[..] == dump[file_offset..file_offset + len]);
+            file_offset += len;
+        }
+        assert_eq!(image.verify().unwrap(), [("MD5".to_string(), true), ("SHA1".to_string(), true)]);
+
+        let volume = Aff4Volume::open(&out_path).unwrap();
+        let property = |name: &str| volume.value(&summary.map_urn, &format!("{}{}", ZDMP_NS, name));
+        assert_eq!(property("machine"), Some("x64"));
+        assert_eq!(property("osBuild"), Some("19041"));
+        assert_eq!(property("hostname"), Some("lab-01"));
+        assert_eq!(property("acquisitionTime"), Some("2023-11-14T22:13:20Z"));
+        assert_eq!(property("caseNotes"), Some("case \"7\""));
+        assert_eq!(volume.value(&summary.map_urn, &format!("{}memoryPageTableEntryOffset", aff4::AFF4_NS)),
+            Some("1744896"));
+
+        fs::remove_file(&in_path).unwrap();
+        fs::remove_file(&out_path).unwrap();
+    }
+}
@@ -1,4 +1,4 @@
-fn bytes_to_chars(bytes: &Vec<u8>) -> String
+fn bytes_to_chars(bytes: &[u8]) -> String
 {
     let mut s = String::new();
 
@@ -6,14 +6,14 @@ fn bytes_to_chars(bytes: &Vec<u8>) -> String
         if *byte >= 32 && *byte <= 126 {
             s += &*format!("{}", *byte as char);
         } else {
-            s += &*format!(".");
+            s += ".";
         }
     }
 
     s
 }
 
-pub fn hexdump(addr: u64, bytes: &Vec<u8>) -> String
+pub fn hexdump(addr: u64, bytes: &[u8]) -> String
 {
     let mut s = String::new();
     let mut line = Vec::new();
@@ -0,0 +1,179 @@
+use std::fmt;
+use std::fs::File;
+use std::io::{Read, Seek};
+use std::path::Path;
+
+use crate::crashdump::{CrashDump, DUMP_HEADER64_SIZE};
+use crate::reader::ZdmpReader;
+use crate::result::{Result, Error};
+use crate::zdmp::{ZdmpFileHdr, ZdmpFileHdrV2, ZdmpVersion, ZdmpMetadata, ZDMP_BLOCK_SIGNATURE,
+    ZDMP_BLOCK_START_OFFSET};
+
+/// Header-level summary of a zdmp file.
+///
+/// Only the file header, the block(s) holding the crash dump header (and
+/// page bitmap) and, for 1.0 files, the first and last records are read, so
+/// this is cheap even for very large files.
+#[derive(Debug)]
+pub struct ZdmpInfo {
+    pub hdr:                ZdmpFileHdr,
+    pub version:            Option<ZdmpVersion>,
+    pub hdr_v2:             Option<ZdmpFileHdrV2>,
+    pub metadata:           Vec<ZdmpMetadata>,
+    pub file_len:           u64,
+    pub expected_blocks:    u64,
+    pub truncated:          bool,
+    pub dump_signature:     Option<[u8; 8]>,
+    pub crashdump:          Option<CrashDump>,
+}
+
+impl ZdmpInfo {
+    /// Unknown versions are still reported, but their blocks are only
+    /// sampled when `force` is set.
+    pub fn new(path: &Path, force: bool) -> Result<Self> {
+        let mut file = File::open(path)?;
+        let file_len = file.metadata()?.len();
+
+        let hdr = ZdmpFileHdr::new(&mut file)?;
+        let expected_blocks = hdr.expected_block_count();
+
+        let mut reader = match ZdmpReader::new(file, force) {
+            Ok(reader) => reader,
+            Err(Error::UnsupportedVersion(_)) => {
+                return Ok(ZdmpInfo {
+                    hdr, version: None, hdr_v2: None, metadata: Vec::new(), file_len,
+                    expected_blocks, truncated: false, dump_signature: None, crashdump: None,
+                });
+            },
+            Err(e) => return Err(e),
+        };
+
+        let truncated = match reader.version {
+            // The 2.0 index is written last, so a missing one means the
+            // writer never finished.
+            ZdmpVersion::V20 => !reader.has_index(),
+            ZdmpVersion::V10 => v10_truncated(&mut reader, expected_blocks)?,
+        };
+
+        // Only the first block(s) are decoded, for the dump header.
+        let head_len = (DUMP_HEADER64_SIZE as u64).min(reader.uncompressed_size()) as usize;
+        let head = reader.read_head(head_len).ok();
+
+        let dump_signature = head.as_ref()
+            .and_then(|head| head.get(..8))
+            .map(|sig| {
+                let mut signature = [0u8; 8];
+                signature.copy_from_slice(sig);
+                signature
+            });
+
+        let crashdump = match head {
+            Some(_) => CrashDump::from_reader(&mut reader).ok(),
+            None => None,
+        };
+
+        Ok(ZdmpInfo {
+            hdr,
+            version: Some(reader.version),
+            hdr_v2: reader.hdr_v2,
+            metadata: reader.metadata,
+            file_len, expected_blocks, truncated, dump_signature, crashdump,
+        })
+    }
+
+    /// Name of the dump embedded in the zdmp, based on the first block.
+    pub fn dump_kind(&self) -> &'static str {
+        match self.dump_signature.as_ref() {
+            Some(b"PAGEDU64") => "Windows crash dump (64-bit)",
+            Some(b"PAGEDUMP") => "Windows crash dump (32-bit)",
+            Some(_) => "unknown",
+            None => "unreadable",
+        }
+    }
+}
+
+/// Header-only truncation check of a 1.0 file: every block needs at least
+/// its record header, the first record must fit, and the file must end with
+/// a whole record, which starts within the last block.
+fn v10_truncated<R: Read + Seek>(reader: &mut ZdmpReader<R>, expected_blocks: u64) -> Result<bool> {
+    let hdr_size = reader.record_hdr_size();
+    let block_size = reader.hdr.block_size as u64;
+    let file_len = reader.file_len;
+
+    if expected_blocks == 0 {
+        return Ok(false);
+    }
+    if file_len < ZDMP_BLOCK_START_OFFSET.saturating_add(expected_blocks.saturating_mul(hdr_size)) {
+        return Ok(true);
+    }
+
+    match reader.read_record(ZDMP_BLOCK_START_OFFSET, 0) {
+        Ok(first) if first.next_offset() > file_len => return Ok(true),
+        Ok(_) => (),
+        // Other corruption is reported by `lint`.
+        Err(Error::DumpParseError(_)) => return Ok(false),
+        Err(e) => return Err(e),
+    }
+
+    let tail_len = (hdr_size + block_size).min(file_len - ZDMP_BLOCK_START_OFFSET);
+    let tail = reader.read_raw(file_len - tail_len, tail_len as usize)?;
+    let hdr_size = hdr_size as usize;
+
+    let ends_with_record = (0..tail.len().saturating_sub(hdr_size - 1)).any(|pos| {
+        let u32_at = |off: usize| u32::from_le_bytes([tail[off], tail[off + 1], tail[off + 2], tail[off + 3]]);
+        let data_size = u32_at(pos + 4) as u64;
+
+        u32_at(pos) == ZDMP_BLOCK_SIGNATURE
+            && data_size <= block_size
+            && (pos + hdr_size) as u64 + data_size == tail_len
+    });
+
+    Ok(!ends_with_record)
+}
+
+impl fmt::Display for ZdmpInfo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let hdr = &self.hdr;
+
+        writeln!(f, "Signature:          0x{:08x} (ZDMP)", { hdr.signature })?;
+        match self.version {
+            Some(layout) if hdr.version_name() == "unknown" =>
+                writeln!(f, "Version:            0x{:04x} (unknown, parsed as {})",
+                    { hdr.version }, layout.name())?,
+            Some(_) => writeln!(f, "Version:            0x{:04x} ({})",
+                { hdr.version }, hdr.version_name())?,
+            None => writeln!(f, "Version:            0x{:04x} (unsupported, use --force)",
+                { hdr.version })?,
+        }
+        writeln!(f, "Block size:         0x{:x}", { hdr.block_size })?;
+        writeln!(f, "Data type:          {} ({})", { hdr.data_type }, hdr.data_type_name())?;
+        writeln!(f, "Codec:              {} ({})", { hdr.compression_format }, hdr.codec_name())?;
+        writeln!(f, "Declared file size: 0x{:x}", { hdr.file_size })?;
+        writeln!(f, "Expected blocks:    {}", self.expected_blocks)?;
+        if let Some(hdr_v2) = &self.hdr_v2 {
+            writeln!(f, "Block records:      {}", { hdr_v2.record_count })?;
+            writeln!(f, "Index offset:       0x{:x}", { hdr_v2.index_offset })?;
+        }
+        for meta in &self.metadata {
+            writeln!(f, "Metadata:           {}", meta)?;
+        }
+        writeln!(f, "Actual file length: 0x{:x}", self.file_len)?;
+        writeln!(f, "Looks truncated:    {}", match (self.truncated, self.version) {
+            (true, _) => "yes",
+            (false, Some(ZdmpVersion::V20)) => "no",
+            (false, _) => "no (header-only estimate)",
+        })?;
+
+        match &self.dump_signature {
+            Some(sig) => write!(f, "Embedded dump:      {} ({})",
+                self.dump_kind(), sig.escape_ascii())?,
+            None => write!(f, "Embedded dump:      {}", self.dump_kind())?,
+        }
+
+        if let Some(crashdump) = &self.crashdump {
+            write!(f, "\n\n{}", crashdump)?;
+        }
+
+        Ok(())
+    }
+}
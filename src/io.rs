@@ -16,7 +16,7 @@ pub struct File {
 type IoResult<T> = std::result::Result<T, std::io::Error>;
 
 impl File {
-    pub fn create<'a>(path: &'a Path) -> Result<File> {
+    pub fn create(path: &Path) -> Result<File> {
         let file = std::fs::File::create(path)
             .map_err(|e| Error::IoError(format!(
             "Failed to create `{}`: {}", path.display(), e)))?;
@@ -24,7 +24,7 @@ impl File {
         Ok(File { file, path: path.display().to_string() })
     }
 
-    pub fn open<'a>(path: &'a Path) -> Result<File> {
+    pub fn open(path: &Path) -> Result<File> {
         let file = std::fs::File::open(path)
             .map_err(|e| Error::IoError(format!(
             "Failed to open `{}`: {}", path.display(), e)))?;
@@ -47,7 +47,7 @@ impl Seek for File {
                     "Failed to seek `{}` {} from current: {}", self.path, x, e),
             };
 
-            std::io::Error::new(std::io::ErrorKind::Other, s)
+            std::io::Error::other(s)
         })
     }
 }
@@ -55,8 +55,7 @@ impl Seek for File {
 impl Read for File {
     fn read(&mut self, buf: &mut [u8]) -> IoResult<usize> {
         self.file.read(buf).map_err(|e|
-            std::io::Error::new(
-                std::io::ErrorKind::Other,
+            std::io::Error::other(
                 format!("Failed to read `{}`: {}", self.path, e)))
     }
 }
@@ -64,20 +63,18 @@ impl Read for File {
 impl Write for File {
     fn write(&mut self, buf: &[u8]) -> IoResult<usize> {
         self.file.write(buf).map_err(|e|
-            std::io::Error::new(
-                std::io::ErrorKind::Other,
+            std::io::Error::other(
                 format!("Failed to write `{}`: {}", self.path, e)))
     }
 
     fn flush(&mut self) -> IoResult<()> {
         self.file.flush().map_err(|e|
-            std::io::Error::new(
-                std::io::ErrorKind::Other,
+            std::io::Error::other(
                 format!("Failed to flush `{}`: {}", self.path, e)))
     }
 }
 
-pub fn create_dir_all<'a>(dir: &'a Path) -> Result<()> {
+pub fn create_dir_all(dir: &Path) -> Result<()> {
     fs::create_dir_all(dir).map_err(|e|
         Error::IoError(format!(
             "Failed to create directory: `{}`: {}", dir.display(), e)))
@@ -0,0 +1,83 @@
+use std::fmt::Write;
+
+/// Minimal JSON object writer for command output.
+#[derive(Debug, Default)]
+pub struct JsonObject {
+    buf:    String,
+}
+
+impl JsonObject {
+    pub fn new() -> Self {
+        JsonObject::default()
+    }
+
+    fn key(&mut self, key: &str) {
+        self.buf.push(if self.buf.is_empty() { '{' } else { ',' });
+        self.buf.push_str(&escape(key));
+        self.buf.push(':');
+    }
+
+    pub fn str(mut self, key: &str, value: &str) -> Self {
+        self.key(key);
+        self.buf.push_str(&escape(value));
+        self
+    }
+
+    pub fn num(mut self, key: &str, value: u64) -> Self {
+        self.key(key);
+        let _ = write!(self.buf, "{}", value);
+        self
+    }
+
+    /// Number written as a "0x" string, for addresses.
+    pub fn hex(self, key: &str, value: u64) -> Self {
+        self.str(key, &format!("0x{:x}", value))
+    }
+
+    pub fn bool(mut self, key: &str, value: bool) -> Self {
+        self.key(key);
+        self.buf.push_str(if value { "true" } else { "false" });
+        self
+    }
+
+    /// Value that is already JSON, or `null`.
+    pub fn raw(mut self, key: &str, json: Option<&str>) -> Self {
+        self.key(key);
+        self.buf.push_str(json.unwrap_or("null"));
+        self
+    }
+
+    pub fn finish(mut self) -> String {
+        if self.buf.is_empty() {
+            self.buf.push('{');
+        }
+        self.buf.push('}');
+        self.buf
+    }
+}
+
+/// JSON array of already encoded values.
+pub fn array<I: IntoIterator<Item = String>>(items: I) -> String {
+    format!("[{}]", items.into_iter().collect::<Vec<_>>().join(","))
+}
+
+/// Quoted and escaped JSON string.
+pub fn escape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => { let _ = write!(out, "\\u{:04x}", c as u32); },
+            c => out.push(c),
+        }
+    }
+
+    out.push('"');
+    out
+}
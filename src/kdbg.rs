@@ -0,0 +1,460 @@
+use std::fmt;
+use std::io::Read;
+use std::mem;
+
+use crate::crashdump::CrashDump;
+use crate::paging::{AddressSpace, PagingMode};
+use crate::physmem::PhysicalMemory;
+use crate::result::{Result, Error};
+use crate::zdmp::PAGE_SIZE;
+
+pub const KDBG_OWNER_TAG:       u32 = 0x4742_444b;  // GBDK
+
+/// Largest `Size` accepted for a KDBG.
+pub const KDBG_MAX_SIZE:        u32 = 0x1000;
+
+/// Distance searched after `cmp byte ptr [KdpDataBlockEncoded], 0` for the
+/// loads of KiWaitNever and KiWaitAlways.
+const KD_COPY_DATA_BLOCK_WINDOW: usize = 0x100;
+
+/// `KDDEBUGGER_DATA64`, up to `KeLoaderBlock`.
+///
+/// Pointers are 64-bit on every architecture, sign-extended on x86. Newer
+/// fields are not decoded.
+#[repr(C, packed)]
+#[derive(Debug, Copy, Clone)]
+pub struct KdDebuggerData64 {
+    pub list_flink:                         u64,
+    pub list_blink:                         u64,
+    pub owner_tag:                          u32,
+    pub size:                               u32,
+    pub kern_base:                          u64,
+    pub breakpoint_with_status:             u64,
+    pub saved_context:                      u64,
+    pub th_callback_stack:                  u16,
+    pub next_callback:                      u16,
+    pub frame_pointer:                      u16,
+    pub pae_enabled:                        u16,
+    pub ki_call_user_mode:                  u64,
+    pub ke_user_callback_dispatcher:        u64,
+    pub ps_loaded_module_list:              u64,
+    pub ps_active_process_head:             u64,
+    pub psp_cid_table:                      u64,
+    pub exp_system_resources_list:          u64,
+    pub exp_paged_pool_descriptor:          u64,
+    pub exp_number_of_paged_pools:          u64,
+    pub ke_time_increment:                  u64,
+    pub ke_bug_check_callback_list_head:    u64,
+    pub ki_bugcheck_data:                   u64,
+    pub iop_error_log_list_head:            u64,
+    pub obp_root_directory_object:          u64,
+    pub obp_type_object_type:               u64,
+    pub mm_system_cache_start:              u64,
+    pub mm_system_cache_end:                u64,
+    pub mm_system_cache_ws:                 u64,
+    pub mm_pfn_database:                    u64,
+    pub mm_system_ptes_start:               u64,
+    pub mm_system_ptes_end:                 u64,
+    pub mm_subsection_base:                 u64,
+    pub mm_number_of_paging_files:          u64,
+    pub mm_lowest_physical_page:            u64,
+    pub mm_highest_physical_page:           u64,
+    pub mm_number_of_physical_pages:        u64,
+    pub mm_maximum_non_paged_pool_in_bytes: u64,
+    pub mm_non_paged_system_start:          u64,
+    pub mm_non_paged_pool_start:            u64,
+    pub mm_non_paged_pool_end:              u64,
+    pub mm_paged_pool_start:                u64,
+    pub mm_paged_pool_end:                  u64,
+    pub mm_paged_pool_information:          u64,
+    pub mm_page_size:                       u64,
+    pub mm_size_of_paged_pool_in_bytes:     u64,
+    pub mm_total_commit_limit:              u64,
+    pub mm_total_committed_pages:           u64,
+    pub mm_shared_commit:                   u64,
+    pub mm_driver_commit:                   u64,
+    pub mm_process_commit:                  u64,
+    pub mm_paged_pool_commit:               u64,
+    pub mm_extended_commit:                 u64,
+    pub mm_zeroed_page_list_head:           u64,
+    pub mm_free_page_list_head:             u64,
+    pub mm_standby_page_list_head:          u64,
+    pub mm_modified_page_list_head:         u64,
+    pub mm_modified_no_write_page_list_head: u64,
+    pub mm_available_pages:                 u64,
+    pub mm_resident_available_pages:        u64,
+    pub pool_track_table:                   u64,
+    pub non_paged_pool_descriptor:          u64,
+    pub mm_highest_user_address:            u64,
+    pub mm_system_range_start:              u64,
+    pub mm_user_probe_address:              u64,
+    pub kd_print_circular_buffer:           u64,
+    pub kd_print_circular_buffer_end:       u64,
+    pub kd_print_write_pointer:             u64,
+    pub kd_print_rollover_count:            u64,
+    pub mm_loaded_user_image_list:          u64,
+    pub nt_build_lab:                       u64,
+    pub ki_normal_system_call:              u64,
+    pub ki_processor_block:                 u64,
+    pub mm_unloaded_drivers:                u64,
+    pub mm_last_unloaded_driver:            u64,
+    pub mm_triage_action_taken:             u64,
+    pub mm_special_pool_tag:                u64,
+    pub kernel_verifier:                    u64,
+    pub mm_verifier_data:                   u64,
+    pub mm_allocated_non_paged_pool:        u64,
+    pub mm_peak_commitment:                 u64,
+    pub mm_total_commit_limit_maximum:      u64,
+    pub cm_nt_csd_version:                  u64,
+    pub mm_physical_memory_block:           u64,
+    pub mm_session_base:                    u64,
+    pub mm_session_size:                    u64,
+    pub mm_system_parent_table_page:        u64,
+    pub mm_virtual_translation_base:        u64,
+    pub offsets:                            [u16; 21],
+    pub l1tf_high_physical_bit_index:       u8,
+    pub l1tf_swizzle_bit_index:             u8,
+    pub padding0:                           u32,
+    pub kd_print_circular_buffer_ptr:       u64,
+    pub kd_print_buffer_size:               u64,
+    pub ke_loader_block:                    u64,
+}
+
+/// Names of `KdDebuggerData64::offsets`.
+pub const KDBG_OFFSET_NAMES: [&str; 21] = [
+    "OffsetKThreadNextProcessor",
+    "OffsetKThreadTeb",
+    "OffsetKThreadKernelStack",
+    "OffsetKThreadInitialStack",
+    "OffsetKThreadApcProcess",
+    "OffsetKThreadState",
+    "OffsetKThreadBStore",
+    "OffsetKThreadBStoreLimit",
+    "SizeEProcess",
+    "OffsetEprocessPeb",
+    "OffsetEprocessParentCID",
+    "OffsetEprocessDirectoryTableBase",
+    "SizePrcb",
+    "OffsetPrcbDpcRoutine",
+    "OffsetPrcbCurrentThread",
+    "OffsetPrcbMhz",
+    "OffsetPrcbCpuType",
+    "OffsetPrcbVendorString",
+    "OffsetPrcbProcStateContext",
+    "OffsetPrcbNumber",
+    "SizeEThread",
+];
+
+/// Keys of a KDBG encoded by `KdCopyDataBlock` (Windows 8 and later x64).
+#[derive(Debug, Copy, Clone)]
+pub struct KdbgKeys {
+    pub wait_never:     u64,
+    pub wait_always:    u64,
+    /// Address of `KdpDataBlockEncoded`.
+    pub block_encoded:  u64,
+}
+
+impl KdbgKeys {
+    pub fn decode(&self, entry: u64) -> u64 {
+        let entry = (entry ^ self.wait_never).rotate_left((self.wait_never & 0xff) as u32);
+        (entry ^ self.block_encoded).swap_bytes() ^ self.wait_always
+    }
+
+    fn decode_block(&self, buf: &mut [u8]) {
+        for chunk in buf.chunks_exact_mut(8) {
+            let mut entry = [0u8; 8];
+            entry.copy_from_slice(chunk);
+            chunk.copy_from_slice(&self.decode(u64::from_le_bytes(entry)).to_le_bytes());
+        }
+    }
+}
+
+/// How the KDBG was found.
+#[derive(Debug, Copy, Clone)]
+pub enum KdbgSource {
+    /// Plain block at the header `KdDebuggerDataBlock`.
+    Header,
+    /// Encoded block at the header `KdDebuggerDataBlock`.
+    Decoded(KdbgKeys),
+    /// Plain block found by scanning physical memory, at this address.
+    Scanned(u64),
+}
+
+/// Kernel debugger data block.
+#[derive(Debug, Clone)]
+pub struct Kdbg {
+    pub source: KdbgSource,
+    pub data:   KdDebuggerData64,
+}
+
+impl Kdbg {
+    /// Find the KDBG of the dumped kernel.
+    ///
+    /// The block at the header `KdDebuggerDataBlock` is used when it is
+    /// plain or can be decoded, physical memory is scanned for the "KDBG"
+    /// owner tag otherwise.
+    pub fn locate<M: PhysicalMemory>(
+        space: &mut AddressSpace<M>,
+        crashdump: &CrashDump
+    ) -> Result<Self> {
+        let va = crashdump.kd_debugger_data_block;
+
+        if let Ok(kdbg) = Kdbg::from_va(space, crashdump, va) {
+            return Ok(kdbg);
+        }
+
+        warn!("KdDebuggerDataBlock @ 0x{:x} is unusable, scanning for the KDBG tag", va);
+        Kdbg::scan(space)
+    }
+
+    fn from_va<M: PhysicalMemory>(
+        space: &mut AddressSpace<M>,
+        crashdump: &CrashDump,
+        va: u64
+    ) -> Result<Self> {
+        let mut hdr = [0u8; 0x18];
+        space.read_virt(va, &mut hdr)?;
+
+        if is_kdbg_header(&hdr) {
+            let buf = read_block(space, va, &hdr)?;
+            return Kdbg::new(KdbgSource::Header, &buf);
+        }
+
+        if space.mode != PagingMode::X64 && space.mode != PagingMode::X64La57 {
+            return Err(Error::DumpParseError(
+                format!("No KDBG owner tag @ 0x{:x}", va)));
+        }
+
+        let keys = find_keys(space, crashdump, &hdr)?;
+        keys.decode_block(&mut hdr);
+
+        let mut buf = read_block(space, va, &hdr)?;
+        keys.decode_block(&mut buf);
+
+        Kdbg::new(KdbgSource::Decoded(keys), &buf)
+    }
+
+    /// Scan physical memory for a plain KDBG.
+    pub fn scan<M: PhysicalMemory>(space: &mut AddressSpace<M>) -> Result<Self> {
+        let mut page = vec![0u8; PAGE_SIZE];
+
+        for range in space.mem.ranges() {
+            for addr in range.step_by(PAGE_SIZE) {
+                if space.mem.read_phys(addr, &mut page).is_err() {
+                    continue;
+                }
+
+                for off in (0..PAGE_SIZE - 0x18).step_by(8) {
+                    let hdr = &page[off..off + 0x18];
+                    if !is_kdbg_header(hdr) {
+                        continue;
+                    }
+
+                    let size = u32::from_le_bytes([hdr[0x14], hdr[0x15], hdr[0x16], hdr[0x17]]);
+                    let mut buf = vec![0u8; size as usize];
+                    if space.mem.read_phys(addr + off as u64, &mut buf).is_err() {
+                        continue;
+                    }
+
+                    let kdbg = Kdbg::new(KdbgSource::Scanned(addr + off as u64), &buf)?;
+                    if kdbg.looks_valid(space) {
+                        return Ok(kdbg);
+                    }
+                }
+            }
+        }
+
+        Err(Error::DumpParseError("No KDBG found".to_string()))
+    }
+
+    fn new(source: KdbgSource, buf: &[u8]) -> Result<Self> {
+        // Older blocks are shorter than the decoded structure.
+        let mut block = buf.to_vec();
+        block.resize(block.len().max(mem::size_of::<KdDebuggerData64>()), 0);
+
+        let mut rdr = &block[..];
+        let data = read_type!(&mut rdr, KdDebuggerData64)?;
+
+        Ok(Kdbg { source, data })
+    }
+
+    /// Whether the kernel base of the block is mapped and starts with "MZ".
+    fn looks_valid<M: PhysicalMemory>(&self, space: &mut AddressSpace<M>) -> bool {
+        matches!(space.read_u16(self.data.kern_base), Ok(0x5a4d))
+    }
+
+    /// Named entries of the offsets table.
+    pub fn offsets(&self) -> Vec<(&'static str, u16)> {
+        let offsets = self.data.offsets;
+        KDBG_OFFSET_NAMES.iter().copied().zip(offsets.iter().copied()).collect()
+    }
+
+    /// Value of the `KDBG_OFFSET_NAMES` field `name`.
+    pub fn offset(&self, name: &str) -> Option<u16> {
+        self.offsets().into_iter()
+            .find(|(n, _)| *n == name)
+            .map(|(_, offset)| offset)
+    }
+}
+
+fn is_kdbg_header(hdr: &[u8]) -> bool {
+    let tag = u32::from_le_bytes([hdr[0x10], hdr[0x11], hdr[0x12], hdr[0x13]]);
+    let size = u32::from_le_bytes([hdr[0x14], hdr[0x15], hdr[0x16], hdr[0x17]]);
+
+    tag == KDBG_OWNER_TAG && size as usize >= 0x18 && size <= KDBG_MAX_SIZE
+}
+
+/// Read the whole block whose (decoded) header is `hdr`.
+fn read_block<M: PhysicalMemory>(space: &mut AddressSpace<M>, va: u64, hdr: &[u8]) -> Result<Vec<u8>> {
+    let size = u32::from_le_bytes([hdr[0x14], hdr[0x15], hdr[0x16], hdr[0x17]]);
+    if !is_kdbg_header(hdr) {
+        return Err(Error::DumpParseError(
+            format!("Unexpected KDBG size: 0x{:x}", size)));
+    }
+
+    // Encoded blocks are decoded by whole entries.
+    let mut buf = vec![0u8; (size as usize + 7) & !7];
+    space.read_virt(va, &mut buf)?;
+
+    Ok(buf)
+}
+
+/// First 64-bit kernel module in `PsLoadedModuleList`: its `DllBase` and
+/// `SizeOfImage`.
+fn kernel_image<M: PhysicalMemory>(space: &mut AddressSpace<M>, crashdump: &CrashDump) -> Result<(u64, u64)> {
+    let entry = space.read_u64(crashdump.ps_loaded_module_list)?;
+    let dll_base = space.read_u64(entry + 0x30)?;
+    let size_of_image = space.read_u32(entry + 0x40)?;
+
+    Ok((dll_base, size_of_image as u64))
+}
+
+/// Find the keys of an encoded KDBG with header `hdr` in the code of
+/// `KdCopyDataBlock`.
+///
+/// The kernel image is searched for `cmp byte ptr [rip+x], 0` on
+/// `KdpDataBlockEncoded` followed by RIP-relative loads of KiWaitNever and
+/// KiWaitAlways. A candidate is accepted when it decodes the owner tag.
+fn find_keys<M: PhysicalMemory>(
+    space: &mut AddressSpace<M>,
+    crashdump: &CrashDump,
+    hdr: &[u8]
+) -> Result<KdbgKeys> {
+    let (base, size) = kernel_image(space, crashdump)?;
+
+    let mut encoded_hdr = [0u64; 3];
+    for (i, entry) in encoded_hdr.iter_mut().enumerate() {
+        let mut v = [0u8; 8];
+        v.copy_from_slice(&hdr[i * 8..i * 8 + 8]);
+        *entry = u64::from_le_bytes(v);
+    }
+
+    let mut page = vec![0u8; PAGE_SIZE + KD_COPY_DATA_BLOCK_WINDOW];
+
+    for page_va in (base..base + size).step_by(PAGE_SIZE) {
+        // Code can straddle pages, read the start of the next one too.
+        page.iter_mut().for_each(|b| *b = 0);
+        if space.read_virt(page_va, &mut page[..PAGE_SIZE]).is_err() {
+            continue;
+        }
+        let _ = space.read_virt(page_va + PAGE_SIZE as u64, &mut page[PAGE_SIZE..]);
+
+        for pos in 0..PAGE_SIZE {
+            // cmp byte ptr [rip+rel32], 0
+            if page[pos..pos + 2] != [0x80, 0x3d] || page[pos + 6] != 0 {
+                continue;
+            }
+
+            let block_encoded = rip_target(page_va, &page, pos, 2, 7);
+            let loads = rip_loads(page_va, &page, pos + 7, KD_COPY_DATA_BLOCK_WINDOW - 7);
+            if loads.len() < 2 {
+                continue;
+            }
+
+            for &never in &loads {
+                for &always in &loads {
+                    if never == always || never == block_encoded || always == block_encoded {
+                        continue;
+                    }
+
+                    let (wait_never, wait_always) = match (space.read_u64(never), space.read_u64(always)) {
+                        (Ok(never), Ok(always)) => (never, always),
+                        _ => continue,
+                    };
+
+                    let keys = KdbgKeys { wait_never, wait_always, block_encoded };
+                    if keys.decode(encoded_hdr[2]) as u32 == KDBG_OWNER_TAG {
+                        return Ok(keys);
+                    }
+                }
+            }
+        }
+    }
+
+    Err(Error::DumpParseError(
+        "KdCopyDataBlock keys not found in the kernel image".to_string()))
+}
+
+/// Target of the RIP-relative displacement at `pos + disp` of an
+/// instruction at `pos` of length `len`.
+fn rip_target(page_va: u64, page: &[u8], pos: usize, disp: usize, len: usize) -> u64 {
+    let rel = i32::from_le_bytes([page[pos + disp], page[pos + disp + 1],
+        page[pos + disp + 2], page[pos + disp + 3]]);
+    (page_va + (pos + len) as u64).wrapping_add(rel as i64 as u64)
+}
+
+/// Targets of `mov r64, qword ptr [rip+rel32]` within `len` bytes from
+/// `start`.
+fn rip_loads(page_va: u64, page: &[u8], start: usize, len: usize) -> Vec<u64> {
+    let end = (start + len).min(page.len() - 7);
+    let mut loads = Vec::new();
+
+    for pos in start..end {
+        let rex = page[pos];
+        let modrm = page[pos + 2];
+        if (rex == 0x48 || rex == 0x4c) && page[pos + 1] == 0x8b && modrm & 0xc7 == 0x05 {
+            let target = rip_target(page_va, page, pos, 3, 7);
+            if !loads.contains(&target) {
+                loads.push(target);
+            }
+        }
+    }
+
+    loads
+}
+
+impl fmt::Display for Kdbg {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let data = &self.data;
+
+        match self.source {
+            KdbgSource::Header => writeln!(f, "Source:                 KdDebuggerDataBlock")?,
+            KdbgSource::Decoded(keys) => {
+                writeln!(f, "Source:                 KdDebuggerDataBlock (encoded)")?;
+                writeln!(f, "KiWaitNever:            0x{:016x}", keys.wait_never)?;
+                writeln!(f, "KiWaitAlways:           0x{:016x}", keys.wait_always)?;
+                writeln!(f, "KdpDataBlockEncoded:    0x{:x}", keys.block_encoded)?;
+            },
+            KdbgSource::Scanned(addr) =>
+                writeln!(f, "Source:                 scan, physical address 0x{:x}", addr)?,
+        }
+
+        writeln!(f, "Size:                   0x{:x}", { data.size })?;
+        writeln!(f, "KernBase:               0x{:x}", { data.kern_base })?;
+        writeln!(f, "PsLoadedModuleList:     0x{:x}", { data.ps_loaded_module_list })?;
+        writeln!(f, "PsActiveProcessHead:    0x{:x}", { data.ps_active_process_head })?;
+        writeln!(f, "PspCidTable:            0x{:x}", { data.psp_cid_table })?;
+        writeln!(f, "MmPfnDatabase:          0x{:x}", { data.mm_pfn_database })?;
+        writeln!(f, "MmSystemRangeStart:     0x{:x}", { data.mm_system_range_start })?;
+        writeln!(f, "KiProcessorBlock:       0x{:x}", { data.ki_processor_block })?;
+        writeln!(f, "NtBuildLab:             0x{:x}", { data.nt_build_lab })?;
+        writeln!(f, "MmPhysicalMemoryBlock:  0x{:x}", { data.mm_physical_memory_block })?;
+        write!(f, "KeLoaderBlock:          0x{:x}", { data.ke_loader_block })?;
+
+        for (name, offset) in self.offsets() {
+            write!(f, "\n{:<34} 0x{:x}", name, offset)?;
+        }
+
+        Ok(())
+    }
+}
@@ -16,6 +16,28 @@ pub mod trace;
 pub mod function;
 
 pub mod zdmp;
+pub mod reader;
+pub mod writer;
+pub mod lznt1;
 pub mod result;
 pub mod io;
-pub mod hexdump;
\ No newline at end of file
+pub mod hexdump;
+pub mod info;
+pub mod crashdump;
+pub mod export;
+pub mod aff4;
+pub mod ewf;
+pub mod seekable;
+pub mod physmem;
+pub mod paging;
+pub mod kdbg;
+pub mod modules;
+pub mod process;
+pub mod profile;
+pub mod poolscan;
+pub mod bugcheck;
+pub mod carve;
+pub mod symbols;
+pub mod pe;
+pub mod json;
+pub mod lint;
\ No newline at end of file
@@ -0,0 +1,244 @@
+use std::fmt;
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::mem;
+use std::path::Path;
+
+use crate::reader::{ZdmpReader, ZdmpRecord};
+use crate::result::Result;
+use crate::zdmp::{
+    ZdmpFileHdr, ZdmpFileHdrV2, ZdmpIndexEntry, ZdmpVersion, CRC32_IEEE, PAGE_SIZE,
+    ZDMP_BLOCK_START_OFFSET, BLOCK_FLAG_COMPRESSED, BLOCK_FLAG_ZERO_RUN, BLOCK_FLAG_ENCRYPTED, BLOCK_FLAG_DUPLICATE,
+};
+
+/// Severity of a lint finding, from least to most severe.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    Info,
+    Warning,
+    Error,
+}
+
+impl Severity {
+    /// Severity named `s`, or `None` for an unknown name.
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "info" => Some(Severity::Info),
+            "warning" | "warn" => Some(Severity::Warning),
+            "error" => Some(Severity::Error),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Severity::Info => "info",
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+        };
+
+        write!(f, "{}", name)
+    }
+}
+
+/// A structural anomaly at a file offset.
+#[derive(Debug)]
+pub struct Finding {
+    pub severity:   Severity,
+    pub offset:     u64,
+    pub message:    String,
+}
+
+impl fmt::Display for Finding {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:<8} 0x{:010x}  {}", self.severity, self.offset, self.message)
+    }
+}
+
+/// Walks a zdmp and collects the anomalies that conversion ignores or only
+/// logs.
+struct Linter {
+    findings:   Vec<Finding>,
+}
+
+impl Linter {
+    fn report(&mut self, severity: Severity, offset: u64, message: String) {
+        self.findings.push(Finding { severity, offset, message });
+    }
+
+    fn check_header(&mut self, hdr: &ZdmpFileHdr, page: &[u8], used: usize) {
+        let block_size = hdr.block_size as usize;
+        if !block_size.is_multiple_of(PAGE_SIZE) {
+            self.report(Severity::Warning, 0x10, format!(
+                "Block size 0x{:x} is not a multiple of the page size", block_size));
+        }
+
+        if let Some(pos) = page[used..].iter().position(|&b| b != 0) {
+            self.report(Severity::Warning, (used + pos) as u64, format!(
+                "Non-zero bytes in the header page after the header (0x{:x} bytes)",
+                page[used..].iter().filter(|&&b| b != 0).count()));
+        }
+    }
+
+    fn check_record(&mut self, reader: &mut ZdmpReader<File>, rec: &ZdmpRecord) {
+        let block_size = reader.block_size();
+
+        if rec.flags & BLOCK_FLAG_ENCRYPTED != 0 {
+            self.report(Severity::Warning, rec.offset, format!(
+                "Block #{} is encrypted and cannot be checked", rec.block_id));
+        }
+
+        if rec.flags & (BLOCK_FLAG_ZERO_RUN | BLOCK_FLAG_DUPLICATE) != 0 {
+            if rec.data_size != 0 {
+                self.report(Severity::Warning, rec.offset, format!(
+                    "Block #{} has a 0x{:x} bytes payload that is never read",
+                    rec.block_id, rec.data_size));
+            }
+            return;
+        }
+
+        let data = match reader.read_payload(rec) {
+            Ok(data) => data,
+            Err(e) => {
+                self.report(Severity::Error, rec.data_offset(), format!(
+                    "Block #{} payload of 0x{:x} bytes is truncated: {:?}",
+                    rec.block_id, rec.data_size, e));
+                return;
+            },
+        };
+
+        let checksum = CRC32_IEEE.checksum(&data);
+        if checksum != rec.crc32 {
+            self.report(Severity::Error, rec.offset, format!(
+                "Block #{} has an incorrect crc32 0x{:x} (expected 0x{:x})",
+                rec.block_id, checksum, rec.crc32));
+        }
+
+        if rec.flags & BLOCK_FLAG_COMPRESSED == 0 || rec.flags & BLOCK_FLAG_ENCRYPTED != 0 {
+            return;
+        }
+
+        let mut uncompressed = Vec::with_capacity(block_size);
+        if let Err(e) = lzxpress::lznt1::decompress2(&data, &mut uncompressed) {
+            self.report(Severity::Error, rec.data_offset(), format!(
+                "Block #{} LZNT1 stream is corrupted: {:?}", rec.block_id, e));
+        }
+
+        if uncompressed.len() > block_size {
+            self.report(Severity::Error, rec.data_offset(), format!(
+                "Block #{} LZNT1 output of 0x{:x} bytes is longer than the block size",
+                rec.block_id, uncompressed.len()));
+        } else if uncompressed.len() < block_size {
+            self.report(Severity::Info, rec.data_offset(), format!(
+                "Block #{} LZNT1 output of 0x{:x} bytes is padded with zeros",
+                rec.block_id, uncompressed.len()));
+        }
+    }
+
+    fn check_index(&mut self, reader: &mut ZdmpReader<File>, hdr_v2: &ZdmpFileHdrV2, records: u64) {
+        if !reader.has_index() {
+            self.report(Severity::Error, hdr_v2.index_offset, format!(
+                "Block index @ 0x{:x} is missing, the file was not finished",
+                { hdr_v2.index_offset }));
+            return;
+        }
+
+        if let Err(e) = reader.load_index(hdr_v2) {
+            self.report(Severity::Error, hdr_v2.index_offset, format!("{:?}", e));
+        }
+
+        if hdr_v2.record_count != records {
+            self.report(Severity::Error, hdr_v2.index_offset, format!(
+                "Block index has {} entries but the file has {} block records",
+                { hdr_v2.record_count }, records));
+        }
+
+        let index_end = hdr_v2.index_offset
+            + hdr_v2.record_count * mem::size_of::<ZdmpIndexEntry>() as u64;
+        if index_end < reader.file_len {
+            self.report(Severity::Warning, index_end, format!(
+                "0x{:x} trailing bytes after the block index", reader.file_len - index_end));
+        }
+    }
+}
+
+/// Check the structure of the zdmp at `path`.
+///
+/// Findings are sorted by offset.
+pub fn lint(path: &Path, force: bool) -> Result<Vec<Finding>> {
+    let mut linter = Linter { findings: Vec::new() };
+
+    let mut file = File::open(path)?;
+    let mut page = vec![0; PAGE_SIZE];
+    let page_len = file.read(&mut page)?;
+    page.truncate(page_len);
+    file.seek(SeekFrom::Start(0))?;
+
+    let mut reader = ZdmpReader::new(file, force)?;
+    let hdr = reader.hdr;
+
+    if reader.file_len < ZDMP_BLOCK_START_OFFSET {
+        linter.report(Severity::Error, reader.file_len, format!(
+            "File of 0x{:x} bytes is shorter than the header page", reader.file_len));
+        return Ok(linter.findings);
+    }
+
+    let used = match reader.hdr_v2 {
+        Some(hdr_v2) => mem::size_of::<ZdmpFileHdr>() + mem::size_of::<ZdmpFileHdrV2>()
+            + hdr_v2.metadata_size as usize,
+        None => mem::size_of::<ZdmpFileHdr>(),
+    };
+    linter.check_header(&hdr, &page, used);
+
+    let mut records = 0;
+    let mut blocks = 0;
+
+    loop {
+        let offset = reader.next_offset();
+        let left = reader.records_end().saturating_sub(offset);
+
+        if left == 0 {
+            break;
+        }
+
+        if left < reader.record_hdr_size() {
+            linter.report(Severity::Warning, offset, format!(
+                "0x{:x} trailing bytes after the last block are smaller than a block header",
+                left));
+            break;
+        }
+
+        let rec = match reader.next_record() {
+            Ok(Some(rec)) => rec,
+            Ok(None) => break,
+            Err(e) => {
+                linter.report(Severity::Error, offset, format!(
+                    "Unreadable block header #{}: {:?}", blocks, e));
+                break;
+            },
+        };
+
+        linter.check_record(&mut reader, &rec);
+
+        records += 1;
+        blocks = rec.block_id + rec.block_count;
+    }
+
+    let block_size = hdr.block_size as u64;
+    let file_size = hdr.file_size;
+    if file_size > blocks * block_size || file_size + block_size <= blocks * block_size {
+        linter.report(Severity::Error, 0x8, format!(
+            "Declared file size 0x{:x} does not match the 0x{:x} bytes of {} blocks",
+            file_size, blocks * block_size, blocks));
+    }
+
+    if let (ZdmpVersion::V20, Some(hdr_v2)) = (reader.version, reader.hdr_v2) {
+        linter.check_index(&mut reader, &hdr_v2, records);
+    }
+
+    linter.findings.sort_by_key(|f| f.offset);
+
+    Ok(linter.findings)
+}
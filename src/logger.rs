@@ -84,17 +84,17 @@ pub fn get_level() -> LogLevel {
 #[macro_export]
 macro_rules! warn {
     ($($arg:tt)*) => {{
-        if crate::logger::get_level() >= crate::logger::LogLevel::Warn {
+        if $crate::logger::get_level() >= $crate::logger::LogLevel::Warn {
             // Separate scope to release the lock.
             {
                 use std::fmt::Write;
-                let buf = &mut *crate::logger::TRACE_BUF.lock().unwrap();
+                let buf = &mut *$crate::logger::TRACE_BUF.lock().unwrap();
                 writeln!(buf, " WARN: {}", format_args!($($arg)*)).unwrap();
             }
 
             // Print immediately if the current log-level is not `Trace`.
-            if crate::logger::get_level() != crate::logger::LogLevel::Trace {
-                crate::logger::flush_trace();
+            if $crate::logger::get_level() != $crate::logger::LogLevel::Trace {
+                $crate::logger::flush_trace();
             }
         }
     }};
@@ -103,17 +103,17 @@ macro_rules! warn {
 #[macro_export]
 macro_rules! info {
     ($($arg:tt)*) => {{
-        if crate::logger::get_level() >= crate::logger::LogLevel::Info {
+        if $crate::logger::get_level() >= $crate::logger::LogLevel::Info {
             // Separate scope to release the lock.
             {
                 use std::fmt::Write;
-                let buf = &mut *crate::logger::TRACE_BUF.lock().unwrap();
+                let buf = &mut *$crate::logger::TRACE_BUF.lock().unwrap();
                 writeln!(buf, " INFO: {}", format_args!($($arg)*)).unwrap();
             }
 
             // Print immediately if the current log-level is not `Trace`.
-            if crate::logger::get_level() != crate::logger::LogLevel::Trace {
-                crate::logger::flush_trace();
+            if $crate::logger::get_level() != $crate::logger::LogLevel::Trace {
+                $crate::logger::flush_trace();
             }
         }
     }};
@@ -122,17 +122,17 @@ macro_rules! info {
 #[macro_export]
 macro_rules! debug {
     ($($arg:tt)*) => {{
-        if crate::logger::get_level() >= crate::logger::LogLevel::Debug {
+        if $crate::logger::get_level() >= $crate::logger::LogLevel::Debug {
             // Separate scope to release the lock.
             {
                 use std::fmt::Write;
-                let buf = &mut *crate::logger::TRACE_BUF.lock().unwrap();
+                let buf = &mut *$crate::logger::TRACE_BUF.lock().unwrap();
                 writeln!(buf, "DEBUG: {}", format_args!($($arg)*)).unwrap();
             }
 
             // Print immediately if the current log-level is not `Trace`.
-            if crate::logger::get_level() != crate::logger::LogLevel::Trace {
-                crate::logger::flush_trace();
+            if $crate::logger::get_level() != $crate::logger::LogLevel::Trace {
+                $crate::logger::flush_trace();
             }
         }
     }};
@@ -141,11 +141,11 @@ macro_rules! debug {
 #[macro_export]
 macro_rules! trace {
     ($($arg:tt)*) => {{
-        if crate::logger::get_level() >= crate::logger::LogLevel::Trace {
+        if $crate::logger::get_level() >= $crate::logger::LogLevel::Trace {
             // Separate scope to release the lock.
             {
                 use std::fmt::Write;
-                let buf = &mut *crate::logger::TRACE_BUF.lock().unwrap();
+                let buf = &mut *$crate::logger::TRACE_BUF.lock().unwrap();
                 writeln!(buf, "TRACE: {}", format_args!($($arg)*)).unwrap()
             }
 
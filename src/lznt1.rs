@@ -0,0 +1,212 @@
+//! LZNT1 compressor.
+//!
+//! `rust-lzxpress` only ships a decompressor, which is all the reader needs.
+//! The writer uses this module to produce streams it can read back.
+
+pub const LZNT1_CHUNK_SIZE:     usize = 0x1000;
+
+const LZNT1_COMPRESSED_FLAG:    u16 = 0x8000;
+const LZNT1_SIGNATURE:          u16 = 0x3000;
+
+const MIN_MATCH:                usize = 3;
+const HASH_BITS:                usize = 12;
+const MAX_CHAIN:                usize = 32;
+
+/// Compress `input` and append the LZNT1 stream to `out`.
+pub fn compress(input: &[u8], out: &mut Vec<u8>) {
+    let mut head = vec![usize::MAX; 1 << HASH_BITS];
+    let mut prev = vec![usize::MAX; LZNT1_CHUNK_SIZE];
+
+    for chunk in input.chunks(LZNT1_CHUNK_SIZE) {
+        compress_chunk(chunk, out, &mut head, &mut prev);
+    }
+}
+
+fn hash(data: &[u8]) -> usize {
+    let v = (data[0] as usize) << 16 | (data[1] as usize) << 8 | data[2] as usize;
+    (v.wrapping_mul(0x9e37_79b1) >> 8) & ((1 << HASH_BITS) - 1)
+}
+
+/// Length and offset limits for a tuple emitted at `pos` within a chunk.
+fn tuple_limits(pos: usize) -> (usize, usize, u32) {
+    let mut p = pos - 1;
+    let mut length_mask = 0xfff;
+    let mut offset_shift = 12;
+
+    while p >= 0x10 {
+        length_mask >>= 1;
+        offset_shift -= 1;
+        p >>= 1;
+    }
+
+    let max_len = length_mask + MIN_MATCH;
+    let max_offset = 1 << (16 - offset_shift);
+
+    (max_len, max_offset, offset_shift)
+}
+
+fn compress_chunk(
+    chunk: &[u8],
+    out: &mut Vec<u8>,
+    head: &mut [usize],
+    prev: &mut [usize]
+) {
+    head.iter_mut().for_each(|h| *h = usize::MAX);
+
+    let start = out.len();
+    out.extend_from_slice(&[0, 0]);
+
+    let mut pos = 0;
+    while pos < chunk.len() {
+        let flags_idx = out.len();
+        out.push(0);
+        let mut flags = 0u8;
+
+        for bit in 0..8 {
+            if pos >= chunk.len() {
+                break;
+            }
+
+            let (len, offset) = find_match(chunk, pos, head, prev);
+
+            if len >= MIN_MATCH {
+                let (_, _, offset_shift) = tuple_limits(pos);
+                let tuple = (((offset - 1) << offset_shift) | (len - MIN_MATCH)) as u16;
+                out.extend_from_slice(&tuple.to_le_bytes());
+                flags |= 1 << bit;
+
+                for p in pos..pos + len {
+                    insert(chunk, p, head, prev);
+                }
+                pos += len;
+            } else {
+                out.push(chunk[pos]);
+                insert(chunk, pos, head, prev);
+                pos += 1;
+            }
+        }
+
+        out[flags_idx] = flags;
+    }
+
+    let data_len = out.len() - start - 2;
+
+    if data_len >= chunk.len() {
+        // Compression did not pay off, store the chunk as is.
+        out.truncate(start);
+        let hdr = (chunk.len() - 1) as u16 | LZNT1_SIGNATURE;
+        out.extend_from_slice(&hdr.to_le_bytes());
+        out.extend_from_slice(chunk);
+    } else {
+        let hdr = (data_len - 1) as u16 | LZNT1_SIGNATURE | LZNT1_COMPRESSED_FLAG;
+        out[start..start + 2].copy_from_slice(&hdr.to_le_bytes());
+    }
+}
+
+fn insert(chunk: &[u8], pos: usize, head: &mut [usize], prev: &mut [usize]) {
+    if pos + MIN_MATCH > chunk.len() {
+        return;
+    }
+
+    let h = hash(&chunk[pos..]);
+    prev[pos] = head[h];
+    head[h] = pos;
+}
+
+/// Longest earlier match for `pos`, as (length, offset).
+fn find_match(chunk: &[u8], pos: usize, head: &[usize], prev: &[usize]) -> (usize, usize) {
+    if pos == 0 || pos + MIN_MATCH > chunk.len() {
+        return (0, 0);
+    }
+
+    let (max_len, max_offset, _) = tuple_limits(pos);
+    let max_len = max_len.min(chunk.len() - pos);
+
+    let mut best = (0, 0);
+    let mut candidate = head[hash(&chunk[pos..])];
+    let mut chain = 0;
+
+    while candidate != usize::MAX && chain < MAX_CHAIN {
+        let offset = pos - candidate;
+        if offset > max_offset {
+            break;
+        }
+
+        let len = chunk[candidate..].iter()
+            .zip(&chunk[pos..pos + max_len])
+            .take_while(|(a, b)| a == b)
+            .count();
+
+        if len > best.0 {
+            best = (len, offset);
+            if len == max_len {
+                break;
+            }
+        }
+
+        candidate = prev[candidate];
+        chain += 1;
+    }
+
+    best
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Deterministic xorshift bytes.
+    fn noise(seed: u64, len: usize) -> Vec<u8> {
+        let mut x = seed | 1;
+        (0..len).map(|_| {
+            x ^= x << 13;
+            x ^= x >> 7;
+            x ^= x << 17;
+            x as u8
+        }).collect()
+    }
+
+    fn round_trip(input: &[u8]) {
+        let mut compressed = Vec::new();
+        compress(input, &mut compressed);
+
+        let mut out = Vec::new();
+        lzxpress::lznt1::decompress2(&compressed, &mut out).unwrap();
+        assert_eq!(out, input, "round trip of 0x{:x} bytes", input.len());
+    }
+
+    #[test]
+    fn round_trip_mixed_inputs() {
+        let text = b"PAGEDU64 KDBG nt!KiSystemStartup ntoskrnl.exe ".repeat(400);
+
+        for n in 0..200u64 {
+            let len = (n as usize * 0x1f3) % 0x5000;
+            let input = match n % 5 {
+                0 => noise(n, len),
+                1 => vec![0u8; len],
+                2 => text[..len.min(text.len())].to_vec(),
+                3 => noise(n, len).iter().map(|b| b & 0x3).collect(),
+                // Random runs of repeated bytes.
+                _ => noise(n, len / 16).iter().flat_map(|&b| [b; 16]).collect(),
+            };
+            round_trip(&input);
+        }
+    }
+
+    #[test]
+    fn round_trip_chunk_boundaries() {
+        for len in [0, 1, 2, 3, LZNT1_CHUNK_SIZE - 1, LZNT1_CHUNK_SIZE, LZNT1_CHUNK_SIZE + 1,
+            3 * LZNT1_CHUNK_SIZE] {
+            round_trip(&noise(len as u64, len));
+            round_trip(&vec![0x41; len]);
+        }
+    }
+
+    #[test]
+    fn compresses_repetitive_data() {
+        let input = vec![0x90u8; 4 * LZNT1_CHUNK_SIZE];
+        let mut compressed = Vec::new();
+        compress(&input, &mut compressed);
+        assert!(compressed.len() < input.len() / 16);
+    }
+}
@@ -1,42 +1,583 @@
+use std::collections::HashMap;
+use std::convert::TryFrom;
 use std::env;
+use std::fs::File;
 use std::path::Path;
+use std::process;
+use std::thread;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
 
-use z2dmp::{logger, info};
-use z2dmp::zdmp;
+use z2dmp::{logger, info, warn};
+use z2dmp::zdmp::{self, ZdmpFileHdr, ZdmpMetadata};
+use z2dmp::info::ZdmpInfo;
+use z2dmp::lint::{self, Severity};
+use z2dmp::writer::{self, ZDMP_DEFAULT_BLOCK_SIZE};
+use z2dmp::export;
+use z2dmp::aff4::Aff4Image;
+use z2dmp::ewf::EwfCaseInfo;
+use z2dmp::seekable::SeekableReader;
+use z2dmp::paging::{AddressSpace, PagingMode};
+use z2dmp::physmem::ZdmpPhysicalMemory;
+use z2dmp::kdbg::Kdbg;
+use z2dmp::bugcheck::BugCheck;
+use z2dmp::{carve, json, modules, poolscan};
+use z2dmp::process::list_processes;
+use z2dmp::profile::EprocessProfile;
+use z2dmp::modules::KldrLayout;
+use z2dmp::crashdump::CrashDump;
+use z2dmp::symbols::{split_symbol, SymbolResolver, SymbolStore, KERNEL_MODULE_ALIAS};
 
-use z2dmp::result::{Result};
+use z2dmp::result::{Result, Error};
+
+fn usage(prog: &str) -> String {
+    format!("Usage: {0} [--force] <input_file> <output_file>\n       \
+        {0} convert [--force] [--format dmp|dmp.zst|raw|bitmap-dmp|lime|elf|aff4|ewf] [--notes] [case options] <input_file> <output_file>\n       \
+        {0} info [--force] <input_file>\n       \
+        {0} compress [options] <dmp_file> <zdmp_file>\n       \
+        {0} lint [--force] [--threshold <level>] <input_file>\n       \
+        {0} kdbg [--force] <input_file>\n       \
+        {0} bugcheck [--force] [--json] <input_file>\n       \
+        {0} modules [--force] [--json] [--symbols <dir>] <input_file>\n       \
+        {0} symbol [--force] --symbols <dir> <input_file> <module!name>...\n       \
+        {0} carve-pe [--force] [--virtual] [--dtb <cr3>] <input_file> <output_dir>\n       \
+        {0} processes [--force] [--json] [--profile <file> | --symbols <dir>] <input_file>\n       \
+        {0} poolscan [--force] [--json] [--tags <tags>] [--threads <n>] <input_file>\n       \
+        {0} pte [--force] [--dtb <cr3>] [--la57] <input_file> <address>\n\n\
+        --force               Parse unknown zdmp versions with the 2.0 layout.\n\
+        --format <format>     Output of convert: crash dump (dmp, default), seekable zstd crash dump (dmp.zst), flat physical image (raw), bitmap dump (bitmap-dmp), LiME (lime), ELF core (elf), AFF4 (aff4) or EWF (ewf).\n\
+        --notes               Keep the crash dump header and CPU context in a PT_NOTE of ELF cores.\n\
+        --dtb <cr3>           Page table root of pte and carve-pe (default: the crash dump DirectoryTableBase).\n\
+        --virtual             Carve from the kernel (or --dtb) address space instead of physical memory.\n\
+        --la57                Use 5-level paging.\n\
+        --json                Print JSON instead of text.\n\
+        --profile <file>      Extra _EPROCESS offset profiles, tried before the bundled ones.\n\
+        --symbols <dir>       Local symbol store (srv*<dir>*<url> accepted) to take offsets from the PDBs.\n\
+        --tags <tags>         Comma separated pool tags of poolscan (default: {2}).\n\
+        --threads <n>         Scan threads of poolscan (default: one per CPU).\n\
+        --threshold <level>   Lowest lint severity (info, warning, error) that fails (default: error).\n\
+        --block-size <n>      Block size of the new zdmp (default: 0x{1:x}).\n\
+        --hostname <name>     Hostname metadata of the new zdmp.\n\
+        --acquired-at <secs>  Acquisition time metadata, in seconds since the Unix epoch.\n\
+        --case-notes <text>   Case notes metadata of the new zdmp, or notes of the EWF image.\n\
+        --case-number <text>  Case number of the EWF image.\n\
+        --evidence-number <text> Evidence number of the EWF image.\n\
+        --examiner <name>     Examiner name of the EWF image.\n\
+        --description <text>  Description of the EWF image (default: the zdmp hostname).", prog, ZDMP_DEFAULT_BLOCK_SIZE,
+        poolscan::DEFAULT_POOL_TAGS.join(","))
+}
+
+/// Options followed by a value.
+const VALUE_OPTIONS: &[&str] = &["--block-size", "--hostname", "--acquired-at", "--case-notes",
+    "--threshold", "--format", "--dtb", "--profile", "--tags", "--threads", "--symbols",
+    "--case-number", "--evidence-number", "--examiner", "--description"];
+
+/// Command line split into positional arguments, `--flags` and
+/// `--option value` pairs.
+struct Args {
+    prog:       String,
+    positional: Vec<String>,
+    flags:      Vec<String>,
+    options:    Vec<(String, String)>,
+}
+
+impl Args {
+    fn parse(args: &[String]) -> Self {
+        let mut positional = Vec::new();
+        let mut flags = Vec::new();
+        let mut options = Vec::new();
+
+        let mut iter = args[1..].iter();
+        while let Some(arg) = iter.next() {
+            if VALUE_OPTIONS.contains(&arg.as_str()) {
+                match iter.next() {
+                    Some(value) => options.push((arg.clone(), value.clone())),
+                    None => panic!("{}", usage(&args[0])),
+                }
+            } else if arg.starts_with("--") {
+                flags.push(arg.clone());
+            } else {
+                positional.push(arg.clone());
+            }
+        }
+
+        Args { prog: args[0].clone(), positional, flags, options }
+    }
+
+    fn has_flag(&self, flag: &str) -> bool {
+        self.flags.iter().any(|f| f == flag)
+    }
+
+    fn option(&self, name: &str) -> Option<&str> {
+        self.options.iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// Positional argument `n`, or exit with the usage text.
+    fn positional(&self, n: usize) -> &str {
+        match self.positional.get(n) {
+            Some(arg) => arg,
+            None => panic!("{}", usage(&self.prog)),
+        }
+    }
+}
 
 fn main()
+-> Result<()> {
+    let args = Args::parse(&env::args().collect::<Vec<String>>());
+
+    match args.positional.first().map(|s| s.as_str()) {
+        Some("info") => cmd_info(&args),
+        Some("compress") => cmd_compress(&args),
+        Some("lint") => cmd_lint(&args),
+        Some("convert") => cmd_convert(&args, 1),
+        Some("pte") => cmd_pte(&args),
+        Some("kdbg") => cmd_kdbg(&args),
+        Some("bugcheck") => cmd_bugcheck(&args),
+        Some("modules") => cmd_modules(&args),
+        Some("carve-pe") => cmd_carve_pe(&args),
+        Some("symbol") => cmd_symbol(&args),
+        Some("processes") => cmd_processes(&args),
+        Some("poolscan") => cmd_poolscan(&args),
+        _ => cmd_convert(&args, 0),
+    }
+}
+
+/// Header of the zdmp at `path`, whose version heads every report.
+fn read_zdmp_hdr(path: &str)
+-> Result<ZdmpFileHdr> {
+    ZdmpFileHdr::new(File::open(path)?)
+}
+
+/// Version line printed ahead of the text reports.
+fn version_line(hdr: &ZdmpFileHdr, force: bool)
+-> Result<String> {
+    let layout = hdr.parse_version(force)?;
+
+    Ok(match hdr.version_name() {
+        "unknown" => format!("Zdmp version: 0x{:04x} (unknown, parsed as {})", { hdr.version }, layout.name()),
+        name => format!("Zdmp version: 0x{:04x} ({})", { hdr.version }, name),
+    })
+}
+
+/// `--json` report: the zdmp version and `report` under `key`.
+fn json_report(hdr: &ZdmpFileHdr, key: &str, report: &str)
+-> String {
+    json::JsonObject::new()
+        .hex("zdmp_version", hdr.version as u64)
+        .str("zdmp_version_name", hdr.version_name())
+        .raw(key, Some(report))
+        .finish()
+}
+
+/// Print the decoded zdmp header without touching the block payloads.
+fn cmd_info(args: &Args)
+-> Result<()> {
+    logger::init("warn")?;
+
+    let zdmp_info = ZdmpInfo::new(Path::new(args.positional(1)), args.has_flag("--force"))?;
+    println!("{}", zdmp_info);
+
+    Ok(())
+}
+
+/// Report structural anomalies and exit with 1 when one reaches the
+/// threshold severity.
+fn cmd_lint(args: &Args)
+-> Result<()> {
+    logger::init("warn")?;
+
+    let threshold = match args.option("--threshold") {
+        Some(level) => match Severity::parse(level) {
+            Some(threshold) => threshold,
+            None => panic!("Unexpected severity `{}`\n{}", level, usage(&args.prog)),
+        },
+        None => Severity::Error,
+    };
+
+    let hdr = read_zdmp_hdr(args.positional(1))?;
+    println!("{}", version_line(&hdr, args.has_flag("--force"))?);
+
+    let findings = lint::lint(Path::new(args.positional(1)), args.has_flag("--force"))?;
+    for finding in &findings {
+        println!("{}", finding);
+    }
+
+    let failed = findings.iter().filter(|f| f.severity >= threshold).count();
+    println!("{} findings, {} at or above {}", findings.len(), failed, threshold);
+
+    if failed > 0 {
+        process::exit(1);
+    }
+
+    Ok(())
+}
+
+/// Explain the translation of a virtual address, like `!pte`.
+fn cmd_pte(args: &Args)
+-> Result<()> {
+    logger::init("warn")?;
+
+    let mem = ZdmpPhysicalMemory::open(Path::new(args.positional(1)), args.has_flag("--force"))?;
+    let va = parse_u64(args.positional(2))?;
+
+    let mut space = AddressSpace::kernel(mem);
+    if let Some(dtb) = args.option("--dtb") {
+        space.dtb = parse_u64(dtb)?;
+    }
+    if args.has_flag("--la57") {
+        space.mode = PagingMode::X64La57;
+    }
+
+    let hdr = read_zdmp_hdr(args.positional(1))?;
+    println!("{}", version_line(&hdr, args.has_flag("--force"))?);
+    println!("{}", space.translate(va)?);
+
+    Ok(())
+}
+
+/// Print the kernel debugger data block.
+fn cmd_kdbg(args: &Args)
+-> Result<()> {
+    logger::init("warn")?;
+
+    let mem = ZdmpPhysicalMemory::open(Path::new(args.positional(1)), args.has_flag("--force"))?;
+    let crashdump = mem.crashdump.clone();
+    let mut space = AddressSpace::kernel(mem);
+
+    let hdr = read_zdmp_hdr(args.positional(1))?;
+    println!("{}", version_line(&hdr, args.has_flag("--force"))?);
+    println!("{}", Kdbg::locate(&mut space, &crashdump)?);
+
+    Ok(())
+}
+
+/// Print the bugcheck and the register context of every processor.
+fn cmd_bugcheck(args: &Args)
+-> Result<()> {
+    logger::init("warn")?;
+
+    let mem = ZdmpPhysicalMemory::open(Path::new(args.positional(1)), args.has_flag("--force"))?;
+    let crashdump = mem.crashdump.clone();
+    let mut space = AddressSpace::kernel(mem);
+
+    let mut bugcheck = BugCheck::new(&crashdump);
+    match Kdbg::locate(&mut space, &crashdump) {
+        Ok(kdbg) => bugcheck.read_processors(&mut space, &crashdump, &kdbg)?,
+        Err(e) => warn!("No KDBG, skipping the processor contexts: {:?}", e),
+    }
+
+    let hdr = read_zdmp_hdr(args.positional(1))?;
+    if args.has_flag("--json") {
+        println!("{}", json_report(&hdr, "bugcheck", &bugcheck.to_json()));
+    } else {
+        println!("{}", version_line(&hdr, args.has_flag("--force"))?);
+        print!("{}", bugcheck);
+    }
+
+    Ok(())
+}
+
+/// List the loaded kernel modules.
+fn cmd_modules(args: &Args)
+-> Result<()> {
+    logger::init("warn")?;
+
+    let mem = ZdmpPhysicalMemory::open(Path::new(args.positional(1)), args.has_flag("--force"))?;
+    let crashdump = mem.crashdump.clone();
+    let mut space = AddressSpace::kernel(mem);
+
+    let modules = match args.option("--symbols") {
+        Some(spec) => {
+            let mut symbols = kernel_symbols(&mut space, &crashdump, spec)?;
+            let list_head = symbols.resolve(&mut space, "nt!PsLoadedModuleList")?;
+            let layout = KldrLayout::from_symbols(symbols.module(&mut space, KERNEL_MODULE_ALIAS)?)?;
+            modules::list_modules_with_layout(&mut space, list_head, &layout)?
+        },
+        None => modules::list_modules(&mut space, crashdump.ps_loaded_module_list)?,
+    };
+
+    let hdr = read_zdmp_hdr(args.positional(1))?;
+    if args.has_flag("--json") {
+        println!("{}", json_report(&hdr, "modules", &json::array(modules.iter().map(|m| m.to_json()))));
+        return Ok(());
+    }
+
+    println!("{}", version_line(&hdr, args.has_flag("--force"))?);
+    println!("{:<18} {:<10} {:<35} {:<10} Path", "Base", "Size", "Timestamp", "Checksum");
+    for module in &modules {
+        println!("{}", module);
+    }
+
+    Ok(())
+}
+
+/// Symbol resolver over the modules of the dump header `PsLoadedModuleList`.
+fn kernel_symbols(space: &mut AddressSpace<ZdmpPhysicalMemory<File>>, crashdump: &CrashDump, spec: &str)
+-> Result<SymbolResolver> {
+    let modules = modules::list_modules(space, crashdump.ps_loaded_module_list)?;
+    Ok(SymbolResolver::new(SymbolStore::new(spec), modules))
+}
+
+/// Resolve `module!global` addresses and print `module!type` layouts.
+fn cmd_symbol(args: &Args)
+-> Result<()> {
+    logger::init("warn")?;
+
+    let spec = args.option("--symbols").ok_or_else(||
+        Error::SymbolError("symbol needs --symbols <dir>".to_string()))?;
+
+    let mem = ZdmpPhysicalMemory::open(Path::new(args.positional(1)), args.has_flag("--force"))?;
+    let crashdump = mem.crashdump.clone();
+    let mut space = AddressSpace::kernel(mem);
+    let mut symbols = kernel_symbols(&mut space, &crashdump, spec)?;
+
+    // At least one name.
+    args.positional(2);
+
+    let hdr = read_zdmp_hdr(args.positional(1))?;
+    println!("{}", version_line(&hdr, args.has_flag("--force"))?);
+    for expr in &args.positional[2..] {
+        let (module, name) = split_symbol(expr)?;
+        let module = symbols.module(&mut space, module)?;
+
+        if let Some(address) = module.global(name) {
+            println!("{} = 0x{:x}", expr, address);
+        } else if let Some(layout) = module.layout(name) {
+            println!("{} (0x{:x} bytes)", expr, layout.size);
+            for (field, offset) in &layout.fields {
+                println!("   +0x{:03x} {}", offset, field);
+            }
+        } else {
+            return Err(Error::SymbolError(format!("No symbol or type {}", expr)));
+        }
+    }
+
+    Ok(())
+}
+
+/// Write the PE images found in memory, with a JSON manifest.
+fn cmd_carve_pe(args: &Args)
+-> Result<()> {
+    logger::init("info")?;
+
+    let mut mem = ZdmpPhysicalMemory::open(Path::new(args.positional(1)), args.has_flag("--force"))?;
+    let out_dir = Path::new(args.positional(2));
+
+    let hdr = read_zdmp_hdr(args.positional(1))?;
+    info!("{}", version_line(&hdr, args.has_flag("--force"))?);
+
+    let images = if args.has_flag("--virtual") || args.option("--dtb").is_some() {
+        let list_head = mem.crashdump.ps_loaded_module_list;
+        let mut space = AddressSpace::kernel(mem);
+
+        // Loaded modules name their images.
+        let names: HashMap<u64, String> = modules::list_modules(&mut space, list_head)
+            .unwrap_or_default()
+            .into_iter()
+            .map(|module| (module.base, module.base_name))
+            .collect();
+
+        if let Some(dtb) = args.option("--dtb") {
+            space.dtb = parse_u64(dtb)?;
+        }
+
+        carve::carve_virtual(&mut space, &names, out_dir)?
+    } else {
+        carve::carve_physical(&mut mem, out_dir)?
+    };
+
+    for image in &images {
+        info!("0x{:016x} 0x{:08x} {:<32} {}", image.address, image.size,
+            image.imphash.as_deref().unwrap_or("-"), image.file);
+    }
+    info!("{} images written to {}", images.len(), out_dir.display());
+
+    Ok(())
+}
+
+/// List the active processes.
+fn cmd_processes(args: &Args)
+-> Result<()> {
+    logger::init("warn")?;
+
+    let profiles = match args.option("--profile") {
+        Some(path) => EprocessProfile::load(Path::new(path))?,
+        None => Vec::new(),
+    };
+
+    let mem = ZdmpPhysicalMemory::open(Path::new(args.positional(1)), args.has_flag("--force"))?;
+    let crashdump = mem.crashdump.clone();
+    let mut space = AddressSpace::kernel(mem);
+
+    let (profile, list_head) = match args.option("--symbols") {
+        Some(spec) => {
+            let mut symbols = kernel_symbols(&mut space, &crashdump, spec)?;
+            let list_head = symbols.resolve(&mut space, "nt!PsActiveProcessHead")?;
+            let nt = symbols.module(&mut space, KERNEL_MODULE_ALIAS)?;
+            (EprocessProfile::from_symbols(nt, crashdump.machine_image_type)?, list_head)
+        },
+        None => (EprocessProfile::select(&profiles, &crashdump)?.clone(), crashdump.ps_active_process_head),
+    };
+
+    let processes = list_processes(&mut space, &profile, list_head)?;
+
+    let hdr = read_zdmp_hdr(args.positional(1))?;
+    if args.has_flag("--json") {
+        println!("{}", json_report(&hdr, "processes", &json::array(processes.iter().map(|p| p.to_json()))));
+        return Ok(());
+    }
+
+    println!("{}", version_line(&hdr, args.has_flag("--force"))?);
+    println!("{:<18} {:>6} {:>6} {:<15} {:<23} {:<23} {:<12} {:>7}",
+        "EPROCESS", "PID", "PPID", "Name", "Created", "Exited", "DTB", "Session");
+    for process in &processes {
+        println!("{}", process);
+    }
+
+    Ok(())
+}
+
+/// Scan physical memory for pool allocations with the given tags.
+fn cmd_poolscan(args: &Args)
+-> Result<()> {
+    logger::init("warn")?;
+
+    let tags = poolscan::parse_tags(&args.option("--tags")
+        .map(|tags| tags.to_string())
+        .unwrap_or_else(|| poolscan::DEFAULT_POOL_TAGS.join(",")))?;
+
+    let threads = match args.option("--threads") {
+        Some(n) => parse_u64(n)? as usize,
+        None => thread::available_parallelism().map(|n| n.get()).unwrap_or(1),
+    };
+
+    let hits = poolscan::scan(Path::new(args.positional(1)), args.has_flag("--force"), &tags, threads)?;
+
+    let hdr = read_zdmp_hdr(args.positional(1))?;
+    if args.has_flag("--json") {
+        println!("{}", json_report(&hdr, "hits", &json::array(hits.iter().map(|h| h.to_json()))));
+        return Ok(());
+    }
+
+    println!("{}", version_line(&hdr, args.has_flag("--force"))?);
+    println!("{:<14} {:<7} Tag  Type", "Physical", "Size");
+    for hit in &hits {
+        println!("{}", hit);
+    }
+    println!("{} hits", hits.len());
+
+    Ok(())
+}
+
+/// Compress a raw crash dump into a ZDMP 2.0 file.
+fn cmd_compress(args: &Args)
+-> Result<()> {
+    logger::init("info")?;
+
+    let in_file = args.positional(1);
+    let out_file = args.positional(2);
+
+    let block_size = match args.option("--block-size") {
+        Some(n) => match u32::try_from(parse_u64(n)?) {
+            Ok(block_size) => block_size,
+            Err(_) => panic!("Block size `{}` does not fit in 32 bits\n{}", n, usage(&args.prog)),
+        },
+        None => ZDMP_DEFAULT_BLOCK_SIZE,
+    };
+
+    let acquired_at = match args.option("--acquired-at") {
+        Some(secs) => parse_u64(secs)?,
+        None => SystemTime::now().duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0),
+    };
+
+    let mut metadata = vec![
+        ZdmpMetadata::AcquisitionTime(acquired_at),
+        ZdmpMetadata::ToolVersion(format!("z2dmp {}", env!("CARGO_PKG_VERSION"))),
+    ];
+    if let Some(hostname) = args.option("--hostname") {
+        metadata.push(ZdmpMetadata::Hostname(hostname.to_string()));
+    }
+    if let Some(notes) = args.option("--case-notes") {
+        metadata.push(ZdmpMetadata::CaseNotes(notes.to_string()));
+    }
+
+    info!("Input File:  {}", in_file);
+    info!("Output File: {}", out_file);
+
+    let start_time = Instant::now();
+    let stats = writer::compress_file(
+        Path::new(in_file), Path::new(out_file), block_size, &metadata)?;
+
+    info!("Blocks:                   {}", stats.blocks);
+    info!("Zero blocks:              {}", stats.zero_blocks);
+    info!("Duplicate blocks:         {}", stats.duplicate_blocks);
+    info!("Uncompressed size:        0x{:x}", stats.uncompressed_size);
+    info!("Compressed size:          0x{:x}", stats.compressed_size);
+    info!("Total compression time:   {} secs", start_time.elapsed().as_secs());
+
+    Ok(())
+}
+
+/// Parse a decimal or `0x`-prefixed hexadecimal number.
+fn parse_u64(s: &str)
+-> Result<u64> {
+    match s.strip_prefix("0x") {
+        Some(hex) => Ok(u64::from_str_radix(hex, 16)?),
+        None => Ok(s.parse::<u64>()?),
+    }
+}
+
+/// Decompress a zdmp. The input and output files are the positional
+/// arguments from `first`.
+fn cmd_convert(args: &Args, first: usize)
 -> Result<()> {
     // Log-level (default: info).
     let log_level = "info".to_string();
-    
-    let _log = logger::init(&log_level);
 
-    let args: Vec<String> = env::args().collect();
-    if args.len() < 3 {
-        panic!("Usage: {} <input_file> <output_file>", args[0]);
+    logger::init(&log_level)?;
+
+    let in_file = args.positional(first);
+    let out_file = args.positional(first + 1);
+
+    if !matches!(args.option("--format"), None | Some("dmp")) {
+        let hdr = read_zdmp_hdr(in_file)?;
+        info!("{}", version_line(&hdr, args.has_flag("--force"))?);
     }
 
-    let in_file = &args[1];
-    let out_file = &args[2];
+    match args.option("--format") {
+        None | Some("dmp") => (),
+        Some("raw") => return cmd_export_raw(args, in_file, out_file),
+        Some("bitmap-dmp") => return cmd_export_bitmap_dmp(args, in_file, out_file),
+        Some("lime") => return cmd_export_lime(args, in_file, out_file),
+        Some("elf") => return cmd_export_elf(args, in_file, out_file),
+        Some("aff4") => return cmd_export_aff4(args, in_file, out_file),
+        Some("ewf") => return cmd_export_ewf(args, in_file, out_file),
+        Some("dmp.zst") => return cmd_export_dmp_zst(args, in_file, out_file),
+        Some(format) => return Err(Error::IoError(
+            format!("Unexpected output format: `{}`", format))),
+    }
 
     // debug mode.
-    let mut silent_mode = false; 
+    let silent_mode = false;
     /*
     if args.len() > 3 {
         silent_mode = true;
         info!("Silent:  {}", silent_mode);
     }
     */
-    
+
     info!("Input File:  {}", in_file);
     info!("Output File: {}", out_file);
 
-    let zdmp_file = zdmp::ZdmpFile::new(Path::new(in_file), Path::new(out_file), silent_mode)?;
+    let zdmp_file = zdmp::ZdmpFile::new(Path::new(in_file), Path::new(out_file),
+        silent_mode, args.has_flag("--force"))?;
 
     let total_time = zdmp_file.finish_time - zdmp_file.start_time;
 
+    info!("Zdmp version:             0x{:x} ({})", { zdmp_file.hdr.version }, zdmp_file.hdr.version_name());
     info!("Expected file size:       0x{:x}", zdmp_file.file_size);
     info!("Current file size:        0x{:x}", zdmp_file.uncompressed_size);
     info!("Total decompression time: {} secs", total_time.as_secs());
@@ -44,3 +585,163 @@ fn main()
 
     Ok(())
 }
+
+/// Write the physical memory runs of the embedded crash dump as a flat image.
+fn cmd_export_raw(args: &Args, in_file: &str, out_file: &str)
+-> Result<()> {
+    info!("Input File:  {}", in_file);
+    info!("Output File: {}", out_file);
+
+    let start_time = Instant::now();
+    let stats = export::export_raw(Path::new(in_file), Path::new(out_file),
+        args.has_flag("--force"))?;
+
+    info!("Physical memory runs:     {}", stats.runs);
+    info!("Bytes written:            0x{:x}", stats.bytes_written);
+    info!("Image size:               0x{:x}", stats.image_size);
+    info!("Total export time:        {} secs", start_time.elapsed().as_secs());
+
+    Ok(())
+}
+
+/// Write the non-zero pages of the embedded crash dump as a full bitmap dump.
+fn cmd_export_bitmap_dmp(args: &Args, in_file: &str, out_file: &str)
+-> Result<()> {
+    info!("Input File:  {}", in_file);
+    info!("Output File: {}", out_file);
+
+    let start_time = Instant::now();
+    let stats = export::export_bitmap_dmp(Path::new(in_file), Path::new(out_file),
+        args.has_flag("--force"))?;
+
+    info!("Bitmap pages:             {}", stats.pages);
+    info!("Present pages:            {}", stats.present_pages);
+    info!("Zero pages dropped:       {}", stats.zero_pages);
+    info!("File size:                0x{:x}", stats.file_size);
+    info!("Total export time:        {} secs", start_time.elapsed().as_secs());
+
+    Ok(())
+}
+
+/// Write the physical memory runs of the embedded crash dump as LiME ranges.
+fn cmd_export_lime(args: &Args, in_file: &str, out_file: &str)
+-> Result<()> {
+    info!("Input File:  {}", in_file);
+    info!("Output File: {}", out_file);
+
+    let start_time = Instant::now();
+    let stats = export::export_lime(Path::new(in_file), Path::new(out_file),
+        args.has_flag("--force"))?;
+
+    info!("LiME ranges:              {}", stats.ranges);
+    info!("Bytes written:            0x{:x}", stats.bytes_written);
+    info!("Total export time:        {} secs", start_time.elapsed().as_secs());
+
+    Ok(())
+}
+
+/// Write the physical memory runs of the embedded crash dump as an ELF core.
+fn cmd_export_elf(args: &Args, in_file: &str, out_file: &str)
+-> Result<()> {
+    info!("Input File:  {}", in_file);
+    info!("Output File: {}", out_file);
+
+    let start_time = Instant::now();
+    let stats = export::export_elf(Path::new(in_file), Path::new(out_file),
+        args.has_flag("--force"), args.has_flag("--notes"))?;
+
+    info!("Program headers:          {}", stats.segments);
+    info!("Bytes written:            0x{:x}", stats.bytes_written);
+    info!("File size:                0x{:x}", stats.file_size);
+    info!("Total export time:        {} secs", start_time.elapsed().as_secs());
+
+    Ok(())
+}
+
+/// Write the embedded crash dump as an AFF4 memory image, then read it back
+/// to check its hashes.
+fn cmd_export_aff4(args: &Args, in_file: &str, out_file: &str)
+-> Result<()> {
+    info!("Input File:  {}", in_file);
+    info!("Output File: {}", out_file);
+
+    let start_time = Instant::now();
+    let summary = export::export_aff4(Path::new(in_file), Path::new(out_file),
+        args.has_flag("--force"))?;
+
+    info!("Volume:                   {}", summary.volume_urn);
+    info!("Map:                      {}", summary.map_urn);
+    info!("Image stream:             {}", summary.image_urn);
+    info!("Stream size:              0x{:x}", summary.stream_size);
+    info!("MD5:                      {}", summary.md5);
+    info!("SHA1:                     {}", summary.sha1);
+
+    let mut image = Aff4Image::open(Path::new(out_file))?;
+    for (algorithm, ok) in image.verify()? {
+        if !ok {
+            return Err(Error::ContainerError(format!("{} mismatch reading back {}", algorithm, out_file)));
+        }
+        info!("{} verified.", algorithm);
+    }
+    info!("Total export time:        {} secs", start_time.elapsed().as_secs());
+
+    Ok(())
+}
+
+/// Write the decoded crash dump in the zstd seekable format, then read it
+/// back to check the frames.
+fn cmd_export_dmp_zst(args: &Args, in_file: &str, out_file: &str)
+-> Result<()> {
+    info!("Input File:  {}", in_file);
+    info!("Output File: {}", out_file);
+
+    let start_time = Instant::now();
+    let summary = export::export_dmp_zst(Path::new(in_file), Path::new(out_file),
+        args.has_flag("--force"))?;
+
+    info!("Frames:                   {}", summary.frames);
+    info!("Decompressed size:        0x{:x}", summary.decompressed_size);
+    info!("File size:                0x{:x}", summary.file_size);
+
+    let mut reader = SeekableReader::open(Path::new(out_file))?;
+    if reader.decompressed_size() != summary.decompressed_size {
+        return Err(Error::ContainerError(format!("Size mismatch reading back {}", out_file)));
+    }
+    reader.verify()?;
+    info!("Frame checksums verified.");
+    info!("Total export time:        {} secs", start_time.elapsed().as_secs());
+
+    Ok(())
+}
+
+/// Write the decoded crash dump as an EWF (E01) image with the case fields
+/// of the command line.
+fn cmd_export_ewf(args: &Args, in_file: &str, out_file: &str)
+-> Result<()> {
+    info!("Input File:  {}", in_file);
+    info!("Output File: {}", out_file);
+
+    let text = |name: &str| args.option(name).unwrap_or("").to_string();
+    let case = EwfCaseInfo {
+        case_number: text("--case-number"),
+        evidence_number: text("--evidence-number"),
+        description: text("--description"),
+        examiner: text("--examiner"),
+        notes: text("--case-notes"),
+        os_version: String::new(),
+        acquisition_time: args.option("--acquired-at").map(parse_u64).transpose()?,
+    };
+
+    let start_time = Instant::now();
+    let summary = export::export_ewf(Path::new(in_file), Path::new(out_file),
+        args.has_flag("--force"), &case)?;
+
+    info!("Chunks:                   {}", summary.chunks);
+    info!("Media size:               0x{:x}", summary.media_size);
+    info!("File size:                0x{:x}", summary.file_size);
+    info!("MD5:                      {}", summary.md5);
+    info!("SHA1:                     {}", summary.sha1);
+    info!("Total export time:        {} secs", start_time.elapsed().as_secs());
+
+    Ok(())
+}
@@ -0,0 +1,143 @@
+use std::fmt;
+
+use crate::crashdump::format_unix_time;
+use crate::json::JsonObject;
+use crate::paging::AddressSpace;
+use crate::pe::PeHeader;
+use crate::physmem::PhysicalMemory;
+use crate::result::Result;
+use crate::symbols::ModuleSymbols;
+use crate::zdmp::PAGE_SIZE;
+
+/// Offsets in `_KLDR_DATA_TABLE_ENTRY`.
+#[derive(Debug, Copy, Clone)]
+pub struct KldrLayout {
+    pub in_load_order_links:    u64,
+    pub dll_base:               u64,
+    pub size_of_image:          u64,
+    pub full_dll_name:          u64,
+    pub base_dll_name:          u64,
+}
+
+pub const KLDR_LAYOUT_32: KldrLayout = KldrLayout {
+    in_load_order_links: 0, dll_base: 0x18, size_of_image: 0x20, full_dll_name: 0x24, base_dll_name: 0x2c,
+};
+pub const KLDR_LAYOUT_64: KldrLayout = KldrLayout {
+    in_load_order_links: 0, dll_base: 0x30, size_of_image: 0x40, full_dll_name: 0x48, base_dll_name: 0x58,
+};
+
+impl KldrLayout {
+    /// Layout from the kernel symbols.
+    pub fn from_symbols(nt: &ModuleSymbols) -> Result<Self> {
+        let field = |name| nt.field_offset("_KLDR_DATA_TABLE_ENTRY", name);
+
+        Ok(KldrLayout {
+            in_load_order_links: field("InLoadOrderLinks")?,
+            dll_base: field("DllBase")?,
+            size_of_image: field("SizeOfImage")?,
+            full_dll_name: field("FullDllName")?,
+            base_dll_name: field("BaseDllName")?,
+        })
+    }
+}
+
+/// A loaded kernel module.
+#[derive(Debug, Clone)]
+pub struct KernelModule {
+    /// Address of the `_KLDR_DATA_TABLE_ENTRY`.
+    pub entry:      u64,
+    pub base:       u64,
+    pub size:       u32,
+    pub full_name:  String,
+    pub base_name:  String,
+    /// From the PE header, when it is in the dump.
+    pub timestamp:  Option<u32>,
+    pub checksum:   Option<u32>,
+}
+
+impl KernelModule {
+    pub fn to_json(&self) -> String {
+        JsonObject::new()
+            .hex("base", self.base)
+            .hex("size", self.size as u64)
+            .str("name", &self.base_name)
+            .str("path", &self.full_name)
+            .raw("timestamp", self.timestamp.map(|t| t.to_string()).as_deref())
+            .raw("checksum", self.checksum.map(|c| c.to_string()).as_deref())
+            .hex("entry", self.entry)
+            .finish()
+    }
+}
+
+impl fmt::Display for KernelModule {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let timestamp = match self.timestamp {
+            Some(t) => format!("0x{:08x} {}", t, format_unix_time(t as u64)),
+            None => "-".to_string(),
+        };
+        let checksum = match self.checksum {
+            Some(c) => format!("0x{:08x}", c),
+            None => "-".to_string(),
+        };
+
+        write!(f, "0x{:016x} 0x{:08x} {:<35} {:<10} {}",
+            self.base, self.size, timestamp, checksum, self.full_name)
+    }
+}
+
+/// Walk the `_KLDR_DATA_TABLE_ENTRY` list at `list_head`, with the usual
+/// layout of the pointer size.
+///
+/// The walk stops at the first unreadable entry or link, with a warning.
+pub fn list_modules<M: PhysicalMemory>(
+    space: &mut AddressSpace<M>,
+    list_head: u64
+) -> Result<Vec<KernelModule>> {
+    let layout = match space.pointer_size() {
+        4 => KLDR_LAYOUT_32,
+        _ => KLDR_LAYOUT_64,
+    };
+
+    list_modules_with_layout(space, list_head, &layout)
+}
+
+pub fn list_modules_with_layout<M: PhysicalMemory>(
+    space: &mut AddressSpace<M>,
+    list_head: u64,
+    layout: &KldrLayout
+) -> Result<Vec<KernelModule>> {
+    let mut modules = Vec::new();
+    for links in space.list_entries(list_head)? {
+        let entry = links.wrapping_sub(layout.in_load_order_links);
+        match read_module(space, layout, entry) {
+            Ok(module) => modules.push(module),
+            Err(e) => {
+                warn!("Unreadable module entry @ 0x{:x}: {:?}", entry, e);
+                break;
+            },
+        }
+    }
+
+    Ok(modules)
+}
+
+fn read_module<M: PhysicalMemory>(
+    space: &mut AddressSpace<M>,
+    layout: &KldrLayout,
+    entry: u64
+) -> Result<KernelModule> {
+    let base = space.read_ptr(entry + layout.dll_base)?;
+    let size = space.read_u32(entry + layout.size_of_image)?;
+    let full_name = space.read_unicode_string(entry + layout.full_dll_name).unwrap_or_default();
+    let base_name = space.read_unicode_string(entry + layout.base_dll_name).unwrap_or_default();
+
+    let mut page = vec![0u8; PAGE_SIZE];
+    let pe = space.read_virt(base, &mut page).ok()
+        .and_then(|_| PeHeader::parse(&page).ok());
+
+    Ok(KernelModule {
+        entry, base, size, full_name, base_name,
+        timestamp: pe.map(|pe| pe.time_date_stamp),
+        checksum: pe.map(|pe| pe.checksum),
+    })
+}
@@ -0,0 +1,582 @@
+use std::collections::HashSet;
+use std::fmt;
+use std::io::{Read, Seek};
+
+use crate::physmem::{PhysicalMemory, ZdmpPhysicalMemory};
+use crate::result::{Result, Error};
+
+pub const PTE_VALID:            u64 = 1 << 0;
+pub const PTE_WRITE:            u64 = 1 << 1;
+pub const PTE_OWNER:            u64 = 1 << 2;
+pub const PTE_WRITE_THROUGH:    u64 = 1 << 3;
+pub const PTE_CACHE_DISABLE:    u64 = 1 << 4;
+pub const PTE_ACCESSED:         u64 = 1 << 5;
+pub const PTE_DIRTY:            u64 = 1 << 6;
+pub const PTE_LARGE_PAGE:       u64 = 1 << 7;
+pub const PTE_GLOBAL:           u64 = 1 << 8;
+pub const PTE_COPY_ON_WRITE:    u64 = 1 << 9;
+pub const PTE_PROTOTYPE:        u64 = 1 << 10;
+pub const PTE_TRANSITION:       u64 = 1 << 11;
+pub const PTE_NO_EXECUTE:       u64 = 1 << 63;
+
+/// Physical address bits of an entry.
+pub const PTE_PFN_MASK:         u64 = 0x000f_ffff_ffff_f000;
+
+/// Largest number of `LIST_ENTRY` links followed.
+pub const MAX_LIST_ENTRIES: usize = 0x10000;
+
+/// Largest `UNICODE_STRING` read, in bytes.
+pub const MAX_UNICODE_STRING_SIZE: usize = 0x1000;
+
+/// ARM64 descriptor bits, 4 KB granule.
+pub const ARM64_DESC_VALID:     u64 = 1 << 0;
+/// Table (or page at level 3) rather than block.
+pub const ARM64_DESC_TABLE:     u64 = 1 << 1;
+pub const ARM64_DESC_AP_SHIFT:  u32 = 6;
+pub const ARM64_DESC_AF:        u64 = 1 << 10;
+pub const ARM64_DESC_NG:        u64 = 1 << 11;
+pub const ARM64_DESC_PXN:       u64 = 1 << 53;
+pub const ARM64_DESC_UXN:       u64 = 1 << 54;
+
+/// Output address bits of an ARM64 descriptor.
+pub const ARM64_DESC_ADDR_MASK: u64 = 0x0000_ffff_ffff_f000;
+
+/// Paging structures used to translate virtual addresses.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum PagingMode {
+    /// x86 2-level paging with 4-byte entries.
+    X86,
+    /// x86 3-level PAE paging.
+    X86Pae,
+    /// x64 4-level paging.
+    X64,
+    /// x64 5-level paging (LA57).
+    X64La57,
+    /// ARM64 4-level translation with a 4 KB granule and 48-bit addresses.
+    Arm64,
+}
+
+/// One level of the paging structures.
+struct Level {
+    name:       &'static str,
+    /// Lowest virtual address bit of the index.
+    shift:      u32,
+    index_bits: u32,
+    /// Whether the large page bit maps a page at this level.
+    large:      bool,
+}
+
+const fn level(name: &'static str, shift: u32, index_bits: u32, large: bool) -> Level {
+    Level { name, shift, index_bits, large }
+}
+
+const LEVELS_X86: &[Level] = &[
+    level("PDE", 22, 10, true), level("PTE", 12, 10, false),
+];
+const LEVELS_X86_PAE: &[Level] = &[
+    level("PDPTE", 30, 2, false), level("PDE", 21, 9, true), level("PTE", 12, 9, false),
+];
+const LEVELS_X64: &[Level] = &[
+    level("PXE", 39, 9, false), level("PPE", 30, 9, true),
+    level("PDE", 21, 9, true), level("PTE", 12, 9, false),
+];
+const LEVELS_X64_LA57: &[Level] = &[
+    level("PML5E", 48, 9, false), level("PXE", 39, 9, false), level("PPE", 30, 9, true),
+    level("PDE", 21, 9, true), level("PTE", 12, 9, false),
+];
+const LEVELS_ARM64: &[Level] = &[
+    level("L0", 39, 9, false), level("L1", 30, 9, true),
+    level("L2", 21, 9, true), level("L3", 12, 9, false),
+];
+
+impl PagingMode {
+    /// Table levels, from the root.
+    fn levels(&self) -> &'static [Level] {
+        match self {
+            PagingMode::X86 => LEVELS_X86,
+            PagingMode::X86Pae => LEVELS_X86_PAE,
+            PagingMode::X64 => LEVELS_X64,
+            PagingMode::X64La57 => LEVELS_X64_LA57,
+            PagingMode::Arm64 => LEVELS_ARM64,
+        }
+    }
+
+    /// Output address bits of an entry.
+    fn addr_mask(&self) -> u64 {
+        match self {
+            PagingMode::Arm64 => ARM64_DESC_ADDR_MASK,
+            _ => PTE_PFN_MASK,
+        }
+    }
+
+    /// Whether `entry` maps a table or a page. ARM64 level 3 descriptors
+    /// also need the page bit.
+    fn is_valid(&self, entry: u64, last: bool) -> bool {
+        match self {
+            PagingMode::Arm64 => entry & ARM64_DESC_VALID != 0
+                && (!last || entry & ARM64_DESC_TABLE != 0),
+            _ => entry & PTE_VALID != 0,
+        }
+    }
+
+    /// Whether a valid entry at a level that allows it maps a large page
+    /// (an ARM64 block) rather than a table.
+    fn is_large(&self, entry: u64) -> bool {
+        match self {
+            PagingMode::Arm64 => entry & ARM64_DESC_TABLE == 0,
+            _ => entry & PTE_LARGE_PAGE != 0,
+        }
+    }
+
+    /// Size of a paging structure entry.
+    fn entry_size(&self) -> u64 {
+        match self {
+            PagingMode::X86 => 4,
+            _ => 8,
+        }
+    }
+
+    /// Physical address of the root table in a DTB (CR3).
+    fn root(&self, dtb: u64) -> u64 {
+        match self {
+            PagingMode::X86 => dtb & 0xffff_f000,
+            PagingMode::X86Pae => dtb & 0xffff_ffe0,
+            // The TTBR ASID is in the top 16 bits.
+            PagingMode::Arm64 => dtb & ARM64_DESC_ADDR_MASK,
+            _ => dtb & PTE_PFN_MASK,
+        }
+    }
+
+    /// Whether `va` is a valid address for this mode: 32-bit or sign-extended
+    /// from 32 bits on x86, canonical on x64, in the TTBR0 or TTBR1 range on
+    /// ARM64.
+    fn is_canonical(&self, va: u64) -> bool {
+        let va_bits = match self {
+            PagingMode::X86 | PagingMode::X86Pae => return va >> 32 == 0 || va >> 31 == 0x1_ffff_ffff,
+            PagingMode::X64 | PagingMode::Arm64 => 48,
+            PagingMode::X64La57 => 57,
+        };
+
+        let high = (va as i64 >> (va_bits - 1)) as u64;
+        high == 0 || high == u64::MAX
+    }
+
+    /// Canonical form of a virtual address built from table indexes.
+    fn sign_extend(&self, va: u64) -> u64 {
+        let va_bits = match self {
+            PagingMode::X86 | PagingMode::X86Pae => return va,
+            PagingMode::X64 | PagingMode::Arm64 => 48,
+            PagingMode::X64La57 => 57,
+        };
+
+        (((va << (64 - va_bits)) as i64) >> (64 - va_bits)) as u64
+    }
+
+    /// Decode the state of an entry that does not have the valid bit.
+    ///
+    /// The software layout of invalid ARM64 entries is not decoded.
+    fn invalid_state(&self, entry: u64) -> PteState {
+        if *self == PagingMode::Arm64 {
+            return match entry {
+                0 => PteState::NotPresent,
+                entry => PteState::Invalid { entry },
+            };
+        }
+
+        // Non-PAE x86 keeps the page file offset in the PFN bits.
+        let page_file_high = match self {
+            PagingMode::X86 => entry >> 12,
+            _ => entry >> 32,
+        };
+
+        if entry == 0 {
+            PteState::NotPresent
+        } else if entry & PTE_PROTOTYPE != 0 {
+            PteState::Prototype { entry }
+        } else if entry & PTE_TRANSITION != 0 {
+            PteState::Transition { phys: entry & PTE_PFN_MASK }
+        } else if page_file_high == 0 {
+            PteState::DemandZero
+        } else {
+            PteState::PageFile { number: (entry >> 1) & 0xf, offset: page_file_high << 12 }
+        }
+    }
+}
+
+/// One paging structure entry read during a walk.
+#[derive(Debug, Copy, Clone)]
+pub struct PageTableStep {
+    pub level:      &'static str,
+    /// Physical address of the entry.
+    pub entry_addr: u64,
+    pub entry:      u64,
+}
+
+/// Final state of a translation.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum PteState {
+    Valid { phys: u64, page_size: u64 },
+    /// Page on the standby or modified list, still in memory.
+    Transition { phys: u64 },
+    /// Refers to a prototype PTE, which is not followed.
+    Prototype { entry: u64 },
+    PageFile { number: u64, offset: u64 },
+    DemandZero,
+    /// Invalid entry whose software state is not decoded (ARM64).
+    Invalid { entry: u64 },
+    NotPresent,
+    NonCanonical,
+}
+
+/// Walk of a virtual address through the paging structures.
+#[derive(Debug, Clone)]
+pub struct Translation {
+    pub mode:   PagingMode,
+    pub va:     u64,
+    pub steps:  Vec<PageTableStep>,
+    pub state:  PteState,
+}
+
+impl Translation {
+    /// Physical address of `va`, when its page is in memory.
+    ///
+    /// Transition pages count as present, their contents are still valid.
+    pub fn physical_address(&self) -> Option<u64> {
+        match self.state {
+            PteState::Valid { phys, .. } | PteState::Transition { phys } => Some(phys),
+            _ => None,
+        }
+    }
+}
+
+/// Virtually and physically contiguous valid pages.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Mapping {
+    pub va:     u64,
+    pub phys:   u64,
+    pub size:   u64,
+}
+
+/// Virtual address space over a physical memory, rooted at a DTB (CR3).
+pub struct AddressSpace<M> {
+    pub mem:    M,
+    pub dtb:    u64,
+    pub mode:   PagingMode,
+}
+
+impl<R: Read + Seek> AddressSpace<ZdmpPhysicalMemory<R>> {
+    /// Kernel address space of the crash dump in a zdmp.
+    pub fn kernel(mem: ZdmpPhysicalMemory<R>) -> Self {
+        let dtb = mem.crashdump.directory_table_base;
+        let mode = mem.crashdump.paging_mode();
+        AddressSpace::new(mem, dtb, mode)
+    }
+}
+
+impl<M: PhysicalMemory> AddressSpace<M> {
+    pub fn new(mem: M, dtb: u64, mode: PagingMode) -> Self {
+        AddressSpace { mem, dtb, mode }
+    }
+
+    /// Size of a virtual address.
+    pub fn pointer_size(&self) -> usize {
+        match self.mode {
+            PagingMode::X86 | PagingMode::X86Pae => 4,
+            _ => 8,
+        }
+    }
+
+    fn read_entry(&mut self, addr: u64) -> Result<u64> {
+        let mut buf = [0u8; 8];
+        let size = self.mode.entry_size() as usize;
+        self.mem.read_phys(addr, &mut buf[..size])?;
+        Ok(u64::from_le_bytes(buf))
+    }
+
+    /// Walk the paging structures for `va`.
+    ///
+    /// Entries that are not valid end the walk with their decoded state.
+    /// Only unreadable paging structures are errors.
+    pub fn translate(&mut self, va: u64) -> Result<Translation> {
+        let mut walk = Translation {
+            mode: self.mode, va, steps: Vec::new(), state: PteState::NonCanonical,
+        };
+
+        if !self.mode.is_canonical(va) {
+            return Ok(walk);
+        }
+
+        let levels = self.mode.levels();
+        let mut table = self.mode.root(self.dtb);
+
+        for (depth, level) in levels.iter().enumerate() {
+            let index = (va >> level.shift) & ((1 << level.index_bits) - 1);
+            let entry_addr = table + index * self.mode.entry_size();
+            let entry = self.read_entry(entry_addr)?;
+            walk.steps.push(PageTableStep { level: level.name, entry_addr, entry });
+
+            let last = depth == levels.len() - 1;
+
+            if !self.mode.is_valid(entry, last) {
+                walk.state = match self.mode.invalid_state(entry) {
+                    // A paging structure in transition is still in memory.
+                    PteState::Transition { phys } if !last => {
+                        table = phys;
+                        continue;
+                    },
+                    PteState::Transition { phys } => PteState::Transition { phys: phys | (va & 0xfff) },
+                    state => state,
+                };
+                return Ok(walk);
+            }
+
+            // PAE PDPTEs have no access or large page bits.
+            if last || (level.large && self.mode.is_large(entry)) {
+                let page_size = 1u64 << level.shift;
+                let base = entry & self.mode.addr_mask() & !(page_size - 1);
+                walk.state = PteState::Valid { phys: base | (va & (page_size - 1)), page_size };
+                return Ok(walk);
+            }
+
+            table = entry & self.mode.addr_mask();
+        }
+
+        Ok(walk)
+    }
+
+    /// Physical address of `va`.
+    pub fn virt_to_phys(&mut self, va: u64) -> Result<u64> {
+        self.translate(va)?
+            .physical_address()
+            .ok_or(Error::VirtualAddressNotValid(va))
+    }
+
+    /// Fill `buf` with the bytes at virtual address `va`.
+    pub fn read_virt(&mut self, va: u64, buf: &mut [u8]) -> Result<()> {
+        let mut done = 0;
+
+        while done < buf.len() {
+            let pos = va.wrapping_add(done as u64);
+            let phys = self.virt_to_phys(pos)?;
+
+            let len = (0x1000 - (pos & 0xfff) as usize).min(buf.len() - done);
+            self.mem.read_phys(phys, &mut buf[done..done + len])?;
+            done += len;
+        }
+
+        Ok(())
+    }
+
+    pub fn read_u16(&mut self, va: u64) -> Result<u16> {
+        let mut buf = [0u8; 2];
+        self.read_virt(va, &mut buf)?;
+        Ok(u16::from_le_bytes(buf))
+    }
+
+    pub fn read_u32(&mut self, va: u64) -> Result<u32> {
+        let mut buf = [0u8; 4];
+        self.read_virt(va, &mut buf)?;
+        Ok(u32::from_le_bytes(buf))
+    }
+
+    pub fn read_u64(&mut self, va: u64) -> Result<u64> {
+        let mut buf = [0u8; 8];
+        self.read_virt(va, &mut buf)?;
+        Ok(u64::from_le_bytes(buf))
+    }
+
+    /// Read a pointer of the size of the address space.
+    pub fn read_ptr(&mut self, va: u64) -> Result<u64> {
+        match self.pointer_size() {
+            4 => self.read_u32(va).map(|ptr| ptr as u64),
+            _ => self.read_u64(va),
+        }
+    }
+
+    /// Valid pages of the address space, in virtual address order.
+    ///
+    /// Unreadable paging structures and pages that are not valid (transition,
+    /// paged out...) are skipped.
+    pub fn mappings(&mut self) -> Result<Vec<Mapping>> {
+        let mut mappings = Vec::new();
+        let root = self.mode.root(self.dtb);
+        self.walk_tables(0, root, 0, &mut mappings);
+
+        Ok(mappings)
+    }
+
+    fn walk_tables(&mut self, depth: usize, table: u64, va: u64, out: &mut Vec<Mapping>) {
+        let levels = self.mode.levels();
+        let level = &levels[depth];
+        let last = depth == levels.len() - 1;
+        let entry_size = self.mode.entry_size() as usize;
+
+        let mut buf = vec![0u8; entry_size << level.index_bits];
+        if let Err(e) = self.mem.read_phys(table, &mut buf) {
+            debug!("Unreadable {} table @ 0x{:x}: {:?}", level.name, table, e);
+            return;
+        }
+
+        for (index, raw) in buf.chunks_exact(entry_size).enumerate() {
+            let mut bytes = [0u8; 8];
+            bytes[..entry_size].copy_from_slice(raw);
+            let entry = u64::from_le_bytes(bytes);
+
+            if !self.mode.is_valid(entry, last) {
+                continue;
+            }
+
+            let entry_va = self.mode.sign_extend(va | (index as u64) << level.shift);
+
+            if last || (level.large && self.mode.is_large(entry)) {
+                let size = 1u64 << level.shift;
+                let phys = entry & self.mode.addr_mask() & !(size - 1);
+
+                match out.last_mut() {
+                    Some(prev) if prev.va + prev.size == entry_va && prev.phys + prev.size == phys =>
+                        prev.size += size,
+                    _ => out.push(Mapping { va: entry_va, phys, size }),
+                }
+            } else {
+                self.walk_tables(depth + 1, entry & self.mode.addr_mask(), entry_va, out);
+            }
+        }
+    }
+
+    /// Flink addresses of the `LIST_ENTRY` list at `list_head`.
+    ///
+    /// The walk stops at the first unreadable link or loop, with a warning.
+    pub fn list_entries(&mut self, list_head: u64) -> Result<Vec<u64>> {
+        let mut entries = Vec::new();
+        let mut seen = HashSet::new();
+        let mut entry = self.read_ptr(list_head)?;
+
+        while entry != list_head {
+            if !seen.insert(entry) || entries.len() >= MAX_LIST_ENTRIES {
+                warn!("List @ 0x{:x} loops or is too long, stopping at 0x{:x}", list_head, entry);
+                break;
+            }
+            entries.push(entry);
+
+            entry = match self.read_ptr(entry) {
+                Ok(next) => next,
+                Err(e) => {
+                    warn!("Unreadable list link @ 0x{:x}: {:?}", entry, e);
+                    break;
+                },
+            };
+        }
+
+        Ok(entries)
+    }
+
+    /// Read the `UNICODE_STRING` at `va`.
+    pub fn read_unicode_string(&mut self, va: u64) -> Result<String> {
+        let len = self.read_u16(va)? as usize;
+        let buffer = self.read_ptr(va + self.pointer_size() as u64)?;
+
+        if len > MAX_UNICODE_STRING_SIZE {
+            return Err(Error::DumpParseError(
+                format!("Unexpected UNICODE_STRING length 0x{:x} @ 0x{:x}", len, va)));
+        }
+
+        let mut buf = vec![0u8; len & !1];
+        self.read_virt(buffer, &mut buf)?;
+
+        let chars: Vec<u16> = buf.chunks_exact(2)
+            .map(|c| u16::from_le_bytes([c[0], c[1]]))
+            .collect();
+        Ok(String::from_utf16_lossy(&chars))
+    }
+}
+
+/// `!pte`-style flags of a valid entry.
+fn entry_flags(entry: u64) -> String {
+    let flag = |bit: u64, set: char, clear: char| if entry & bit != 0 { set } else { clear };
+
+    [
+        flag(PTE_COPY_ON_WRITE, 'C', '-'),
+        flag(PTE_GLOBAL, 'G', '-'),
+        flag(PTE_LARGE_PAGE, 'L', '-'),
+        flag(PTE_DIRTY, 'D', '-'),
+        flag(PTE_ACCESSED, 'A', '-'),
+        flag(PTE_CACHE_DISABLE, 'N', '-'),
+        flag(PTE_WRITE_THROUGH, 'T', '-'),
+        flag(PTE_OWNER, 'U', 'K'),
+        flag(PTE_WRITE, 'W', 'R'),
+        flag(PTE_NO_EXECUTE, '-', 'E'),
+        flag(PTE_VALID, 'V', '-'),
+    ].iter().collect()
+}
+
+/// Access permissions and attributes of an ARM64 page or block descriptor.
+fn arm64_flags(entry: u64) -> String {
+    let flag = |bit: u64, set: &'static str| if entry & bit != 0 { set } else { "-" };
+
+    let ap = match (entry >> ARM64_DESC_AP_SHIFT) & 0b11 {
+        0b00 => "EL1:RW EL0:--",
+        0b01 => "EL1:RW EL0:RW",
+        0b10 => "EL1:R- EL0:--",
+        _ => "EL1:R- EL0:R-",
+    };
+
+    [ap, flag(ARM64_DESC_AF, "AF"), flag(ARM64_DESC_NG, "nG"),
+        flag(ARM64_DESC_PXN, "PXN"), flag(ARM64_DESC_UXN, "UXN")].join(" ")
+}
+
+impl fmt::Display for Translation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "VA {:016x}", self.va)?;
+
+        for (i, step) in self.steps.iter().enumerate() {
+            write!(f, "\n{:<5} at {:012x}  contains {:016x}", step.level, step.entry_addr, step.entry)?;
+
+            // Only the last entry can be an invalid or transition one.
+            let leaf = i == self.steps.len() - 1;
+            let valid = match leaf {
+                true => matches!(self.state, PteState::Valid { .. }),
+                false => self.mode.is_valid(step.entry, false),
+            };
+            if !valid {
+                continue;
+            }
+
+            let pfn = (step.entry & self.mode.addr_mask()) >> 12;
+            match (self.mode, leaf) {
+                (PagingMode::Arm64, true) => write!(f, "  pfn {:x} {}", pfn, arm64_flags(step.entry))?,
+                (PagingMode::Arm64, false) => write!(f, "  pfn {:x} table", pfn)?,
+                _ => write!(f, "  pfn {:x} {}", pfn, entry_flags(step.entry))?,
+            }
+        }
+
+        match self.state {
+            PteState::Valid { phys, page_size } =>
+                write!(f, "\nValid, physical address {:x} (page size 0x{:x})", phys, page_size),
+            PteState::Transition { phys } =>
+                write!(f, "\nTransition, physical address {:x}", phys),
+            PteState::Prototype { entry } =>
+                write!(f, "\nPrototype PTE {:x}", entry),
+            PteState::PageFile { number, offset } =>
+                write!(f, "\nNot present, page file {} offset {:x}", number, offset),
+            PteState::DemandZero => write!(f, "\nDemand zero"),
+            PteState::Invalid { entry } => write!(f, "\nInvalid entry {:x}", entry),
+            PteState::NotPresent => write!(f, "\nNot present"),
+            PteState::NonCanonical => write!(f, "\nAddress out of the virtual address space"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn arm64_invalid_entries_are_not_decoded_as_x64() {
+        // Page file entry in the x64 software layout.
+        let entry = 0x0000_1234_0000_0082;
+        assert_eq!(PagingMode::X64.invalid_state(entry),
+            PteState::PageFile { number: 1, offset: 0x1234000 });
+        assert_eq!(PagingMode::Arm64.invalid_state(entry), PteState::Invalid { entry });
+        assert_eq!(PagingMode::Arm64.invalid_state(0), PteState::NotPresent);
+    }
+}
@@ -0,0 +1,288 @@
+use crate::result::{Result, Error};
+
+pub const IMAGE_DOS_SIGNATURE:      u16 = 0x5a4d;       // MZ
+pub const IMAGE_NT_SIGNATURE:       u32 = 0x0000_4550;  // PE\0\0
+
+pub const IMAGE_NT_OPTIONAL_HDR32_MAGIC: u16 = 0x10b;
+pub const IMAGE_NT_OPTIONAL_HDR64_MAGIC: u16 = 0x20b;
+
+pub const IMAGE_FILE_DLL:                u16 = 0x2000;
+pub const IMAGE_SUBSYSTEM_NATIVE:        u16 = 1;
+pub const IMAGE_DIRECTORY_ENTRY_IMPORT:  usize = 1;
+pub const IMAGE_DIRECTORY_ENTRY_DEBUG:   usize = 6;
+pub const IMAGE_DEBUG_TYPE_CODEVIEW:     u32 = 2;
+pub const CV_SIGNATURE_RSDS:             u32 = 0x5344_5352;  // SDSR
+
+pub const IMAGE_SIZEOF_SECTION_HEADER:   usize = 40;
+pub const IMAGE_SIZEOF_IMPORT_DESCRIPTOR: usize = 20;
+pub const IMAGE_SIZEOF_DEBUG_DIRECTORY:  usize = 28;
+
+/// Largest number of imported functions read, in case of a corrupted table.
+pub const MAX_IMPORTS: usize = 0x10000;
+
+/// Fields of a PE image header.
+#[derive(Debug, Copy, Clone)]
+pub struct PeHeader {
+    pub nt_offset:      u32,
+    pub machine:        u16,
+    pub number_of_sections: u16,
+    pub time_date_stamp: u32,
+    pub size_of_optional_header: u16,
+    pub characteristics: u16,
+    pub magic:          u16,
+    pub section_alignment: u32,
+    pub file_alignment: u32,
+    pub size_of_image:  u32,
+    pub size_of_headers: u32,
+    pub checksum:       u32,
+    pub subsystem:      u16,
+    /// Import directory (RVA, size).
+    pub import_directory: (u32, u32),
+    /// Debug directory (RVA, size).
+    pub debug_directory: (u32, u32),
+}
+
+/// An `IMAGE_SECTION_HEADER`.
+#[derive(Debug, Clone)]
+pub struct PeSection {
+    /// Offset of the header in the image.
+    pub header_offset:      usize,
+    pub name:               String,
+    pub virtual_size:       u32,
+    pub virtual_address:    u32,
+    pub size_of_raw_data:   u32,
+    pub pointer_to_raw_data: u32,
+    pub characteristics:    u32,
+}
+
+fn u16_at(buf: &[u8], off: usize) -> Option<u16> {
+    buf.get(off..off + 2).map(|b| u16::from_le_bytes([b[0], b[1]]))
+}
+
+fn u32_at(buf: &[u8], off: usize) -> Option<u32> {
+    buf.get(off..off + 4).map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+}
+
+fn u64_at(buf: &[u8], off: usize) -> Option<u64> {
+    Some(u32_at(buf, off)? as u64 | (u32_at(buf, off + 4)? as u64) << 32)
+}
+
+impl PeHeader {
+    /// Parse the DOS and NT headers at the start of `buf`.
+    pub fn parse(buf: &[u8]) -> Result<Self> {
+        let err = || Error::DumpParseError("Invalid PE header".to_string());
+
+        if u16_at(buf, 0) != Some(IMAGE_DOS_SIGNATURE) {
+            return Err(err());
+        }
+
+        let nt_offset = u32_at(buf, 0x3c).ok_or_else(err)?;
+        let nt = nt_offset as usize;
+        if u32_at(buf, nt) != Some(IMAGE_NT_SIGNATURE) {
+            return Err(err());
+        }
+
+        // The fields up to Subsystem are at the same offsets in PE32 and
+        // PE32+ optional headers, the data directories are not.
+        let opt = nt + 0x18;
+        let magic = u16_at(buf, opt);
+        let directories = match magic {
+            Some(IMAGE_NT_OPTIONAL_HDR32_MAGIC) => opt + 0x60,
+            Some(IMAGE_NT_OPTIONAL_HDR64_MAGIC) => opt + 0x70,
+            _ => return Err(err()),
+        };
+        let directory = |index: usize| {
+            let off = directories + index * 8;
+            (u32_at(buf, off).unwrap_or(0), u32_at(buf, off + 4).unwrap_or(0))
+        };
+
+        Ok(PeHeader {
+            nt_offset,
+            machine: u16_at(buf, nt + 4).ok_or_else(err)?,
+            number_of_sections: u16_at(buf, nt + 6).ok_or_else(err)?,
+            time_date_stamp: u32_at(buf, nt + 8).ok_or_else(err)?,
+            size_of_optional_header: u16_at(buf, nt + 0x14).ok_or_else(err)?,
+            characteristics: u16_at(buf, nt + 0x16).ok_or_else(err)?,
+            magic: magic.ok_or_else(err)?,
+            section_alignment: u32_at(buf, opt + 0x20).ok_or_else(err)?,
+            file_alignment: u32_at(buf, opt + 0x24).ok_or_else(err)?,
+            size_of_image: u32_at(buf, opt + 0x38).ok_or_else(err)?,
+            size_of_headers: u32_at(buf, opt + 0x3c).ok_or_else(err)?,
+            checksum: u32_at(buf, opt + 0x40).ok_or_else(err)?,
+            subsystem: u16_at(buf, opt + 0x44).ok_or_else(err)?,
+            import_directory: directory(IMAGE_DIRECTORY_ENTRY_IMPORT),
+            debug_directory: directory(IMAGE_DIRECTORY_ENTRY_DEBUG),
+        })
+    }
+
+    pub fn is_64bit(&self) -> bool {
+        self.magic == IMAGE_NT_OPTIONAL_HDR64_MAGIC
+    }
+
+    /// File name extension of the image kind.
+    pub fn extension(&self) -> &'static str {
+        if self.characteristics & IMAGE_FILE_DLL != 0 {
+            "dll"
+        } else if self.subsystem == IMAGE_SUBSYSTEM_NATIVE {
+            "sys"
+        } else {
+            "exe"
+        }
+    }
+
+    /// Section table of the image whose headers are at the start of `buf`.
+    pub fn sections(&self, buf: &[u8]) -> Vec<PeSection> {
+        let table = self.nt_offset as usize + 0x18 + self.size_of_optional_header as usize;
+
+        (0..self.number_of_sections as usize)
+            .map(|n| table + n * IMAGE_SIZEOF_SECTION_HEADER)
+            .map_while(|off| {
+                let hdr = buf.get(off..off + IMAGE_SIZEOF_SECTION_HEADER)?;
+                let len = hdr[..8].iter().position(|&b| b == 0).unwrap_or(8);
+
+                Some(PeSection {
+                    header_offset: off,
+                    name: String::from_utf8_lossy(&hdr[..len]).to_string(),
+                    virtual_size: u32_at(hdr, 8)?,
+                    virtual_address: u32_at(hdr, 12)?,
+                    size_of_raw_data: u32_at(hdr, 16)?,
+                    pointer_to_raw_data: u32_at(hdr, 20)?,
+                    characteristics: u32_at(hdr, 36)?,
+                })
+            })
+            .collect()
+    }
+
+    /// Imported (DLL, function) names of the image mapped in `image`.
+    /// Functions imported by ordinal are named `ord<n>`.
+    pub fn imports(&self, image: &[u8]) -> Vec<(String, String)> {
+        let mut imports = Vec::new();
+        let (rva, _) = self.import_directory;
+        if rva == 0 {
+            return imports;
+        }
+
+        let thunk_size = if self.is_64bit() { 8 } else { 4 };
+        let ordinal_flag = 1u64 << (thunk_size * 8 - 1);
+
+        let mut desc = rva as usize;
+        while let Some(raw) = image.get(desc..desc + IMAGE_SIZEOF_IMPORT_DESCRIPTOR) {
+            let original_first_thunk = u32_at(raw, 0).unwrap_or(0);
+            let name = u32_at(raw, 12).unwrap_or(0);
+            let first_thunk = u32_at(raw, 16).unwrap_or(0);
+            if name == 0 && first_thunk == 0 {
+                break;
+            }
+
+            let dll = c_string_at(image, name as usize);
+
+            // The IAT holds the resolved addresses once loaded.
+            let mut thunk = match original_first_thunk {
+                0 => first_thunk as usize,
+                oft => oft as usize,
+            };
+            while imports.len() < MAX_IMPORTS {
+                let value = match thunk_size {
+                    8 => u64_at(image, thunk),
+                    _ => u32_at(image, thunk).map(|v| v as u64),
+                };
+
+                let function = match value {
+                    None | Some(0) => break,
+                    Some(v) if v & ordinal_flag != 0 => format!("ord{}", v & 0xffff),
+                    Some(v) => c_string_at(image, v as usize + 2),
+                };
+
+                imports.push((dll.clone(), function));
+                thunk += thunk_size;
+            }
+
+            desc += IMAGE_SIZEOF_IMPORT_DESCRIPTOR;
+        }
+
+        imports
+    }
+}
+
+/// RVA and size of the CodeView data of the `IMAGE_DEBUG_DIRECTORY` entries
+/// in `buf`.
+pub fn codeview_entry(buf: &[u8]) -> Option<(u32, u32)> {
+    buf.chunks_exact(IMAGE_SIZEOF_DEBUG_DIRECTORY)
+        .find(|entry| u32_at(entry, 12) == Some(IMAGE_DEBUG_TYPE_CODEVIEW))
+        .and_then(|entry| Some((u32_at(entry, 20)?, u32_at(entry, 16)?)))
+}
+
+/// PDB identity of an image, from its RSDS CodeView record.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CodeViewInfo {
+    pub guid:       [u8; 16],
+    pub age:        u32,
+    pub pdb_name:   String,
+}
+
+impl CodeViewInfo {
+    pub fn parse(buf: &[u8]) -> Result<Self> {
+        if u32_at(buf, 0) != Some(CV_SIGNATURE_RSDS) || buf.len() < 24 {
+            return Err(Error::DumpParseError("Invalid RSDS CodeView record".to_string()));
+        }
+
+        let mut guid = [0u8; 16];
+        guid.copy_from_slice(&buf[4..20]);
+
+        Ok(CodeViewInfo {
+            guid,
+            age: u32_at(buf, 20).unwrap_or(0),
+            pdb_name: c_string_at(buf, 24),
+        })
+    }
+
+    /// GUID in its usual mixed-endian text form, without braces.
+    pub fn guid_string(&self) -> String {
+        let g = &self.guid;
+        format!("{:08X}{:04X}{:04X}{}",
+            u32::from_le_bytes([g[0], g[1], g[2], g[3]]),
+            u16::from_le_bytes([g[4], g[5]]),
+            u16::from_le_bytes([g[6], g[7]]),
+            g[8..].iter().map(|b| format!("{:02X}", b)).collect::<String>())
+    }
+
+    /// Symbol server directory name: GUID followed by the age in hex.
+    pub fn signature(&self) -> String {
+        format!("{}{:X}", self.guid_string(), self.age)
+    }
+
+    /// File name of the PDB path.
+    pub fn pdb_file_name(&self) -> &str {
+        self.pdb_name.rsplit(['\\', '/']).next().unwrap_or(&self.pdb_name)
+    }
+}
+
+/// Import hash (Mandiant imphash) of `imports`: the MD5 of the lowercase
+/// `dll.function` list, without the dll, sys or ocx extensions.
+///
+/// Unlike pefile, ordinals of ws2_32 and oleaut32 are not resolved to names.
+pub fn imphash(imports: &[(String, String)]) -> Option<String> {
+    if imports.is_empty() {
+        return None;
+    }
+
+    let list: Vec<String> = imports.iter()
+        .map(|(dll, function)| {
+            let dll = dll.to_lowercase();
+            let lib = match dll.rsplit_once('.') {
+                Some((lib, "dll")) | Some((lib, "sys")) | Some((lib, "ocx")) => lib,
+                _ => &dll,
+            };
+            format!("{}.{}", lib, function.to_lowercase())
+        })
+        .collect();
+
+    Some(format!("{:x}", md5::compute(list.join(","))))
+}
+
+/// NUL terminated string at `off`, empty when out of bounds.
+fn c_string_at(buf: &[u8], off: usize) -> String {
+    let bytes = buf.get(off..).unwrap_or(&[]);
+    let len = bytes.iter().take(0x200).position(|&b| b == 0).unwrap_or(bytes.len().min(0x200));
+    String::from_utf8_lossy(&bytes[..len]).to_string()
+}
@@ -0,0 +1,126 @@
+use std::fs::File;
+use std::io::{Read, Seek};
+use std::ops::Range;
+use std::path::Path;
+
+use crate::crashdump::{CrashDump, PhysicalMemoryRun};
+use crate::reader::ZdmpReader;
+use crate::result::{Result, Error};
+
+/// Number of decompressed blocks kept by `ZdmpPhysicalMemory`.
+pub const BLOCK_CACHE_SIZE: usize = 16;
+
+/// Physical address space of a memory dump.
+pub trait PhysicalMemory {
+    /// Fill `buf` with the bytes at physical address `addr`.
+    ///
+    /// Fails with `Error::PhysicalAddressNotPresent` when part of the range
+    /// is not in the dump.
+    fn read_phys(&mut self, addr: u64, buf: &mut [u8]) -> Result<()>;
+
+    /// Physical address ranges present in the dump, sorted by address.
+    fn ranges(&self) -> Vec<Range<u64>>;
+}
+
+/// Physical memory of the crash dump embedded in a zdmp.
+///
+/// Physical addresses are mapped through the crash dump runs to offsets in
+/// the decompressed dump, and only the blocks covering a read are decoded.
+/// The most recently used blocks are cached.
+pub struct ZdmpPhysicalMemory<R> {
+    reader:         ZdmpReader<R>,
+    pub crashdump:  CrashDump,
+    runs:           Vec<PhysicalMemoryRun>,
+    cache:          Vec<(u64, Vec<u8>)>,
+}
+
+impl ZdmpPhysicalMemory<File> {
+    pub fn open(path: &Path, force: bool) -> Result<Self> {
+        ZdmpPhysicalMemory::new(ZdmpReader::open(path, force)?)
+    }
+}
+
+impl<R: Read + Seek> ZdmpPhysicalMemory<R> {
+    pub fn new(mut reader: ZdmpReader<R>) -> Result<Self> {
+        let crashdump = CrashDump::from_reader(&mut reader)?;
+
+        let mut runs = crashdump.runs.clone();
+        runs.sort_by_key(|run| run.base_page);
+
+        Ok(ZdmpPhysicalMemory { reader, crashdump, runs, cache: Vec::new() })
+    }
+
+    /// Offset in the decompressed dump of physical address `addr`.
+    pub fn dump_offset(&self, addr: u64) -> Result<u64> {
+        self.find_run(addr)
+            .map(|run| run.file_offset + addr - run.base_address())
+            .ok_or(Error::PhysicalAddressNotPresent(addr))
+    }
+
+    fn find_run(&self, addr: u64) -> Option<&PhysicalMemoryRun> {
+        let pos = self.runs.partition_point(|run| run.base_address() <= addr);
+        match pos {
+            0 => None,
+            pos => Some(&self.runs[pos - 1]),
+        }.filter(|run| addr < run.base_address() + run.size())
+    }
+
+    /// Decompressed block `block_id`, from the cache when possible.
+    fn block(&mut self, block_id: u64) -> Result<&[u8]> {
+        match self.cache.iter().position(|(id, _)| *id == block_id) {
+            Some(pos) => {
+                let entry = self.cache.remove(pos);
+                self.cache.push(entry);
+            },
+            None => {
+                let mut block = match self.cache.len() {
+                    BLOCK_CACHE_SIZE => self.cache.remove(0).1,
+                    _ => Vec::with_capacity(self.reader.block_size()),
+                };
+
+                self.reader.read_block(block_id, &mut block)?;
+                self.cache.push((block_id, block));
+            },
+        }
+
+        Ok(&self.cache.last().unwrap().1)
+    }
+}
+
+impl<R: Read + Seek> PhysicalMemory for ZdmpPhysicalMemory<R> {
+    fn read_phys(&mut self, addr: u64, buf: &mut [u8]) -> Result<()> {
+        let block_size = self.reader.block_size() as u64;
+        let mut done = 0;
+
+        while done < buf.len() {
+            let pos = addr + done as u64;
+            let run = *self.find_run(pos).ok_or(Error::PhysicalAddressNotPresent(pos))?;
+
+            // Stop at the end of the run, the next address may be in another
+            // one or missing.
+            let run_left = run.base_address() + run.size() - pos;
+            let offset = run.file_offset + pos - run.base_address();
+
+            let block = self.block(offset / block_size)?;
+            let start = (offset % block_size) as usize;
+            if start >= block.len() {
+                return Err(Error::DumpParseError(
+                    format!("Physical address 0x{:x} is past the end of the dump", pos)));
+            }
+
+            let len = (block.len() - start)
+                .min(buf.len() - done)
+                .min(run_left as usize);
+            buf[done..done + len].copy_from_slice(&block[start..start + len]);
+            done += len;
+        }
+
+        Ok(())
+    }
+
+    fn ranges(&self) -> Vec<Range<u64>> {
+        self.runs.iter()
+            .map(|run| run.base_address()..run.base_address() + run.size())
+            .collect()
+    }
+}
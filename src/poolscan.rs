@@ -0,0 +1,347 @@
+use std::fmt;
+use std::path::Path;
+use std::sync::{mpsc, Mutex};
+use std::thread;
+
+use crate::crashdump::CrashDump;
+use crate::json::JsonObject;
+use crate::reader::ZdmpReader;
+use crate::result::{Result, Error};
+use crate::zdmp::{self, PAGE_SIZE, BLOCK_FLAG_ZERO_RUN, BLOCK_FLAG_DUPLICATE};
+
+/// Tags scanned for by default: processes, threads, files, TCP endpoints and
+/// listeners, UDP endpoints, drivers and mutants.
+pub const DEFAULT_POOL_TAGS: &[&str] = &["Proc", "Thre", "File", "TcpE", "TcpL", "UdpA", "Driv", "Muta"];
+
+/// Bit set in the tag of protected allocations before Windows 8.
+pub const PROTECTED_POOL: u32 = 0x8000_0000;
+
+/// First x64 build (Windows 10 1903) whose kernel pools are segment heaps.
+pub const SEGMENT_HEAP_BUILD: u32 = 18362;
+
+/// Blocks queued per scan thread.
+const QUEUED_BLOCKS: usize = 4;
+
+/// A `_POOL_HEADER` that passed the sanity checks.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct PoolHit {
+    pub physical_address:   u64,
+    /// Size of the allocation, header included.
+    pub size:               u32,
+    pub tag:                [u8; 4],
+    pub pool_type:          u8,
+}
+
+impl PoolHit {
+    pub fn tag_name(&self) -> String {
+        String::from_utf8_lossy(&self.tag).to_string()
+    }
+
+    pub fn to_json(&self) -> String {
+        JsonObject::new()
+            .hex("physical_address", self.physical_address)
+            .hex("size", self.size as u64)
+            .str("tag", &self.tag_name())
+            .num("pool_type", self.pool_type as u64)
+            .finish()
+    }
+}
+
+impl fmt::Display for PoolHit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "0x{:012x} 0x{:05x} {} {}",
+            self.physical_address, self.size, self.tag_name(), self.pool_type)
+    }
+}
+
+/// `_POOL_HEADER` layout of the dumped system.
+#[derive(Debug, Copy, Clone)]
+struct PoolLayout {
+    /// Header size, alignment and `BlockSize` unit.
+    unit:       usize,
+    /// Bits of `PreviousSize` and `BlockSize`.
+    size_bits:  u32,
+    /// Segment heap allocations have no `PreviousSize` and may cross pages.
+    segment_heap: bool,
+}
+
+const POOL_LAYOUT_32: PoolLayout = PoolLayout { unit: 8, size_bits: 9, segment_heap: false };
+const POOL_LAYOUT_64: PoolLayout = PoolLayout { unit: 16, size_bits: 8, segment_heap: false };
+
+impl PoolLayout {
+    fn new(crashdump: &CrashDump) -> Self {
+        match crashdump.is_64bit {
+            true => PoolLayout { segment_heap: crashdump.minor_version >= SEGMENT_HEAP_BUILD, ..POOL_LAYOUT_64 },
+            false => POOL_LAYOUT_32,
+        }
+    }
+
+    /// Split the first `_POOL_HEADER` dword into (PreviousSize, BlockSize,
+    /// PoolType).
+    fn fields(&self, dword: u32) -> (usize, usize, u8) {
+        let size_mask = (1 << self.size_bits) - 1;
+        let previous = dword & size_mask;
+        let block = (dword >> 16) & size_mask;
+        let pool_type = dword >> (16 + self.size_bits);
+
+        (previous as usize * self.unit, block as usize * self.unit, pool_type as u8)
+    }
+}
+
+/// Parse `Proc,Thre,...` into pool tags.
+pub fn parse_tags(s: &str) -> Result<Vec<[u8; 4]>> {
+    s.split(',')
+        .map(|tag| match tag.as_bytes() {
+            bytes if !bytes.is_empty() && bytes.len() <= 4 => {
+                let mut out = *b"    ";
+                out[..bytes.len()].copy_from_slice(bytes);
+                Ok(out)
+            },
+            _ => Err(Error::IoError(format!("Unexpected pool tag: `{}`", tag))),
+        })
+        .collect()
+}
+
+/// Scan the physical memory of the crash dump in the zdmp at `path` for the
+/// `_POOL_HEADER`s of `tags`.
+///
+/// The zdmp is read in a single pass; blocks are decoded and scanned on
+/// `threads` threads. Hits are sorted by physical address.
+pub fn scan(path: &Path, force: bool, tags: &[[u8; 4]], threads: usize) -> Result<Vec<PoolHit>> {
+    let mut reader = ZdmpReader::open(path, force)?;
+    let crashdump = CrashDump::from_reader(&mut reader)?;
+
+    if crashdump.runs.is_empty() {
+        return Err(Error::DumpParseError(
+            "Crash dump has no physical memory runs".to_string()));
+    }
+
+    let layout = PoolLayout::new(&crashdump);
+    let tags: Vec<u32> = tags.iter().map(|tag| u32::from_le_bytes(*tag)).collect();
+    let block_size = reader.block_size();
+    let threads = threads.max(1);
+
+    let (job_tx, job_rx) = mpsc::sync_channel::<ScanJob>(threads * QUEUED_BLOCKS);
+    let job_rx = Mutex::new(job_rx);
+
+    thread::scope(|scope| {
+        let workers: Vec<_> = (0..threads)
+            .map(|_| scope.spawn(|| scan_worker(&job_rx, &crashdump, layout, &tags, block_size)))
+            .collect();
+
+        let read = queue_blocks(&mut reader, &job_tx);
+        drop(job_tx);
+
+        let mut hits = Vec::new();
+        for worker in workers {
+            hits.extend(worker.join().expect("pool scan thread panicked")?);
+        }
+        read?;
+
+        hits.sort();
+        Ok(hits)
+    })
+}
+
+/// A block to scan, repeated `count` times from the dump offset `pos`.
+struct ScanJob {
+    pos:        u64,
+    count:      u64,
+    /// Decoded block, or the payload to decode.
+    decoded:    bool,
+    flags:      u32,
+    crc32:      u32,
+    data:       Vec<u8>,
+}
+
+/// Read the records in file order and queue their blocks.
+fn queue_blocks(reader: &mut ZdmpReader<std::fs::File>, jobs: &mpsc::SyncSender<ScanJob>) -> Result<()> {
+    let block_size = reader.block_size() as u64;
+    let mut pos = 0;
+
+    while let Some(rec) = reader.next_record()? {
+        let job_pos = pos;
+        pos += rec.block_count * block_size;
+
+        // Pool headers are never zero.
+        if rec.flags & BLOCK_FLAG_ZERO_RUN != 0 {
+            continue;
+        }
+
+        let job = if rec.flags & BLOCK_FLAG_DUPLICATE != 0 {
+            let mut block = Vec::with_capacity(block_size as usize);
+            reader.decode_record(&rec, &mut block)?;
+
+            ScanJob { pos: job_pos, count: 1, decoded: true, flags: 0, crc32: 0, data: block }
+        } else {
+            ScanJob {
+                pos: job_pos, count: rec.block_count, decoded: false,
+                flags: rec.flags, crc32: rec.crc32, data: reader.read_payload(&rec)?,
+            }
+        };
+
+        // The workers stopped on an error, which they report.
+        if jobs.send(job).is_err() {
+            break;
+        }
+    }
+
+    Ok(())
+}
+
+fn scan_worker(
+    jobs: &Mutex<mpsc::Receiver<ScanJob>>,
+    crashdump: &CrashDump,
+    layout: PoolLayout,
+    tags: &[u32],
+    block_size: usize
+) -> Result<Vec<PoolHit>> {
+    let mut hits = Vec::new();
+    let mut block = Vec::with_capacity(block_size);
+
+    loop {
+        let job = match jobs.lock().expect("pool scan queue poisoned").recv() {
+            Ok(job) => job,
+            Err(_) => return Ok(hits),
+        };
+
+        let data = if job.decoded {
+            &job.data
+        } else {
+            zdmp::decode_payload(block_size, job.flags, job.crc32, &job.data, &mut block)?;
+            &block
+        };
+
+        for copy in 0..job.count {
+            let pos = job.pos + copy * block_size as u64;
+            scan_block(data, pos, crashdump, layout, tags, &mut hits);
+        }
+    }
+}
+
+/// Scan a decoded block at the dump offset `pos`.
+fn scan_block(
+    data: &[u8],
+    pos: u64,
+    crashdump: &CrashDump,
+    layout: PoolLayout,
+    tags: &[u32],
+    hits: &mut Vec<PoolHit>
+) {
+    for offset in (0..data.len().saturating_sub(layout.unit - 1)).step_by(layout.unit) {
+        let tag = u32::from_le_bytes([data[offset + 4], data[offset + 5], data[offset + 6], data[offset + 7]]);
+        if !tags.contains(&(tag & !PROTECTED_POOL)) {
+            continue;
+        }
+
+        let dword = u32::from_le_bytes([data[offset], data[offset + 1], data[offset + 2], data[offset + 3]]);
+        let (previous_size, size, pool_type) = layout.fields(dword);
+        if !is_sane(offset % PAGE_SIZE, previous_size, size, pool_type, layout) {
+            continue;
+        }
+
+        if let Some(physical_address) = crashdump.physical_address(pos + offset as u64) {
+            hits.push(PoolHit {
+                physical_address,
+                size: size as u32,
+                tag: (tag & !PROTECTED_POOL).to_le_bytes(),
+                pool_type,
+            });
+        }
+    }
+}
+
+/// Small pool allocations are in use and, outside of the segment heap, fit
+/// in their page and follow the previous allocation of the page.
+fn is_sane(page_offset: usize, previous_size: usize, size: usize, pool_type: u8, layout: PoolLayout) -> bool {
+    if pool_type == 0 || size <= layout.unit {
+        return false;
+    }
+    if layout.segment_heap {
+        return true;
+    }
+
+    let previous_ok = match page_offset {
+        0 => previous_size == 0,
+        _ => previous_size != 0 && previous_size <= page_offset,
+    };
+
+    page_offset + size <= PAGE_SIZE && previous_ok
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crashdump::{PhysicalMemoryRun, IMAGE_FILE_MACHINE_I386, IMAGE_FILE_MACHINE_AMD64};
+
+    /// First `_POOL_HEADER` dword.
+    fn header(layout: PoolLayout, previous_size: usize, size: usize, pool_type: u32) -> u32 {
+        (previous_size / layout.unit) as u32
+            | ((size / layout.unit) as u32) << 16
+            | pool_type << (16 + layout.size_bits)
+    }
+
+    /// One page of memory at physical page 0x10 holding `headers` (offset,
+    /// first dword, tag).
+    fn scan_page(crashdump: &mut CrashDump, headers: &[(usize, u32, &[u8; 4])]) -> Vec<PoolHit> {
+        crashdump.runs = vec![PhysicalMemoryRun { base_page: 0x10, page_count: 1, file_offset: 0x2000 }];
+
+        let mut page = vec![0u8; PAGE_SIZE];
+        for &(offset, dword, tag) in headers {
+            page[offset..offset + 4].copy_from_slice(&dword.to_le_bytes());
+            page[offset + 4..offset + 8].copy_from_slice(tag);
+        }
+
+        let mut hits = Vec::new();
+        let tags = [u32::from_le_bytes(*b"Proc")];
+        scan_block(&page, 0x2000, crashdump, PoolLayout::new(crashdump), &tags, &mut hits);
+        hits
+    }
+
+    #[test]
+    fn win7_headers_follow_their_page() {
+        let mut crashdump = CrashDump::empty(IMAGE_FILE_MACHINE_I386, 7601);
+        let layout = PoolLayout::new(&crashdump);
+        assert!(!layout.segment_heap);
+
+        assert!(is_sane(0, 0, 0x2c0, 2, layout));
+        assert!(is_sane(0x2c0, 0x2c0, 0x280, 2, layout));
+        assert!(!is_sane(0x2c0, 0, 0x280, 2, layout));
+        assert!(!is_sane(0, 0x40, 0x280, 2, layout));
+        assert!(!is_sane(0xf00, 0x40, 0x280, 2, layout));
+        assert!(!is_sane(0, 0, 0x280, 0, layout));
+
+        let hits = scan_page(&mut crashdump, &[
+            (0, header(layout, 0, 0x2c0, 2), b"Proc"),
+            (0x2c0, header(layout, 0x2c0, 0x280, 2), b"Proc"),
+            // No PreviousSize.
+            (0x540, header(layout, 0, 0x280, 2), b"Proc"),
+            (0x800, header(layout, 0x280, 0x280, 2), b"Thre"),
+        ]);
+        assert_eq!(hits.iter().map(|h| h.physical_address).collect::<Vec<_>>(), vec![0x10000, 0x102c0]);
+        assert_eq!(hits[1].size, 0x280);
+    }
+
+    #[test]
+    fn segment_heap_headers_have_no_previous_size() {
+        let layout = PoolLayout::new(&CrashDump::empty(IMAGE_FILE_MACHINE_AMD64, 17763));
+        assert!(!layout.segment_heap);
+        assert!(!is_sane(0x10, 0, 0xa40, 2, layout));
+
+        let mut crashdump = CrashDump::empty(IMAGE_FILE_MACHINE_AMD64, 19041);
+        let layout = PoolLayout::new(&crashdump);
+        assert!(layout.segment_heap);
+
+        // VS allocation in the middle of a page, and one crossing the next.
+        assert!(is_sane(0x10, 0, 0xa40, 2, layout));
+        assert!(is_sane(0xb00, 0, 0xa40, 2, layout));
+        assert!(!is_sane(0x10, 0, 0xa40, 0, layout));
+        assert!(!is_sane(0x10, 0, 0x10, 2, layout));
+
+        let hits = scan_page(&mut crashdump, &[
+            (0x10, header(layout, 0, 0xa40, 2), b"Proc"),
+            (0xb00, header(layout, 0, 0xa40, 2), b"Proc"),
+        ]);
+        assert_eq!(hits.iter().map(|h| h.physical_address).collect::<Vec<_>>(), vec![0x10010, 0x10b00]);
+    }
+}
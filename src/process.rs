@@ -0,0 +1,123 @@
+use std::fmt;
+
+use crate::crashdump::format_filetime;
+use crate::json::{self, JsonObject};
+use crate::paging::AddressSpace;
+use crate::physmem::PhysicalMemory;
+use crate::profile::EprocessProfile;
+use crate::result::Result;
+
+/// Length of `_EPROCESS.ImageFileName`.
+pub const IMAGE_FILE_NAME_SIZE: usize = 15;
+
+/// A process of the `PsActiveProcessHead` list.
+#[derive(Debug, Clone)]
+pub struct Process {
+    /// Address of the `_EPROCESS`.
+    pub eprocess:               u64,
+    pub pid:                    u64,
+    pub ppid:                   Option<u64>,
+    pub image_file_name:        String,
+    pub create_time:            Option<u64>,
+    pub exit_time:              Option<u64>,
+    pub directory_table_base:   u64,
+    pub session_id:             Option<u32>,
+}
+
+impl Process {
+    pub fn to_json(&self) -> String {
+        let number = |value: Option<u64>| value.map(|v| v.to_string());
+
+        JsonObject::new()
+            .num("pid", self.pid)
+            .raw("ppid", number(self.ppid).as_deref())
+            .str("name", &self.image_file_name)
+            .raw("create_time", self.create_time.map(|t| json::escape(&format_filetime(t))).as_deref())
+            .raw("exit_time", self.exit_time.map(|t| json::escape(&format_filetime(t))).as_deref())
+            .hex("dtb", self.directory_table_base)
+            .raw("session", number(self.session_id.map(|s| s as u64)).as_deref())
+            .hex("eprocess", self.eprocess)
+            .finish()
+    }
+}
+
+impl fmt::Display for Process {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let or_dash = |value: Option<String>| value.unwrap_or_else(|| "-".to_string());
+
+        write!(f, "0x{:016x} {:>6} {:>6} {:<15} {:<23} {:<23} 0x{:010x} {:>7}",
+            self.eprocess,
+            self.pid,
+            or_dash(self.ppid.map(|p| p.to_string())),
+            self.image_file_name,
+            or_dash(self.create_time.map(format_filetime)),
+            or_dash(self.exit_time.map(format_filetime)),
+            self.directory_table_base,
+            or_dash(self.session_id.map(|s| s.to_string())))
+    }
+}
+
+/// Walk the `_EPROCESS.ActiveProcessLinks` list at `list_head`.
+///
+/// The walk stops at the first unreadable entry or link, with a warning.
+pub fn list_processes<M: PhysicalMemory>(
+    space: &mut AddressSpace<M>,
+    profile: &EprocessProfile,
+    list_head: u64
+) -> Result<Vec<Process>> {
+    let mut processes = Vec::new();
+    for entry in space.list_entries(list_head)? {
+        let eprocess = entry.wrapping_sub(profile.active_process_links);
+
+        match read_process(space, profile, eprocess) {
+            Ok(process) => processes.push(process),
+            Err(e) => {
+                warn!("Unreadable _EPROCESS @ 0x{:x}: {:?}", eprocess, e);
+                break;
+            },
+        }
+    }
+
+    Ok(processes)
+}
+
+fn read_process<M: PhysicalMemory>(
+    space: &mut AddressSpace<M>,
+    profile: &EprocessProfile,
+    eprocess: u64
+) -> Result<Process> {
+    let pid = space.read_ptr(eprocess + profile.unique_process_id)?;
+
+    let mut name = [0u8; IMAGE_FILE_NAME_SIZE];
+    space.read_virt(eprocess + profile.image_file_name, &mut name)?;
+    let len = name.iter().position(|&b| b == 0).unwrap_or(name.len());
+    let image_file_name = String::from_utf8_lossy(&name[..len]).to_string();
+
+    let directory_table_base = space.read_ptr(eprocess + profile.directory_table_base)?;
+
+    let ppid = match profile.inherited_from_unique_process_id {
+        Some(offset) => space.read_ptr(eprocess + offset).ok(),
+        None => None,
+    };
+
+    // Zero when the process did not start or exit yet.
+    let mut time = |offset: Option<u64>| offset
+        .and_then(|offset| space.read_u64(eprocess + offset).ok())
+        .filter(|&t| t != 0);
+    let create_time = time(profile.create_time);
+    let exit_time = time(profile.exit_time);
+
+    // The System process and the idle process have no session.
+    let session_id = match profile.session {
+        Some(offset) => match space.read_ptr(eprocess + offset) {
+            Ok(0) | Err(_) => None,
+            Ok(session) => space.read_u32(session + profile.session_id).ok(),
+        },
+        None => None,
+    };
+
+    Ok(Process {
+        eprocess, pid, ppid, image_file_name, create_time, exit_time,
+        directory_table_base, session_id,
+    })
+}
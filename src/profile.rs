@@ -0,0 +1,362 @@
+use std::fs;
+use std::path::Path;
+
+use crate::crashdump::{
+    CrashDump, IMAGE_FILE_MACHINE_I386, IMAGE_FILE_MACHINE_AMD64, IMAGE_FILE_MACHINE_ARM64,
+};
+use crate::result::{Result, Error};
+use crate::symbols::ModuleSymbols;
+
+/// `_EPROCESS` field offsets of a range of kernel builds.
+///
+/// Optional fields are left out of the output when unknown.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EprocessProfile {
+    /// `IMAGE_FILE_MACHINE_*`.
+    pub machine:                            u32,
+    pub first_build:                        u32,
+    pub last_build:                         u32,
+    /// `Pcb.DirectoryTableBase`.
+    pub directory_table_base:               u64,
+    pub unique_process_id:                  u64,
+    pub active_process_links:               u64,
+    pub image_file_name:                    u64,
+    pub inherited_from_unique_process_id:   Option<u64>,
+    pub create_time:                        Option<u64>,
+    pub exit_time:                          Option<u64>,
+    pub session:                            Option<u64>,
+    /// `_MM_SESSION_SPACE.SessionId`.
+    pub session_id:                         u64,
+}
+
+/// Profiles built into the binary.
+pub const BUNDLED_PROFILES: &[EprocessProfile] = &[
+    // Windows 7 SP1, Server 2008 R2 SP1.
+    EprocessProfile {
+        machine: IMAGE_FILE_MACHINE_I386,
+        first_build: 7601,
+        last_build: 7601,
+        directory_table_base: 0x18,
+        unique_process_id: 0xb4,
+        active_process_links: 0xb8,
+        image_file_name: 0x16c,
+        inherited_from_unique_process_id: Some(0x140),
+        create_time: Some(0xa0),
+        exit_time: Some(0xa8),
+        session: None,
+        session_id: 0x8,
+    },
+    EprocessProfile {
+        machine: IMAGE_FILE_MACHINE_AMD64,
+        first_build: 7601,
+        last_build: 7601,
+        directory_table_base: 0x28,
+        unique_process_id: 0x180,
+        active_process_links: 0x188,
+        image_file_name: 0x2e0,
+        inherited_from_unique_process_id: Some(0x290),
+        create_time: Some(0x168),
+        exit_time: Some(0x170),
+        session: Some(0x2d8),
+        session_id: 0x8,
+    },
+    // Windows 8, Server 2012.
+    EprocessProfile {
+        machine: IMAGE_FILE_MACHINE_AMD64,
+        first_build: 9200,
+        last_build: 9200,
+        directory_table_base: 0x28,
+        unique_process_id: 0x2e0,
+        active_process_links: 0x2e8,
+        image_file_name: 0x438,
+        inherited_from_unique_process_id: Some(0x3e0),
+        create_time: None,
+        exit_time: None,
+        session: None,
+        session_id: 0x8,
+    },
+    // Windows 8.1, Server 2012 R2.
+    EprocessProfile {
+        machine: IMAGE_FILE_MACHINE_AMD64,
+        first_build: 9600,
+        last_build: 9600,
+        directory_table_base: 0x28,
+        unique_process_id: 0x2e0,
+        active_process_links: 0x2e8,
+        image_file_name: 0x438,
+        inherited_from_unique_process_id: Some(0x3e0),
+        create_time: None,
+        exit_time: None,
+        session: None,
+        session_id: 0x8,
+    },
+    // Windows 10 1507 and 1511.
+    EprocessProfile {
+        machine: IMAGE_FILE_MACHINE_AMD64,
+        first_build: 10240,
+        last_build: 10586,
+        directory_table_base: 0x28,
+        unique_process_id: 0x2e8,
+        active_process_links: 0x2f0,
+        image_file_name: 0x450,
+        inherited_from_unique_process_id: Some(0x3e0),
+        create_time: None,
+        exit_time: None,
+        session: None,
+        session_id: 0x8,
+    },
+    // Windows 10 1607, Server 2016.
+    EprocessProfile {
+        machine: IMAGE_FILE_MACHINE_AMD64,
+        first_build: 14393,
+        last_build: 14393,
+        directory_table_base: 0x28,
+        unique_process_id: 0x2e8,
+        active_process_links: 0x2f0,
+        image_file_name: 0x450,
+        inherited_from_unique_process_id: Some(0x3e0),
+        create_time: None,
+        exit_time: None,
+        session: None,
+        session_id: 0x8,
+    },
+    // Windows 10 1703 to 1809, Server 2019.
+    EprocessProfile {
+        machine: IMAGE_FILE_MACHINE_AMD64,
+        first_build: 15063,
+        last_build: 17763,
+        directory_table_base: 0x28,
+        unique_process_id: 0x2e0,
+        active_process_links: 0x2e8,
+        image_file_name: 0x450,
+        inherited_from_unique_process_id: Some(0x3e0),
+        create_time: None,
+        exit_time: None,
+        session: None,
+        session_id: 0x8,
+    },
+    // Windows 10 1903 and 1909.
+    EprocessProfile {
+        machine: IMAGE_FILE_MACHINE_AMD64,
+        first_build: 18362,
+        last_build: 18363,
+        directory_table_base: 0x28,
+        unique_process_id: 0x2e8,
+        active_process_links: 0x2f0,
+        image_file_name: 0x450,
+        inherited_from_unique_process_id: Some(0x3e0),
+        create_time: None,
+        exit_time: None,
+        session: None,
+        session_id: 0x8,
+    },
+    // Windows 10 2004 to 22H2, Windows 11 21H2 to 23H2.
+    EprocessProfile {
+        machine: IMAGE_FILE_MACHINE_AMD64,
+        first_build: 19041,
+        last_build: 22631,
+        directory_table_base: 0x28,
+        unique_process_id: 0x440,
+        active_process_links: 0x448,
+        image_file_name: 0x5a8,
+        inherited_from_unique_process_id: Some(0x540),
+        create_time: Some(0x468),
+        exit_time: None,
+        session: Some(0x558),
+        session_id: 0x8,
+    },
+    // Windows 11 24H2, Server 2025, where the _EPROCESS fields moved down.
+    EprocessProfile {
+        machine: IMAGE_FILE_MACHINE_AMD64,
+        first_build: 26100,
+        last_build: 26100,
+        directory_table_base: 0x28,
+        unique_process_id: 0x1d0,
+        active_process_links: 0x1d8,
+        image_file_name: 0x338,
+        inherited_from_unique_process_id: None,
+        create_time: None,
+        exit_time: None,
+        session: None,
+        session_id: 0x8,
+    },
+];
+
+impl EprocessProfile {
+    fn new(machine: u32, first_build: u32, last_build: u32) -> Self {
+        EprocessProfile {
+            machine,
+            first_build,
+            last_build,
+            directory_table_base: 0,
+            unique_process_id: 0,
+            active_process_links: 0,
+            image_file_name: 0,
+            inherited_from_unique_process_id: None,
+            create_time: None,
+            exit_time: None,
+            session: None,
+            session_id: 0x8,
+        }
+    }
+
+    /// Profile from the kernel symbols, for any build.
+    pub fn from_symbols(nt: &ModuleSymbols, machine: u32) -> Result<Self> {
+        let field = |name| nt.field_offset("_EPROCESS", name);
+
+        Ok(EprocessProfile {
+            machine,
+            first_build: 0,
+            last_build: u32::MAX,
+            directory_table_base: field("Pcb")? + nt.field_offset("_KPROCESS", "DirectoryTableBase")?,
+            unique_process_id: field("UniqueProcessId")?,
+            active_process_links: field("ActiveProcessLinks")?,
+            image_file_name: field("ImageFileName")?,
+            inherited_from_unique_process_id: field("InheritedFromUniqueProcessId").ok(),
+            create_time: field("CreateTime").ok(),
+            exit_time: field("ExitTime").ok(),
+            session: field("Session").ok(),
+            session_id: nt.field_offset("_MM_SESSION_SPACE", "SessionId").unwrap_or(0x8),
+        })
+    }
+
+    pub fn matches(&self, machine: u32, build: u32) -> bool {
+        self.machine == machine && (self.first_build..=self.last_build).contains(&build)
+    }
+
+    /// The first profile of `profiles`, then of the bundled ones, for the
+    /// machine and build of `crashdump`.
+    pub fn select<'a>(profiles: &'a [Self], crashdump: &CrashDump) -> Result<&'a Self> {
+        let (machine, build) = (crashdump.machine_image_type, crashdump.minor_version);
+
+        profiles.iter()
+            .chain(BUNDLED_PROFILES)
+            .find(|p| p.matches(machine, build))
+            .ok_or_else(|| Error::DumpParseError(format!(
+                "No _EPROCESS profile for {} build {}, add one with --profile",
+                crashdump.machine_name(), build)))
+    }
+
+    /// Load profiles from a file of `[<machine> <build>[-<last build>]]`
+    /// sections (machine: x86, x64 or arm64) of `<field> = <offset>` lines,
+    /// using the `_EPROCESS` field names. `#` starts a comment.
+    pub fn load(path: &Path) -> Result<Vec<Self>> {
+        Self::parse(&fs::read_to_string(path)?)
+    }
+
+    pub fn parse(text: &str) -> Result<Vec<Self>> {
+        let mut profiles: Vec<Self> = Vec::new();
+
+        for (n, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            let parse_error = |message: &str| Error::DumpParseError(
+                format!("Profile line {}: {}: `{}`", n + 1, message, line));
+
+            if let Some(section) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+                let mut parts = section.split_whitespace();
+                let (machine, builds) = match (parts.next(), parts.next(), parts.next()) {
+                    (Some(machine), Some(builds), None) => (machine, builds),
+                    _ => return Err(parse_error("expected [<machine> <build>[-<last build>]]")),
+                };
+
+                let machine = match machine.to_ascii_lowercase().as_str() {
+                    "x86" => IMAGE_FILE_MACHINE_I386,
+                    "x64" | "amd64" => IMAGE_FILE_MACHINE_AMD64,
+                    "arm64" => IMAGE_FILE_MACHINE_ARM64,
+                    _ => return Err(parse_error("unexpected machine")),
+                };
+
+                let (first, last) = match builds.split_once('-') {
+                    Some((first, last)) => (first.parse::<u32>()?, last.parse::<u32>()?),
+                    None => (builds.parse::<u32>()?, builds.parse::<u32>()?),
+                };
+
+                profiles.push(EprocessProfile::new(machine, first, last));
+                continue;
+            }
+
+            let profile = match profiles.last_mut() {
+                Some(profile) => profile,
+                None => return Err(parse_error("field outside of a section")),
+            };
+
+            let (field, value) = match line.split_once('=') {
+                Some((field, value)) => (field.trim(), parse_offset(value.trim())?),
+                None => return Err(parse_error("expected <field> = <offset>")),
+            };
+
+            match field {
+                "DirectoryTableBase" => profile.directory_table_base = value,
+                "UniqueProcessId" => profile.unique_process_id = value,
+                "ActiveProcessLinks" => profile.active_process_links = value,
+                "ImageFileName" => profile.image_file_name = value,
+                "InheritedFromUniqueProcessId" => profile.inherited_from_unique_process_id = Some(value),
+                "CreateTime" => profile.create_time = Some(value),
+                "ExitTime" => profile.exit_time = Some(value),
+                "Session" => profile.session = Some(value),
+                "SessionId" => profile.session_id = value,
+                _ => return Err(parse_error("unexpected field")),
+            }
+        }
+
+        if let Some(profile) = profiles.iter().find(|p|
+            p.unique_process_id == 0 || p.active_process_links == 0 || p.image_file_name == 0) {
+            return Err(Error::DumpParseError(format!(
+                "Profile 0x{:x} {}-{} lacks UniqueProcessId, ActiveProcessLinks or ImageFileName",
+                profile.machine, profile.first_build, profile.last_build)));
+        }
+
+        Ok(profiles)
+    }
+}
+
+fn parse_offset(s: &str) -> Result<u64> {
+    match s.strip_prefix("0x") {
+        Some(hex) => Ok(u64::from_str_radix(hex, 16)?),
+        None => Ok(s.parse::<u64>()?),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn select_bundled_profiles_by_build() {
+        // One build of each range: (build, UniqueProcessId, ActiveProcessLinks).
+        let builds = [
+            (7601, 0x180, 0x188),
+            (9200, 0x2e0, 0x2e8),
+            (9600, 0x2e0, 0x2e8),
+            (10586, 0x2e8, 0x2f0),
+            (14393, 0x2e8, 0x2f0),
+            (16299, 0x2e0, 0x2e8),
+            (18363, 0x2e8, 0x2f0),
+            (19045, 0x440, 0x448),
+            (26100, 0x1d0, 0x1d8),
+        ];
+
+        for &(build, unique_process_id, active_process_links) in &builds {
+            let profile = EprocessProfile::select(&[], &CrashDump::empty(IMAGE_FILE_MACHINE_AMD64, build)).unwrap();
+            assert!(profile.matches(IMAGE_FILE_MACHINE_AMD64, build));
+            assert_eq!((profile.unique_process_id, profile.active_process_links),
+                (unique_process_id, active_process_links), "build {}", build);
+        }
+
+        assert!(EprocessProfile::select(&[], &CrashDump::empty(IMAGE_FILE_MACHINE_AMD64, 9500)).is_err());
+        assert!(EprocessProfile::select(&[], &CrashDump::empty(IMAGE_FILE_MACHINE_ARM64, 19041)).is_err());
+    }
+
+    #[test]
+    fn user_profiles_come_first() {
+        let profiles = EprocessProfile::parse(
+            "[x64 19041-19045]\nUniqueProcessId = 0x10\nActiveProcessLinks = 0x18\nImageFileName = 0x20\n").unwrap();
+
+        let profile = EprocessProfile::select(&profiles, &CrashDump::empty(IMAGE_FILE_MACHINE_AMD64, 19044)).unwrap();
+        assert_eq!(profile.active_process_links, 0x18);
+    }
+}
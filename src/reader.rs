@@ -0,0 +1,479 @@
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::mem;
+use std::path::Path;
+
+use crate::result::{Result, Error};
+use crate::zdmp::{
+    self, ZdmpFileHdr, ZdmpFileHdrV2, ZdmpBlockHdr, ZdmpBlockHdrV2, ZdmpIndexEntry,
+    ZdmpMetadata, ZdmpVersion, CRC32_IEEE, ZDMP_BLOCK_START_OFFSET, PAGE_SIZE,
+    BLOCK_FLAG_RAW, BLOCK_FLAG_COMPRESSED, BLOCK_FLAG_DUPLICATE,
+};
+
+/// One record of the block stream, from either container version.
+#[derive(Debug, Copy, Clone)]
+pub struct ZdmpRecord {
+    /// File offset of the record header.
+    pub offset:             u64,
+    pub hdr_size:           u64,
+    /// First uncompressed block covered by the record.
+    pub block_id:           u64,
+    pub block_count:        u64,
+    pub flags:              u32,
+    pub data_size:          u32,
+    pub crc32:              u32,
+    /// Block repeated by a duplicate record.
+    pub ref_block:          u64,
+}
+
+impl ZdmpRecord {
+    /// File offset of the record payload.
+    pub fn data_offset(&self) -> u64 {
+        self.offset + self.hdr_size
+    }
+
+    /// File offset of the following record.
+    pub fn next_offset(&self) -> u64 {
+        self.data_offset() + self.data_size as u64
+    }
+}
+
+/// Block-level reader over a zdmp file.
+///
+/// Records can be walked in file order with `next_record`, or single
+/// blocks decoded in any order with `read_block`. Random access uses the
+/// 2.0 trailing index when present and a one-off header scan otherwise.
+pub struct ZdmpReader<R> {
+    rdr:                    R,
+    pub hdr:                ZdmpFileHdr,
+    pub version:            ZdmpVersion,
+    pub hdr_v2:             Option<ZdmpFileHdrV2>,
+    pub metadata:           Vec<ZdmpMetadata>,
+    pub file_len:           u64,
+    index:                  Option<Vec<ZdmpIndexEntry>>,
+    next_offset:            u64,
+    next_block:             u64,
+}
+
+impl ZdmpReader<File> {
+    pub fn open(path: &Path, force: bool) -> Result<Self> {
+        let file = File::open(path)?;
+        ZdmpReader::new(file, force)
+    }
+}
+
+impl<R: Read + Seek> ZdmpReader<R> {
+    pub fn new(mut rdr: R, force: bool) -> Result<Self> {
+        let file_len = rdr.seek(SeekFrom::End(0))?;
+        rdr.seek(SeekFrom::Start(0))?;
+
+        let hdr = ZdmpFileHdr::new(&mut rdr)?;
+        let version = hdr.parse_version(force)?;
+
+        if hdr.block_size == 0 {
+            return Err(Error::DumpParseError(
+                "Unexpected zdump block size: 0x0".to_string()));
+        }
+
+        let (hdr_v2, metadata) = match version {
+            ZdmpVersion::V10 => (None, Vec::new()),
+            ZdmpVersion::V20 => {
+                let hdr_v2 = ZdmpFileHdrV2::new(&mut rdr)?;
+
+                let meta_offset = mem::size_of::<ZdmpFileHdr>() + mem::size_of::<ZdmpFileHdrV2>();
+                let meta_size = hdr_v2.metadata_size as usize;
+                if meta_offset + meta_size > PAGE_SIZE {
+                    return Err(Error::DumpParseError(
+                        format!("Metadata size 0x{:x} overruns the header page", meta_size)));
+                }
+
+                let mut buf = vec![0; meta_size];
+                rdr.read_exact(&mut buf)?;
+
+                (Some(hdr_v2), ZdmpMetadata::parse(&buf)?)
+            },
+        };
+
+        Ok(ZdmpReader {
+            rdr, hdr, version, hdr_v2, metadata, file_len,
+            index: None,
+            next_offset: ZDMP_BLOCK_START_OFFSET,
+            next_block: 0,
+        })
+    }
+
+    pub fn block_size(&self) -> usize {
+        self.hdr.block_size as usize
+    }
+
+    /// Size of the decompressed dump.
+    pub fn uncompressed_size(&self) -> u64 {
+        self.hdr.file_size
+    }
+
+    /// Whether the 2.0 trailing index is present and within the file.
+    /// Header values that overflow mean no index.
+    pub fn has_index(&self) -> bool {
+        match self.hdr_v2 {
+            Some(hdr_v2) => {
+                let index_end = hdr_v2.record_count
+                    .checked_mul(mem::size_of::<ZdmpIndexEntry>() as u64)
+                    .and_then(|index_size| hdr_v2.index_offset.checked_add(index_size));
+                hdr_v2.index_offset != 0
+                    && index_end.is_some_and(|end| end <= self.file_len)
+            },
+            None => false,
+        }
+    }
+
+    /// Size of a record header for this version.
+    pub fn record_hdr_size(&self) -> u64 {
+        match self.version {
+            ZdmpVersion::V10 => mem::size_of::<ZdmpBlockHdr>() as u64,
+            ZdmpVersion::V20 => mem::size_of::<ZdmpBlockHdrV2>() as u64,
+        }
+    }
+
+    /// File offset where `next_record` reads next.
+    pub fn next_offset(&self) -> u64 {
+        self.next_offset
+    }
+
+    /// End of the block records: the index for 2.0 files, EOF otherwise.
+    pub fn records_end(&self) -> u64 {
+        match self.hdr_v2 {
+            Some(hdr_v2) if self.has_index() => hdr_v2.index_offset,
+            _ => self.file_len,
+        }
+    }
+
+    /// Read the record header at `offset`, whose first block is `block_id`.
+    pub fn read_record(&mut self, offset: u64, block_id: u64) -> Result<ZdmpRecord> {
+        self.rdr.seek(SeekFrom::Start(offset))?;
+
+        let rec = match self.version {
+            ZdmpVersion::V10 => {
+                let block_hdr = ZdmpBlockHdr::new(&mut self.rdr)?;
+                let flags = if block_hdr.data_size == self.hdr.block_size {
+                    BLOCK_FLAG_RAW
+                } else {
+                    BLOCK_FLAG_COMPRESSED
+                };
+
+                ZdmpRecord {
+                    offset, block_id, flags,
+                    hdr_size: self.record_hdr_size(),
+                    block_count: 1,
+                    data_size: block_hdr.data_size,
+                    crc32: block_hdr.crc32,
+                    ref_block: 0,
+                }
+            },
+            ZdmpVersion::V20 => {
+                let block_hdr = ZdmpBlockHdrV2::new(&mut self.rdr)?;
+
+                ZdmpRecord {
+                    offset, block_id,
+                    hdr_size: self.record_hdr_size(),
+                    block_count: block_hdr.block_count as u64,
+                    flags: block_hdr.flags,
+                    data_size: block_hdr.data_size,
+                    crc32: block_hdr.crc32,
+                    ref_block: block_hdr.ref_block as u64,
+                }
+            },
+        };
+
+        if rec.data_size > self.hdr.block_size {
+            return Err(Error::DumpParseError(
+                format!("Unexpected zdump block size: 0x{:x}", rec.data_size)));
+        }
+
+        if rec.block_count == 0 {
+            return Err(Error::DumpParseError(
+                format!("Block record @ 0x{:x} covers no block", offset)));
+        }
+
+        // Only backward references, so that duplicate chains terminate.
+        if rec.flags & BLOCK_FLAG_DUPLICATE != 0
+            && (rec.ref_block >= rec.block_id || rec.block_count != 1) {
+            return Err(Error::DumpParseError(
+                format!("Block #{} has an invalid duplicate reference to block #{}",
+                    rec.block_id, rec.ref_block)));
+        }
+
+        Ok(rec)
+    }
+
+    /// Next record in file order, or `None` at the end of the stream.
+    pub fn next_record(&mut self) -> Result<Option<ZdmpRecord>> {
+        if self.next_offset >= self.records_end() {
+            return Ok(None);
+        }
+
+        let rec = self.read_record(self.next_offset, self.next_block)?;
+        self.next_offset = rec.next_offset();
+        self.next_block += rec.block_count;
+
+        Ok(Some(rec))
+    }
+
+    /// Restart `next_record` from the first record.
+    pub fn rewind(&mut self) {
+        self.next_offset = ZDMP_BLOCK_START_OFFSET;
+        self.next_block = 0;
+    }
+
+    /// Read the raw payload of `rec`.
+    pub fn read_payload(&mut self, rec: &ZdmpRecord) -> Result<Vec<u8>> {
+        self.read_raw(rec.data_offset(), rec.data_size as usize)
+    }
+
+    /// Read `len` bytes of the file at `offset`.
+    pub fn read_raw(&mut self, offset: u64, len: usize) -> Result<Vec<u8>> {
+        let mut data = vec![0; len];
+        self.rdr.seek(SeekFrom::Start(offset))?;
+        self.rdr.read_exact(&mut data)?;
+
+        Ok(data)
+    }
+
+    /// Decode one block of `rec` into `out`.
+    ///
+    /// Records covering several blocks expand to the same block repeated
+    /// `block_count` times. Duplicate records decode the block they refer to,
+    /// which cannot be a duplicate itself: the writer never chains them.
+    pub fn decode_record(&mut self, rec: &ZdmpRecord, out: &mut Vec<u8>) -> Result<()> {
+        let target;
+        let rec = if rec.flags & BLOCK_FLAG_DUPLICATE != 0 {
+            target = self.find_record(rec.ref_block)?;
+            if target.flags & BLOCK_FLAG_DUPLICATE != 0 {
+                return Err(Error::DumpParseError(
+                    format!("Block #{} is a duplicate of duplicate block #{}",
+                        rec.block_id, rec.ref_block)));
+            }
+            &target
+        } else {
+            rec
+        };
+
+        let data = self.read_payload(rec)?;
+        zdmp::decode_payload(self.block_size(), rec.flags, rec.crc32, &data, out)
+    }
+
+    /// Load the 2.0 trailing index, or build one by walking the record
+    /// headers.
+    pub fn build_index(&mut self) -> Result<()> {
+        if self.index.is_some() {
+            return Ok(());
+        }
+
+        let index = match self.hdr_v2 {
+            Some(hdr_v2) if self.has_index() => self.load_index(&hdr_v2)?,
+            _ => self.scan_index()?,
+        };
+
+        self.index = Some(index);
+
+        Ok(())
+    }
+
+    /// Load the 2.0 trailing index without building a fallback one.
+    pub fn load_index(&mut self, hdr_v2: &ZdmpFileHdrV2) -> Result<Vec<ZdmpIndexEntry>> {
+        let entry_size = mem::size_of::<ZdmpIndexEntry>();
+        let mut buf = vec![0; hdr_v2.record_count as usize * entry_size];
+
+        self.rdr.seek(SeekFrom::Start(hdr_v2.index_offset))?;
+        self.rdr.read_exact(&mut buf)?;
+
+        let checksum = CRC32_IEEE.checksum(&buf);
+        if checksum != hdr_v2.index_crc32 {
+            return Err(Error::DumpParseError(
+                format!("Incorrect index crc32. 0x{:x} (expected 0x{:x})",
+                    checksum, { hdr_v2.index_crc32 })));
+        }
+
+        buf.chunks(entry_size)
+            .map(|mut entry| read_type!(&mut entry, ZdmpIndexEntry))
+            .collect()
+    }
+
+    fn scan_index(&mut self) -> Result<Vec<ZdmpIndexEntry>> {
+        let mut index = Vec::new();
+
+        let (next_offset, next_block) = (self.next_offset, self.next_block);
+        self.rewind();
+
+        while let Some(rec) = self.next_record()? {
+            index.push(ZdmpIndexEntry { block_id: rec.block_id, offset: rec.offset });
+        }
+
+        self.next_offset = next_offset;
+        self.next_block = next_block;
+
+        Ok(index)
+    }
+
+    /// Record covering uncompressed block `block_id`.
+    pub fn find_record(&mut self, block_id: u64) -> Result<ZdmpRecord> {
+        self.build_index()?;
+        let index = self.index.as_ref().unwrap();
+
+        // Last record starting at or before `block_id`.
+        let pos = index.partition_point(|e| e.block_id <= block_id);
+        let entry = match pos {
+            0 => None,
+            pos => Some(index[pos - 1]),
+        };
+
+        let entry = entry.ok_or_else(|| Error::DumpParseError(
+            format!("Block #{} is not in the zdmp", block_id)))?;
+
+        let rec = self.read_record(entry.offset, entry.block_id)?;
+        if block_id >= rec.block_id + rec.block_count {
+            return Err(Error::DumpParseError(
+                format!("Block #{} is not in the zdmp", block_id)));
+        }
+
+        Ok(rec)
+    }
+
+    /// Decode uncompressed block `block_id` into `out`.
+    pub fn read_block(&mut self, block_id: u64, out: &mut Vec<u8>) -> Result<()> {
+        let rec = self.find_record(block_id)?;
+        self.decode_record(&rec, out)
+    }
+
+    /// First `len` decompressed bytes of the dump.
+    ///
+    /// The records are decoded in file order, so no index is needed.
+    pub fn read_head(&mut self, len: usize) -> Result<Vec<u8>> {
+        let (next_offset, next_block) = (self.next_offset, self.next_block);
+        self.rewind();
+
+        let mut head = Vec::with_capacity(len);
+        let mut block = Vec::with_capacity(self.block_size());
+
+        while head.len() < len {
+            let rec = match self.next_record()? {
+                Some(rec) => rec,
+                None => break,
+            };
+
+            self.decode_record(&rec, &mut block)?;
+            for _ in 0..rec.block_count {
+                if head.len() == len {
+                    break;
+                }
+
+                let take = block.len().min(len - head.len());
+                head.extend_from_slice(&block[..take]);
+            }
+        }
+
+        self.next_offset = next_offset;
+        self.next_block = next_block;
+
+        if head.len() < len {
+            return Err(Error::DumpParseError(
+                format!("Dump is shorter than 0x{:x} bytes", len)));
+        }
+
+        Ok(head)
+    }
+
+    /// Fill `buf` with the decompressed dump bytes at `offset`.
+    pub fn read_at(&mut self, offset: u64, buf: &mut [u8]) -> Result<()> {
+        if offset.checked_add(buf.len() as u64).is_none_or(|end| end > self.uncompressed_size()) {
+            return Err(Error::DumpParseError(
+                format!("Read of 0x{:x} bytes @ 0x{:x} is past the end of the dump",
+                    buf.len(), offset)));
+        }
+
+        let block_size = self.block_size() as u64;
+        let mut block = Vec::with_capacity(self.block_size());
+        let mut done = 0;
+
+        while done < buf.len() {
+            let pos = offset + done as u64;
+            self.read_block(pos / block_size, &mut block)?;
+
+            let start = (pos % block_size) as usize;
+            let len = (block.len() - start).min(buf.len() - done);
+            buf[done..done + len].copy_from_slice(&block[start..start + len]);
+            done += len;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::writer::ZdmpWriter;
+
+    #[test]
+    fn reject_duplicate_chains() {
+        let block_size = 0x1000;
+        let block: Vec<u8> = (0..block_size).map(|i| (i * 7) as u8).collect();
+
+        // One data record followed by duplicates of block #0.
+        let mut writer = ZdmpWriter::new(Cursor::new(Vec::new()), block_size as u32, &[]).unwrap();
+        for _ in 0..3 {
+            writer.write_block(&block).unwrap();
+        }
+        let mut file = writer.finish().unwrap().into_inner();
+
+        let mut reader = ZdmpReader::new(Cursor::new(file.clone()), false).unwrap();
+        let mut out = Vec::new();
+        reader.read_block(2, &mut out).unwrap();
+        assert_eq!(out, block);
+
+        // Point the last duplicate at the first one.
+        reader.next_record().unwrap();
+        reader.next_record().unwrap();
+        let last = reader.next_record().unwrap().unwrap();
+        assert!(last.flags & BLOCK_FLAG_DUPLICATE != 0);
+        let ref_block = last.offset as usize + 20;
+        file[ref_block..ref_block + 4].copy_from_slice(&1u32.to_le_bytes());
+
+        let mut reader = ZdmpReader::new(Cursor::new(file), false).unwrap();
+        assert!(reader.read_block(1, &mut out).is_ok());
+        assert!(matches!(reader.read_block(2, &mut out), Err(Error::DumpParseError(_))));
+    }
+
+    #[test]
+    fn force_unknown_versions_to_the_newest_layout() {
+        let block: Vec<u8> = (0..0x1000).map(|i| (i * 3) as u8).collect();
+
+        let mut writer = ZdmpWriter::new(Cursor::new(Vec::new()), 0x1000, &[]).unwrap();
+        writer.write_block(&block).unwrap();
+        let mut file = writer.finish().unwrap().into_inner();
+        file[4..8].copy_from_slice(&0x0300u32.to_le_bytes());
+
+        assert!(matches!(ZdmpReader::new(Cursor::new(file.clone()), false),
+            Err(Error::UnsupportedVersion(0x0300))));
+
+        let mut reader = ZdmpReader::new(Cursor::new(file), true).unwrap();
+        assert_eq!(reader.version, ZdmpVersion::V20);
+        let mut out = Vec::new();
+        reader.read_block(0, &mut out).unwrap();
+        assert_eq!(out, block);
+    }
+
+    #[test]
+    fn read_at_rejects_wrapping_offsets() {
+        let mut writer = ZdmpWriter::new(Cursor::new(Vec::new()), 0x1000, &[]).unwrap();
+        writer.write_block(&[0x5a; 0x1000]).unwrap();
+        let file = writer.finish().unwrap().into_inner();
+
+        let mut reader = ZdmpReader::new(Cursor::new(file), false).unwrap();
+        let mut buf = [0; 0x10];
+        assert!(reader.read_at(0xff0, &mut buf).is_ok());
+        assert!(matches!(reader.read_at(0xff8, &mut buf), Err(Error::DumpParseError(_))));
+        assert!(matches!(reader.read_at(u64::MAX - 4, &mut buf), Err(Error::DumpParseError(_))));
+    }
+}
@@ -9,26 +9,36 @@ pub enum Error {
 
     // Dumps.
     DumpParseError(String),
-    
+    UnsupportedVersion(u32),
+    DumpWriteError(String),
+    PhysicalAddressNotPresent(u64),
+    VirtualAddressNotValid(u64),
+
+    // Symbols.
+    SymbolError(String),
+
+    // Evidence containers.
+    ContainerError(String),
+
     // Int.
     IntParseError(String),
 }
 
 impl From<std::io::Error> for Error {
     fn from(err: std::io::Error) -> Self {
-        let msg = err.to_string();
-        match err.kind() {
-            _ => Error::IoError(msg),
-        }
+        Error::IoError(err.to_string())
+    }
+}
+
+impl From<pdb::Error> for Error {
+    fn from(err: pdb::Error) -> Self {
+        Error::SymbolError(err.to_string())
     }
 }
 
 impl From<std::num::ParseIntError> for Error {
     fn from(err: std::num::ParseIntError) -> Self {
-        let msg = err.to_string();
-        match err {
-            _ => Error::IntParseError(msg),
-        }
+        Error::IntParseError(err.to_string())
     }
 }
 
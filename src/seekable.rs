@@ -0,0 +1,406 @@
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+use zstd::bulk::{Compressor, Decompressor};
+use zstd::zstd_safe::CParameter;
+
+use crate::result::{Result, Error};
+
+/// Magic of the skippable frame holding the seek table.
+pub const SEEK_TABLE_FRAME_MAGIC:   u32 = 0x184d_2a5e;
+/// Magic at the end of the seek table footer.
+pub const SEEKABLE_MAGIC:           u32 = 0x8f92_eab1;
+pub const SEEK_TABLE_FOOTER_SIZE:   usize = 9;
+/// Seek table descriptor flag of entries with a checksum.
+pub const SEEK_TABLE_CHECKSUM_FLAG: u8 = 0x80;
+
+/// Decompressed size of the frames written by `SeekableWriter`.
+pub const SEEKABLE_FRAME_SIZE:      usize = 0x10_0000;
+pub const SEEKABLE_DEFAULT_LEVEL:   i32 = 9;
+
+/// A frame of a seekable zstd file.
+#[derive(Debug, Copy, Clone)]
+pub struct SeekFrame {
+    pub compressed_offset:      u64,
+    pub decompressed_offset:    u64,
+    pub compressed_size:        u32,
+    pub decompressed_size:      u32,
+    /// Low 32 bits of the XXH64 of the decompressed frame.
+    pub checksum:               Option<u32>,
+}
+
+/// Sizes of a written seekable zstd file.
+#[derive(Debug, Clone)]
+pub struct SeekableSummary {
+    pub frames:             u64,
+    pub decompressed_size:  u64,
+    pub file_size:          u64,
+}
+
+/// Writer of the zstd seekable format: the bytes written are compressed in
+/// independent frames of `SEEKABLE_FRAME_SIZE` bytes, followed by a seek
+/// table in a skippable frame written by `finish`.
+///
+/// Frames carry their content checksum, which is also the checksum of their
+/// seek table entry.
+pub struct SeekableWriter<W: Write> {
+    out:                W,
+    compressor:         Compressor<'static>,
+    frame:              Vec<u8>,
+    /// (compressed size, decompressed size, checksum) of the frames.
+    entries:            Vec<(u32, u32, u32)>,
+    decompressed_size:  u64,
+    file_size:          u64,
+}
+
+impl SeekableWriter<BufWriter<File>> {
+    pub fn create(path: &Path, level: i32) -> Result<Self> {
+        SeekableWriter::new(BufWriter::new(File::create(path)?), level)
+    }
+}
+
+impl<W: Write> SeekableWriter<W> {
+    pub fn new(out: W, level: i32) -> Result<Self> {
+        let mut compressor = Compressor::new(level)?;
+        compressor.set_parameter(CParameter::ChecksumFlag(true))?;
+
+        Ok(SeekableWriter {
+            out,
+            compressor,
+            frame: Vec::with_capacity(SEEKABLE_FRAME_SIZE),
+            entries: Vec::new(),
+            decompressed_size: 0,
+            file_size: 0,
+        })
+    }
+
+    fn write_frame(&mut self) -> io::Result<()> {
+        let compressed = self.compressor.compress(&self.frame)?;
+        let checksum = match compressed.len().checked_sub(4) {
+            Some(at) => u32::from_le_bytes([compressed[at], compressed[at + 1],
+                compressed[at + 2], compressed[at + 3]]),
+            None => 0,
+        };
+
+        self.out.write_all(&compressed)?;
+        self.entries.push((compressed.len() as u32, self.frame.len() as u32, checksum));
+        self.decompressed_size += self.frame.len() as u64;
+        self.file_size += compressed.len() as u64;
+        self.frame.clear();
+
+        Ok(())
+    }
+
+    /// Compress the last frame and write the seek table.
+    pub fn finish(mut self) -> Result<SeekableSummary> {
+        if !self.frame.is_empty() {
+            self.write_frame()?;
+        }
+
+        let mut table = Vec::with_capacity(8 + self.entries.len() * 12 + SEEK_TABLE_FOOTER_SIZE);
+        let size = self.entries.len() * 12 + SEEK_TABLE_FOOTER_SIZE;
+        table.extend_from_slice(&SEEK_TABLE_FRAME_MAGIC.to_le_bytes());
+        table.extend_from_slice(&(size as u32).to_le_bytes());
+        for (compressed_size, decompressed_size, checksum) in &self.entries {
+            table.extend_from_slice(&compressed_size.to_le_bytes());
+            table.extend_from_slice(&decompressed_size.to_le_bytes());
+            table.extend_from_slice(&checksum.to_le_bytes());
+        }
+        table.extend_from_slice(&(self.entries.len() as u32).to_le_bytes());
+        table.push(SEEK_TABLE_CHECKSUM_FLAG);
+        table.extend_from_slice(&SEEKABLE_MAGIC.to_le_bytes());
+
+        self.out.write_all(&table)?;
+        self.out.flush()?;
+
+        Ok(SeekableSummary {
+            frames: self.entries.len() as u64,
+            decompressed_size: self.decompressed_size,
+            file_size: self.file_size + table.len() as u64,
+        })
+    }
+}
+
+impl<W: Write> Write for SeekableWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let len = (SEEKABLE_FRAME_SIZE - self.frame.len()).min(buf.len());
+        self.frame.extend_from_slice(&buf[..len]);
+
+        if self.frame.len() == SEEKABLE_FRAME_SIZE {
+            self.write_frame()?;
+        }
+
+        Ok(len)
+    }
+
+    /// Frames are only written once full, or by `finish`.
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Reader of the zstd seekable format, from its seek table. Only the frames
+/// covering a read are decompressed, the last one is kept.
+pub struct SeekableReader<R> {
+    inner:          R,
+    pub frames:     Vec<SeekFrame>,
+    decompressor:   Decompressor<'static>,
+    /// Last decompressed frame.
+    frame:          Option<(usize, Vec<u8>)>,
+    /// Position of the `Read` implementation.
+    pos:            u64,
+}
+
+impl SeekableReader<BufReader<File>> {
+    pub fn open(path: &Path) -> Result<Self> {
+        SeekableReader::new(BufReader::new(File::open(path)?))
+    }
+}
+
+impl<R: Read + Seek> SeekableReader<R> {
+    pub fn new(mut inner: R) -> Result<Self> {
+        let err = |msg: &str| Error::ContainerError(msg.to_string());
+
+        let file_size = inner.seek(SeekFrom::End(0))?;
+        if file_size < (8 + SEEK_TABLE_FOOTER_SIZE) as u64 {
+            return Err(err("File too small for a zstd seek table"));
+        }
+
+        let mut footer = [0u8; SEEK_TABLE_FOOTER_SIZE];
+        inner.seek(SeekFrom::End(-(SEEK_TABLE_FOOTER_SIZE as i64)))?;
+        inner.read_exact(&mut footer)?;
+
+        let u32_at = |b: &[u8], off: usize| u32::from_le_bytes([b[off], b[off + 1], b[off + 2], b[off + 3]]);
+        if u32_at(&footer, 5) != SEEKABLE_MAGIC {
+            return Err(err("Missing zstd seekable magic"));
+        }
+
+        let number_of_frames = u32_at(&footer, 0) as u64;
+        let descriptor = footer[4];
+        let entry_size = if descriptor & SEEK_TABLE_CHECKSUM_FLAG != 0 { 12 } else { 8 };
+        let table_size = number_of_frames * entry_size + SEEK_TABLE_FOOTER_SIZE as u64;
+        if table_size + 8 > file_size {
+            return Err(err("zstd seek table larger than the file"));
+        }
+
+        let table_offset = file_size - table_size - 8;
+        let mut table = vec![0u8; table_size as usize + 8];
+        inner.seek(SeekFrom::Start(table_offset))?;
+        inner.read_exact(&mut table)?;
+        if u32_at(&table, 0) != SEEK_TABLE_FRAME_MAGIC || u32_at(&table, 4) as u64 != table_size {
+            return Err(err("Invalid zstd seek table frame"));
+        }
+
+        let mut frames = Vec::with_capacity(number_of_frames as usize);
+        let (mut compressed_offset, mut decompressed_offset) = (0, 0);
+        for entry in table[8..].chunks_exact(entry_size as usize).take(number_of_frames as usize) {
+            let frame = SeekFrame {
+                compressed_offset,
+                decompressed_offset,
+                compressed_size: u32_at(entry, 0),
+                decompressed_size: u32_at(entry, 4),
+                checksum: (entry_size == 12).then(|| u32_at(entry, 8)),
+            };
+            compressed_offset += frame.compressed_size as u64;
+            decompressed_offset += frame.decompressed_size as u64;
+            frames.push(frame);
+        }
+
+        if compressed_offset != table_offset {
+            return Err(err("zstd seek table does not match the frames"));
+        }
+
+        Ok(SeekableReader {
+            inner,
+            frames,
+            decompressor: Decompressor::new()?,
+            frame: None,
+            pos: 0,
+        })
+    }
+
+    /// Size of the decompressed content.
+    pub fn decompressed_size(&self) -> u64 {
+        self.frames.last().map_or(0, |f| f.decompressed_offset + f.decompressed_size as u64)
+    }
+
+    /// Decompressed frame `n`, checked against its seek table entry.
+    fn read_frame(&mut self, n: usize) -> Result<&[u8]> {
+        if self.frame.as_ref().map(|(i, _)| *i) != Some(n) {
+            let frame = self.frames[n];
+            let mut data = vec![0u8; frame.compressed_size as usize];
+            self.inner.seek(SeekFrom::Start(frame.compressed_offset))?;
+            self.inner.read_exact(&mut data)?;
+
+            // The decompressor verifies the content checksum of the frame.
+            let out = self.decompressor.decompress(&data, frame.decompressed_size as usize)?;
+            if out.len() != frame.decompressed_size as usize {
+                return Err(Error::ContainerError(format!("Short zstd frame {}", n)));
+            }
+            if let Some(checksum) = frame.checksum {
+                if data.len() < 4 || data[data.len() - 4..] != checksum.to_le_bytes() {
+                    return Err(Error::ContainerError(
+                        format!("Checksum of zstd frame {} does not match its seek table entry", n)));
+                }
+            }
+
+            self.frame = Some((n, out));
+        }
+
+        Ok(self.frame.as_ref().map(|(_, data)| data.as_slice()).unwrap_or(&[]))
+    }
+
+    /// Decompress every frame, checking their content checksums.
+    pub fn verify(&mut self) -> Result<()> {
+        for n in 0..self.frames.len() {
+            self.read_frame(n)?;
+        }
+        Ok(())
+    }
+
+    /// Fill `buf` from the decompressed content at `offset`.
+    pub fn read_at(&mut self, offset: u64, buf: &mut [u8]) -> Result<()> {
+        if offset.checked_add(buf.len() as u64).is_none_or(|end| end > self.decompressed_size()) {
+            return Err(Error::ContainerError(
+                format!("Read @ 0x{:x} past the end of the zstd content", offset)));
+        }
+
+        let mut done = 0;
+        while done < buf.len() {
+            let pos = offset + done as u64;
+            let n = self.frames.partition_point(|f| f.decompressed_offset + f.decompressed_size as u64 <= pos);
+            let start = (pos - self.frames[n].decompressed_offset) as usize;
+
+            let frame = self.read_frame(n)?;
+            let len = (frame.len() - start).min(buf.len() - done);
+            buf[done..done + len].copy_from_slice(&frame[start..start + len]);
+            done += len;
+        }
+
+        Ok(())
+    }
+}
+
+impl<R: Read + Seek> Read for SeekableReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = (self.decompressed_size().saturating_sub(self.pos)).min(buf.len() as u64) as usize;
+        self.read_at(self.pos, &mut buf[..len])
+            .map_err(|e| io::Error::other(format!("{:?}", e)))?;
+        self.pos += len as u64;
+        Ok(len)
+    }
+}
+
+impl<R: Read + Seek> Seek for SeekableReader<R> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let pos = match pos {
+            SeekFrom::Start(pos) => Some(pos),
+            SeekFrom::End(delta) => self.decompressed_size().checked_add_signed(delta),
+            SeekFrom::Current(delta) => self.pos.checked_add_signed(delta),
+        };
+
+        self.pos = pos.ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput,
+            "Seek before the start of the zstd content"))?;
+        Ok(self.pos)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    /// Deterministic xorshift bytes.
+    fn noise(seed: u64, len: usize) -> Vec<u8> {
+        let mut x = 2 * seed + 1;
+        (0..len).map(|_| {
+            x ^= x << 13;
+            x ^= x >> 7;
+            x ^= x << 17;
+            x as u8
+        }).collect()
+    }
+
+    /// Three frames, written in pieces that do not line up with them.
+    fn write_seekable() -> (Vec<u8>, Vec<u8>) {
+        let text = b"\x00\x00\x00\x00MmSt nt!PspCidTable ".repeat(0xb000);
+        let mut data = noise(1, SEEKABLE_FRAME_SIZE - 0x321);
+        data.extend_from_slice(&text[..SEEKABLE_FRAME_SIZE]);
+        data.extend_from_slice(&noise(2, 0x5555));
+
+        let mut file = Vec::new();
+        let mut writer = SeekableWriter::new(&mut file, 3).unwrap();
+        for piece in data.chunks(0x1234) {
+            writer.write_all(piece).unwrap();
+        }
+        let summary = writer.finish().unwrap();
+
+        assert_eq!(summary.frames, 3);
+        assert_eq!(summary.decompressed_size, data.len() as u64);
+        assert_eq!(summary.file_size, file.len() as u64);
+        (data, file)
+    }
+
+    /// Offset of seek table entry `n` of a file of three frames.
+    fn entry_offset(file: &[u8], n: usize) -> usize {
+        file.len() - SEEK_TABLE_FOOTER_SIZE - 3 * 12 + n * 12
+    }
+
+    #[test]
+    fn round_trip_read_at() {
+        let (data, file) = write_seekable();
+
+        let mut reader = SeekableReader::new(Cursor::new(file)).unwrap();
+        assert_eq!(reader.frames.len(), 3);
+        assert_eq!(reader.decompressed_size(), data.len() as u64);
+        reader.verify().unwrap();
+
+        // Reads inside a frame and across the frame boundaries.
+        let boundary = SEEKABLE_FRAME_SIZE;
+        for (offset, len) in [(0x10, 0x20), (boundary - 0x10, 0x20), (boundary - 1, boundary + 2), (data.len() - 1, 1)] {
+            let mut buf = vec![0u8; len];
+            reader.read_at(offset as u64, &mut buf).unwrap();
+            assert!(buf[..] == data[offset..offset + len]);
+        }
+
+        let mut out = Vec::new();
+        reader.seek(SeekFrom::Start(0)).unwrap();
+        reader.read_to_end(&mut out).unwrap();
+        assert!(out == data);
+
+        // Past the end.
+        assert!(matches!(reader.read_at(data.len() as u64 - 1, &mut [0u8; 2]), Err(Error::ContainerError(_))));
+        assert!(matches!(reader.read_at(data.len() as u64, &mut [0u8; 1]), Err(Error::ContainerError(_))));
+        assert!(matches!(reader.read_at(u64::MAX, &mut [0u8; 1]), Err(Error::ContainerError(_))));
+    }
+
+    #[test]
+    fn reject_bad_entry_checksum() {
+        let (data, mut file) = write_seekable();
+        let checksum = entry_offset(&file, 1) + 8;
+        file[checksum] ^= 0x01;
+
+        let mut reader = SeekableReader::new(Cursor::new(file)).unwrap();
+        assert!(reader.read_frame(0).is_ok());
+        assert!(matches!(reader.read_frame(1), Err(Error::ContainerError(_))));
+        assert!(reader.read_at(SEEKABLE_FRAME_SIZE as u64, &mut [0u8; 1]).is_err());
+        assert!(reader.verify().is_err());
+
+        let mut buf = [0u8; 0x10];
+        reader.read_at(0x100, &mut buf).unwrap();
+        assert!(buf[..] == data[0x100..0x110]);
+    }
+
+    #[test]
+    fn reject_table_not_matching_the_frames() {
+        let (_, mut file) = write_seekable();
+        let compressed_size = entry_offset(&file, 0);
+        let entry = &file[compressed_size..];
+        let size = u32::from_le_bytes([entry[0], entry[1], entry[2], entry[3]]);
+        file[compressed_size..compressed_size + 4].copy_from_slice(&(size + 1).to_le_bytes());
+
+        assert!(matches!(SeekableReader::new(Cursor::new(file)), Err(Error::ContainerError(_))));
+    }
+}
@@ -0,0 +1,272 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+
+use pdb::{FallibleIterator, SymbolData, TypeData, TypeIndex, PDB};
+
+use crate::modules::KernelModule;
+use crate::paging::AddressSpace;
+use crate::pe::{self, CodeViewInfo, PeHeader, IMAGE_SIZEOF_DEBUG_DIRECTORY};
+use crate::physmem::PhysicalMemory;
+use crate::result::{Result, Error};
+use crate::zdmp::PAGE_SIZE;
+
+/// Module name that refers to the kernel, whatever its image name.
+pub const KERNEL_MODULE_ALIAS: &str = "nt";
+
+/// Largest number of debug directory entries read.
+const MAX_DEBUG_ENTRIES: u32 = 16;
+
+/// Local symbol directory with the symbol server layout
+/// `<root>/<pdb name>/<GUID><age>/<pdb name>`.
+#[derive(Debug, Clone)]
+pub struct SymbolStore {
+    pub root:   PathBuf,
+}
+
+impl SymbolStore {
+    /// Symbol directory from a path or a `srv*<cache>*<server>` path, where
+    /// only the local cache is used.
+    pub fn new(spec: &str) -> Self {
+        let root = match spec.split_once('*') {
+            Some((prefix, rest)) if prefix.eq_ignore_ascii_case("srv")
+                || prefix.eq_ignore_ascii_case("cache") => rest.split('*').next().unwrap_or(rest),
+            _ => spec,
+        };
+
+        SymbolStore { root: PathBuf::from(root) }
+    }
+
+    /// Path of the PDB of `cv`.
+    pub fn find(&self, cv: &CodeViewInfo) -> Result<PathBuf> {
+        let name = cv.pdb_file_name();
+
+        [name.to_string(), name.to_lowercase()].iter()
+            .map(|name| self.root.join(name).join(cv.signature()).join(name))
+            .find(|path| path.is_file())
+            .ok_or_else(|| Error::SymbolError(format!("No {} {} in {}",
+                name, cv.signature(), self.root.display())))
+    }
+}
+
+/// Size and field offsets of a structure or union.
+#[derive(Debug, Clone, Default)]
+pub struct TypeLayout {
+    pub size:   u64,
+    /// (name, offset), in declaration order.
+    pub fields: Vec<(String, u64)>,
+}
+
+impl TypeLayout {
+    pub fn field(&self, name: &str) -> Option<u64> {
+        self.fields.iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, offset)| *offset)
+    }
+}
+
+/// Globals and type layouts of a loaded module, from its PDB.
+#[derive(Debug, Clone)]
+pub struct ModuleSymbols {
+    pub name:       String,
+    pub base:       u64,
+    pub cv:         CodeViewInfo,
+    /// Global name to RVA.
+    globals:        HashMap<String, u32>,
+    types:          HashMap<String, TypeLayout>,
+}
+
+impl ModuleSymbols {
+    /// Load the PDB at `path` for the module `name` at `base`. Its GUID must
+    /// match `cv`.
+    pub fn load(path: &Path, name: &str, base: u64, cv: &CodeViewInfo) -> Result<Self> {
+        let mut pdb = PDB::open(File::open(path)?)?;
+
+        let info = pdb.pdb_information()?;
+        if info.guid.to_bytes_le() != cv.guid {
+            return Err(Error::SymbolError(format!("{} does not match the {} GUID",
+                path.display(), name)));
+        }
+
+        let address_map = pdb.address_map()?;
+        let mut globals = HashMap::new();
+
+        let symbol_table = pdb.global_symbols()?;
+        let mut symbols = symbol_table.iter();
+        while let Some(symbol) = symbols.next()? {
+            let (name, offset) = match symbol.parse() {
+                Ok(SymbolData::Public(data)) => (data.name, data.offset),
+                Ok(SymbolData::Data(data)) => (data.name, data.offset),
+                _ => continue,
+            };
+
+            if let Some(rva) = offset.to_rva(&address_map) {
+                globals.entry(name.to_string().into_owned()).or_insert(rva.0);
+            }
+        }
+
+        Ok(ModuleSymbols {
+            name: name.to_string(),
+            base,
+            cv: cv.clone(),
+            globals,
+            types: load_types(&mut pdb)?,
+        })
+    }
+
+    /// Virtual address of the global `name`. Names decorated with a leading
+    /// underscore (x86) are found by their plain name too.
+    pub fn global(&self, name: &str) -> Option<u64> {
+        self.globals.get(name)
+            .or_else(|| self.globals.get(&format!("_{}", name)))
+            .map(|&rva| self.base + rva as u64)
+    }
+
+    /// Layout of the structure or union `name`, also looked up as the last
+    /// component of namespaced names.
+    pub fn layout(&self, name: &str) -> Option<&TypeLayout> {
+        self.types.get(name).or_else(|| {
+            let suffix = format!("::{}", name);
+            self.types.iter()
+                .find(|(n, _)| n.ends_with(&suffix))
+                .map(|(_, layout)| layout)
+        })
+    }
+
+    /// Offset of `field` in the structure `type_name`.
+    pub fn field_offset(&self, type_name: &str, field: &str) -> Result<u64> {
+        self.layout(type_name)
+            .ok_or_else(|| Error::SymbolError(format!("No type {}!{}", self.name, type_name)))?
+            .field(field)
+            .ok_or_else(|| Error::SymbolError(format!("No field {}!{}.{}", self.name, type_name, field)))
+    }
+}
+
+/// Layouts of the complete structures and unions of a PDB.
+fn load_types(pdb: &mut PDB<File>) -> Result<HashMap<String, TypeLayout>> {
+    let type_information = pdb.type_information()?;
+    let mut finder = type_information.finder();
+    let mut records = Vec::new();
+
+    let mut iter = type_information.iter();
+    while let Some(item) = iter.next()? {
+        finder.update(&iter);
+
+        match item.parse() {
+            Ok(TypeData::Class(class)) if !class.properties.forward_reference() => {
+                if let Some(fields) = class.fields {
+                    records.push((class.name.to_string().into_owned(), class.size, fields));
+                }
+            },
+            Ok(TypeData::Union(union)) if !union.properties.forward_reference() => {
+                records.push((union.name.to_string().into_owned(), union.size, union.fields));
+            },
+            _ => (),
+        }
+    }
+
+    let mut types = HashMap::new();
+    for (name, size, fields) in records {
+        let mut layout = TypeLayout { size, fields: Vec::new() };
+        let mut next = Some(fields);
+
+        // Long field lists continue in other records.
+        while let Some(index) = next.take() {
+            if let Ok(TypeData::FieldList(list)) = finder.find(index).and_then(|t| t.parse()) {
+                for field in list.fields {
+                    if let TypeData::Member(member) = field {
+                        layout.fields.push((member.name.to_string().into_owned(), member.offset));
+                    }
+                }
+                next = list.continuation.filter(|&c: &TypeIndex| c != index);
+            }
+        }
+
+        types.entry(name).or_insert(layout);
+    }
+
+    Ok(types)
+}
+
+/// Read the CodeView record of the image mapped at `base`.
+pub fn read_codeview<M: PhysicalMemory>(space: &mut AddressSpace<M>, base: u64) -> Result<CodeViewInfo> {
+    let mut header = vec![0u8; PAGE_SIZE];
+    space.read_virt(base, &mut header)?;
+    let hdr = PeHeader::parse(&header)?;
+
+    let (rva, size) = hdr.debug_directory;
+    let count = (size / IMAGE_SIZEOF_DEBUG_DIRECTORY as u32).min(MAX_DEBUG_ENTRIES);
+    let mut entries = vec![0u8; count as usize * IMAGE_SIZEOF_DEBUG_DIRECTORY];
+    space.read_virt(base + rva as u64, &mut entries)?;
+
+    let (data_rva, data_size) = pe::codeview_entry(&entries).ok_or_else(||
+        Error::SymbolError(format!("No CodeView record in the image @ 0x{:x}", base)))?;
+
+    let mut data = vec![0u8; (data_size as usize).min(PAGE_SIZE)];
+    space.read_virt(base + data_rva as u64, &mut data)?;
+
+    CodeViewInfo::parse(&data)
+}
+
+/// Symbols of the loaded modules, loaded on demand from a symbol store.
+pub struct SymbolResolver {
+    pub store:      SymbolStore,
+    pub modules:    Vec<KernelModule>,
+    loaded:         Vec<ModuleSymbols>,
+}
+
+impl SymbolResolver {
+    /// Resolver over `modules`, whose first entry is the kernel.
+    pub fn new(store: SymbolStore, modules: Vec<KernelModule>) -> Self {
+        SymbolResolver { store, modules, loaded: Vec::new() }
+    }
+
+    /// Symbols of the module `name` (its image name, with or without the
+    /// extension, or `nt` for the kernel), loaded from its PDB if needed.
+    pub fn module<M: PhysicalMemory>(
+        &mut self,
+        space: &mut AddressSpace<M>,
+        name: &str
+    ) -> Result<&ModuleSymbols> {
+        let module = self.modules.iter().enumerate()
+            .find(|(n, module)| {
+                let base_name = module.base_name.to_lowercase();
+                let stem = base_name.rsplit_once('.').map(|(stem, _)| stem).unwrap_or(&base_name);
+                let name = name.to_lowercase();
+
+                (*n == 0 && name == KERNEL_MODULE_ALIAS) || base_name == name || stem == name
+            })
+            .map(|(_, module)| module.clone())
+            .ok_or_else(|| Error::SymbolError(format!("No loaded module {}", name)))?;
+
+        let pos = match self.loaded.iter().position(|m| m.base == module.base) {
+            Some(pos) => pos,
+            None => {
+                let cv = read_codeview(space, module.base)?;
+                let path = self.store.find(&cv)?;
+                info!("Loading {} symbols from {}", module.base_name, path.display());
+
+                self.loaded.push(ModuleSymbols::load(&path, &module.base_name, module.base, &cv)?);
+                self.loaded.len() - 1
+            },
+        };
+
+        Ok(&self.loaded[pos])
+    }
+
+    /// Address of `module!global`.
+    pub fn resolve<M: PhysicalMemory>(&mut self, space: &mut AddressSpace<M>, expr: &str) -> Result<u64> {
+        let (module, name) = split_symbol(expr)?;
+        let symbols = self.module(space, module)?;
+
+        symbols.global(name)
+            .ok_or_else(|| Error::SymbolError(format!("No symbol {}", expr)))
+    }
+}
+
+/// Split `module!name`.
+pub fn split_symbol(expr: &str) -> Result<(&str, &str)> {
+    expr.split_once('!')
+        .filter(|(module, name)| !module.is_empty() && !name.is_empty())
+        .ok_or_else(|| Error::SymbolError(format!("Expected module!name: `{}`", expr)))
+}
@@ -9,7 +9,7 @@ macro_rules! trace_func {
 #[macro_export]
 macro_rules! trace_hexdump {
     ($addr: expr, $sym: expr, $vec: expr) => {{
-        for s in crate::hexdump::hexdump($addr, &$vec)
+        for s in $crate::hexdump::hexdump($addr, &$vec)
             .split(|c| c == '\n')
         {
             trace_func!("{}: {}", $sym, s);
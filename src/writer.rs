@@ -0,0 +1,450 @@
+use std::collections::{HashMap, VecDeque};
+use std::convert::TryFrom;
+use std::fs::File;
+use std::io::{BufWriter, Read, Seek, SeekFrom, Write};
+use std::mem;
+use std::path::Path;
+use std::rc::Rc;
+
+use crate::bytes::as_bytes;
+use crate::lznt1;
+use crate::result::{Result, Error};
+use crate::zdmp::{
+    ZdmpFileHdr, ZdmpFileHdrV2, ZdmpBlockHdrV2, ZdmpIndexEntry, ZdmpMetadata,
+    CRC32_IEEE, ZDMP_FILE_SIGNATURE, ZDMP_FILE_VERSION_20, ZDMP_BLOCK_V2_SIGNATURE,
+    ZDMP_BLOCK_START_OFFSET, PAGE_SIZE, BLOCK_DATA_TYPE_COMPRESSION, COMPRESSION_FORMAT_LZNT1,
+    BLOCK_FLAG_RAW, BLOCK_FLAG_COMPRESSED, BLOCK_FLAG_ZERO_RUN, BLOCK_FLAG_DUPLICATE,
+};
+
+/// Default block size of the writer.
+pub const ZDMP_DEFAULT_BLOCK_SIZE:  u32 = 0x10000;
+
+/// Number of distinct recent blocks kept in memory to find duplicates.
+pub const ZDMP_DEDUP_WINDOW:        usize = 256;
+
+/// Record counts of a `ZdmpWriter`.
+#[derive(Debug, Default, Copy, Clone)]
+pub struct ZdmpWriterStats {
+    pub blocks:             u64,
+    pub zero_blocks:        u64,
+    pub duplicate_blocks:   u64,
+    pub uncompressed_size:  u64,
+    pub compressed_size:    u64,
+}
+
+/// Writes a ZDMP 2.0 file.
+///
+/// Records are appended sequentially after the header page. The header page
+/// is rewritten by `finish` once the index offset and the total size are
+/// known, so an unfinished file has no index.
+///
+/// Consecutive zero blocks are merged into a single zero-run record, and a
+/// block equal to one of the last `ZDMP_DEDUP_WINDOW` distinct blocks is
+/// written as a duplicate record.
+pub struct ZdmpWriter<W> {
+    wtr:                    W,
+    hdr:                    ZdmpFileHdr,
+    metadata:               Vec<u8>,
+    index:                  Vec<ZdmpIndexEntry>,
+    offset:                 u64,
+    block_id:               u64,
+    compressed:             Vec<u8>,
+    zero_run:               u32,
+    /// Block ids of the last `ZDMP_DEDUP_WINDOW` distinct blocks, and the
+    /// same blocks in the order they were written.
+    recent:                 HashMap<Rc<[u8]>, u64>,
+    recent_order:           VecDeque<Rc<[u8]>>,
+    stats:                  ZdmpWriterStats,
+}
+
+impl ZdmpWriter<BufWriter<File>> {
+    pub fn create(path: &Path, block_size: u32, metadata: &[ZdmpMetadata]) -> Result<Self> {
+        let file = File::create(path)?;
+        ZdmpWriter::new(BufWriter::new(file), block_size, metadata)
+    }
+}
+
+impl<W: Write + Seek> ZdmpWriter<W> {
+    pub fn new(wtr: W, block_size: u32, metadata: &[ZdmpMetadata]) -> Result<Self> {
+        if block_size == 0 || !(block_size as usize).is_multiple_of(PAGE_SIZE) {
+            return Err(Error::DumpWriteError(
+                format!("Block size 0x{:x} is not a multiple of 0x{:x}", block_size, PAGE_SIZE)));
+        }
+
+        let mut meta_buf = Vec::new();
+        for meta in metadata {
+            meta.encode(&mut meta_buf)?;
+        }
+
+        let meta_offset = mem::size_of::<ZdmpFileHdr>() + mem::size_of::<ZdmpFileHdrV2>();
+        if meta_offset + meta_buf.len() > PAGE_SIZE {
+            return Err(Error::DumpWriteError(
+                format!("Metadata does not fit in the header page (0x{:x} bytes)", meta_buf.len())));
+        }
+
+        let hdr = ZdmpFileHdr {
+            signature: ZDMP_FILE_SIGNATURE,
+            version: ZDMP_FILE_VERSION_20,
+            file_size: 0,
+            block_size,
+            data_type: BLOCK_DATA_TYPE_COMPRESSION,
+            compression_format: COMPRESSION_FORMAT_LZNT1,
+        };
+
+        let mut writer = ZdmpWriter {
+            wtr, hdr,
+            metadata: meta_buf,
+            index: Vec::new(),
+            offset: ZDMP_BLOCK_START_OFFSET,
+            block_id: 0,
+            compressed: Vec::with_capacity(block_size as usize),
+            zero_run: 0,
+            recent: HashMap::new(),
+            recent_order: VecDeque::new(),
+            stats: ZdmpWriterStats::default(),
+        };
+
+        writer.write_header_page(&ZdmpFileHdrV2::default())?;
+
+        Ok(writer)
+    }
+
+    fn write_header_page(&mut self, hdr_v2: &ZdmpFileHdrV2) -> Result<()> {
+        let mut hdr_v2 = *hdr_v2;
+        hdr_v2.metadata_size = self.metadata.len() as u32;
+
+        let mut page = Vec::with_capacity(PAGE_SIZE);
+        page.extend_from_slice(as_bytes(&self.hdr));
+        page.extend_from_slice(as_bytes(&hdr_v2));
+        page.extend_from_slice(&self.metadata);
+        page.resize(PAGE_SIZE, 0);
+
+        self.wtr.seek(SeekFrom::Start(0))?;
+        self.wtr.write_all(&page)?;
+
+        Ok(())
+    }
+
+    fn write_record(
+        &mut self,
+        flags: u32,
+        block_count: u32,
+        ref_block: u32,
+        payload: &[u8]
+    ) -> Result<()> {
+        let block_hdr = ZdmpBlockHdrV2 {
+            signature: ZDMP_BLOCK_V2_SIGNATURE,
+            flags,
+            data_size: payload.len() as u32,
+            crc32: CRC32_IEEE.checksum(payload),
+            block_count,
+            ref_block,
+        };
+
+        self.index.push(ZdmpIndexEntry { block_id: self.block_id, offset: self.offset });
+
+        self.wtr.write_all(as_bytes(&block_hdr))?;
+        self.wtr.write_all(payload)?;
+
+        self.offset += (mem::size_of::<ZdmpBlockHdrV2>() + payload.len()) as u64;
+        self.block_id += block_count as u64;
+
+        Ok(())
+    }
+
+    /// Write the pending zero blocks as one zero-run record.
+    fn flush_zero_run(&mut self) -> Result<()> {
+        if self.zero_run == 0 {
+            return Ok(());
+        }
+
+        let block_count = mem::take(&mut self.zero_run);
+        self.write_record(BLOCK_FLAG_ZERO_RUN, block_count, 0, &[])
+    }
+
+    /// Append one block of uncompressed data.
+    ///
+    /// Only the last block may be shorter than the block size; it is padded
+    /// with zeros and `file_size` keeps the real length.
+    pub fn write_block(&mut self, data: &[u8]) -> Result<()> {
+        let block_size = self.hdr.block_size as usize;
+
+        if data.len() > block_size || !self.hdr.file_size.is_multiple_of(block_size as u64) {
+            return Err(Error::DumpWriteError(
+                format!("Unexpected block of 0x{:x} bytes after a short block", data.len())));
+        }
+
+        self.hdr.file_size += data.len() as u64;
+        self.stats.blocks += 1;
+
+        if data.iter().all(|&b| b == 0) {
+            self.stats.zero_blocks += 1;
+            self.zero_run += 1;
+            if self.zero_run == u32::MAX {
+                self.flush_zero_run()?;
+            }
+            return Ok(());
+        }
+
+        self.flush_zero_run()?;
+
+        if let Some(&ref_block) = self.recent.get(data) {
+            if let Ok(ref_block) = u32::try_from(ref_block) {
+                self.stats.duplicate_blocks += 1;
+                return self.write_record(BLOCK_FLAG_DUPLICATE, 1, ref_block, &[]);
+            }
+        }
+
+        if data.len() == block_size {
+            if self.recent_order.len() >= ZDMP_DEDUP_WINDOW {
+                if let Some(oldest) = self.recent_order.pop_front() {
+                    self.recent.remove(&oldest);
+                }
+            }
+            let key: Rc<[u8]> = Rc::from(data);
+            self.recent.insert(key.clone(), self.block_id);
+            self.recent_order.push_back(key);
+        }
+
+        let mut compressed = mem::take(&mut self.compressed);
+        compressed.clear();
+        lznt1::compress(data, &mut compressed);
+
+        let res = if data.len() == block_size && compressed.len() < block_size {
+            self.write_record(BLOCK_FLAG_COMPRESSED, 1, 0, &compressed)
+        } else if data.len() == block_size {
+            self.write_record(BLOCK_FLAG_RAW, 1, 0, data)
+        } else {
+            let mut padded = data.to_vec();
+            padded.resize(block_size, 0);
+            self.write_record(BLOCK_FLAG_RAW, 1, 0, &padded)
+        };
+
+        self.compressed = compressed;
+
+        res
+    }
+
+    /// Write the trailing index and the final header page.
+    pub fn finish(mut self) -> Result<W> {
+        self.flush_zero_run()?;
+
+        let mut index = Vec::with_capacity(self.index.len() * mem::size_of::<ZdmpIndexEntry>());
+        for entry in &self.index {
+            index.extend_from_slice(as_bytes(entry));
+        }
+
+        self.wtr.seek(SeekFrom::Start(self.offset))?;
+        self.wtr.write_all(&index)?;
+
+        let hdr_v2 = ZdmpFileHdrV2 {
+            index_offset: self.offset,
+            record_count: self.index.len() as u64,
+            index_crc32: CRC32_IEEE.checksum(&index),
+            metadata_size: 0,
+        };
+        self.write_header_page(&hdr_v2)?;
+        self.wtr.flush()?;
+
+        Ok(self.wtr)
+    }
+
+    /// Record counts and sizes so far. The compressed size does not include
+    /// the index or pending zero runs.
+    pub fn stats(&self) -> ZdmpWriterStats {
+        ZdmpWriterStats {
+            uncompressed_size: self.hdr.file_size,
+            compressed_size: self.offset,
+            ..self.stats
+        }
+    }
+}
+
+/// Compress the raw dump at `in_path` into a ZDMP 2.0 file at `out_path`.
+pub fn compress_file(
+    in_path: &Path,
+    out_path: &Path,
+    block_size: u32,
+    metadata: &[ZdmpMetadata]
+) -> Result<ZdmpWriterStats> {
+    let mut in_file = File::open(in_path)?;
+    let mut writer = ZdmpWriter::create(out_path, block_size, metadata)?;
+
+    let mut block = vec![0; block_size as usize];
+    loop {
+        let len = read_full(&mut in_file, &mut block)?;
+        if len == 0 {
+            break;
+        }
+
+        writer.write_block(&block[..len])?;
+    }
+
+    writer.flush_zero_run()?;
+    let stats = writer.stats();
+    writer.finish()?;
+
+    Ok(stats)
+}
+
+/// Fill `buf` as far as the reader allows and return the length read.
+fn read_full(mut rdr: impl Read, buf: &mut [u8]) -> Result<usize> {
+    let mut len = 0;
+
+    while len < buf.len() {
+        match rdr.read(&mut buf[len..])? {
+            0 => break,
+            n => len += n,
+        }
+    }
+
+    Ok(len)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::reader::ZdmpReader;
+
+    const BLOCK_SIZE: u32 = 0x4000;
+
+    /// Deterministic xorshift bytes.
+    fn noise(seed: u64, len: usize) -> Vec<u8> {
+        let mut x = 2 * seed + 1;
+        (0..len).map(|_| {
+            x ^= x << 13;
+            x ^= x >> 7;
+            x ^= x << 17;
+            x as u8
+        }).collect()
+    }
+
+    fn write_zdmp(data: &[u8], metadata: &[ZdmpMetadata]) -> Vec<u8> {
+        let mut writer = ZdmpWriter::new(Cursor::new(Vec::new()), BLOCK_SIZE, metadata).unwrap();
+        for block in data.chunks(BLOCK_SIZE as usize) {
+            writer.write_block(block).unwrap();
+        }
+        writer.finish().unwrap().into_inner()
+    }
+
+    #[test]
+    fn round_trip_read_at() {
+        let text = b"\x00\x00\x00\x00MmSt nt!PspCidTable ".repeat(0x800);
+        let mut data = Vec::new();
+        data.extend_from_slice(&noise(1, BLOCK_SIZE as usize));
+        data.extend_from_slice(&text[..BLOCK_SIZE as usize * 2]);
+        data.extend_from_slice(&noise(2, BLOCK_SIZE as usize * 3 + 0x123));
+        data.extend_from_slice(&noise(3, 0x777));
+
+        let metadata = vec![
+            ZdmpMetadata::Hostname("lab-01".to_string()),
+            ZdmpMetadata::AcquisitionTime(1_700_000_000),
+        ];
+        let file = write_zdmp(&data, &metadata);
+
+        let mut reader = ZdmpReader::new(Cursor::new(file), false).unwrap();
+        assert!(reader.has_index());
+        assert_eq!(reader.uncompressed_size(), data.len() as u64);
+        assert_eq!(reader.metadata, metadata);
+
+        let mut out = vec![0u8; data.len()];
+        reader.read_at(0, &mut out).unwrap();
+        assert!(out == data);
+
+        // Reads starting and ending inside blocks.
+        for (offset, len) in [(0x10, 0x20), (0x3ff0, 0x20), (0x5432, 0x9876), (data.len() - 1, 1)] {
+            let mut buf = vec![0u8; len];
+            reader.read_at(offset as u64, &mut buf).unwrap();
+            assert!(buf[..] == data[offset..offset + len]);
+        }
+
+        assert!(reader.read_at(data.len() as u64, &mut [0u8; 1]).is_err());
+    }
+
+    #[test]
+    fn round_trip_without_index() {
+        let data = noise(4, BLOCK_SIZE as usize * 3);
+        let mut file = write_zdmp(&data, &[]);
+
+        // Drop the index and clear its offset, like an unfinished writer.
+        let index_offset = file.len() - 3 * mem::size_of::<ZdmpIndexEntry>();
+        file.truncate(index_offset);
+        let hdr_v2 = mem::size_of::<ZdmpFileHdr>();
+        file[hdr_v2..hdr_v2 + 8].copy_from_slice(&0u64.to_le_bytes());
+
+        let mut reader = ZdmpReader::new(Cursor::new(file), false).unwrap();
+        assert!(!reader.has_index());
+
+        let mut out = vec![0u8; data.len()];
+        reader.read_at(0, &mut out).unwrap();
+        assert!(out == data);
+    }
+
+    /// Size of the same data with one compressed or raw record per block,
+    /// without zero runs or duplicates.
+    fn plain_size(data: &[u8]) -> usize {
+        let records = data.chunks(BLOCK_SIZE as usize).map(|block| {
+            let mut compressed = Vec::new();
+            lznt1::compress(block, &mut compressed);
+            mem::size_of::<ZdmpBlockHdrV2>() + compressed.len().min(BLOCK_SIZE as usize)
+                + mem::size_of::<ZdmpIndexEntry>()
+        });
+        PAGE_SIZE + records.sum::<usize>()
+    }
+
+    #[test]
+    fn zero_runs_and_duplicates_shrink_the_file() {
+        // Like a VM dump: code and data pages, free (zero) memory and pages
+        // repeated across processes.
+        let shared = noise(5, BLOCK_SIZE as usize);
+        let mut data = Vec::new();
+        for n in 0..48u64 {
+            match n % 4 {
+                0 => data.extend_from_slice(&noise(n + 100, BLOCK_SIZE as usize)),
+                1 | 2 => data.resize(data.len() + BLOCK_SIZE as usize, 0),
+                _ => data.extend_from_slice(&shared),
+            }
+        }
+
+        let mut writer = ZdmpWriter::new(Cursor::new(Vec::new()), BLOCK_SIZE, &[]).unwrap();
+        for block in data.chunks(BLOCK_SIZE as usize) {
+            writer.write_block(block).unwrap();
+        }
+        writer.flush_zero_run().unwrap();
+        let stats = writer.stats();
+        let file = writer.finish().unwrap().into_inner();
+
+        assert_eq!(stats.zero_blocks, 24);
+        assert_eq!(stats.duplicate_blocks, 11);
+        assert!(file.len() < plain_size(&data) * 2 / 3,
+            "0x{:x} bytes, 0x{:x} without zero runs and duplicates", file.len(), plain_size(&data));
+
+        let mut reader = ZdmpReader::new(Cursor::new(file), false).unwrap();
+        let mut out = vec![0u8; data.len()];
+        reader.read_at(0, &mut out).unwrap();
+        assert!(out == data);
+    }
+
+    #[test]
+    fn dedup_window_keeps_the_latest_blocks() {
+        let blocks: Vec<Vec<u8>> = (0..ZDMP_DEDUP_WINDOW as u64 + 44)
+            .map(|n| noise(n + 1000, BLOCK_SIZE as usize))
+            .collect();
+
+        let mut writer = ZdmpWriter::new(Cursor::new(Vec::new()), BLOCK_SIZE, &[]).unwrap();
+        for block in &blocks {
+            writer.write_block(block).unwrap();
+        }
+        assert_eq!(writer.stats().duplicate_blocks, 0);
+
+        // The first 44 blocks left the window, the next ones are still in it.
+        writer.write_block(&blocks[0]).unwrap();
+        assert_eq!(writer.stats().duplicate_blocks, 0);
+        writer.write_block(&blocks[45]).unwrap();
+        writer.write_block(&blocks[blocks.len() - 1]).unwrap();
+        assert_eq!(writer.stats().duplicate_blocks, 2);
+    }
+
+}
@@ -2,28 +2,31 @@ use std::io::Cursor;
 use std::io::Read;
 use std::path::Path;
 
-use std::io::Write;                                                                                                                                                                  
-// use std::io::prelude::*;                                                                                                                                                             
-use std::fs::File;  
+use std::io::Write;
+use std::fs::File;
 
-use std::io::Seek;
+use std::io::{Seek, SeekFrom};
 
+use std::convert::TryFrom;
+use std::fmt;
 use std::mem;
 
 use std::time::{Instant};
 
+use crate::reader::ZdmpReader;
 use crate::result::{Result, Error};
 
 use crc::{Crc, CRC_32_ISO_HDLC};
 use std::str;
-use lzxpress;
 
 pub const CRC32_IEEE: Crc<u32> = Crc::<u32>::new(&CRC_32_ISO_HDLC);
 
 pub const ZDMP_FILE_SIGNATURE:      u32 = 0x504d_445a;  // ZDMP
 pub const ZDMP_BLOCK_SIGNATURE:     u32 = 0x4b4c_425a;  // KLBZ
+pub const ZDMP_BLOCK_V2_SIGNATURE:  u32 = 0x324c_425a;  // 2LBZ
 
 pub const ZDMP_FILE_VERSION_10:     u32 = 0x0100;
+pub const ZDMP_FILE_VERSION_20:     u32 = 0x0200;
 pub const PAGE_SIZE:                usize = 0x1000;
 pub const ZDMP_BLOCK_START_OFFSET:  u64  = 0x1000;
 
@@ -33,6 +36,20 @@ pub const BLOCK_DATA_TYPE_ENCRYPTION:   u16 = 0x02;
 
 pub const COMPRESSION_FORMAT_LZNT1:     u16 = 0x02;
 
+// Version 2.0 per-block flags.
+pub const BLOCK_FLAG_RAW:               u32 = 0x01;
+pub const BLOCK_FLAG_COMPRESSED:        u32 = 0x02;
+pub const BLOCK_FLAG_ZERO_RUN:          u32 = 0x04;
+pub const BLOCK_FLAG_ENCRYPTED:         u32 = 0x08;
+pub const BLOCK_FLAG_DUPLICATE:         u32 = 0x10;
+
+// Version 2.0 metadata tags.
+pub const ZDMP_META_END:                u16 = 0x00;
+pub const ZDMP_META_HOSTNAME:           u16 = 0x01;
+pub const ZDMP_META_ACQUISITION_TIME:   u16 = 0x02;
+pub const ZDMP_META_TOOL_VERSION:       u16 = 0x03;
+pub const ZDMP_META_CASE_NOTES:         u16 = 0x04;
+
 /// ZDMP File Header
 #[repr(C, packed)]
 #[derive(Debug, Copy, Clone)]
@@ -54,10 +71,74 @@ pub struct ZdmpBlockHdr {
     pub crc32:              u32,
 }
 
+/// ZDMP 2.0 File Header extension, right after `ZdmpFileHdr`.
+///
+/// The metadata TLVs follow it and fill the rest of the header page.
+#[repr(C, packed)]
+#[derive(Debug, Copy, Clone, Default)]
+pub struct ZdmpFileHdrV2 {
+    pub index_offset:       u64,
+    pub record_count:       u64,
+    pub index_crc32:        u32,
+    pub metadata_size:      u32,
+}
+
+/// ZDMP 2.0 Block Header
+///
+/// A record covers `block_count` consecutive uncompressed blocks. Only
+/// zero runs cover more than one. Duplicate records have no payload and
+/// repeat the earlier block `ref_block`.
+#[repr(C, packed)]
+#[derive(Debug, Copy, Clone)]
+pub struct ZdmpBlockHdrV2 {
+    pub signature:          u32,
+    pub flags:              u32,
+    pub data_size:          u32,
+    pub crc32:              u32,
+    pub block_count:        u32,
+    pub ref_block:          u32,
+}
+
+/// ZDMP 2.0 trailing index entry, one per block record.
+#[repr(C, packed)]
+#[derive(Debug, Copy, Clone)]
+pub struct ZdmpIndexEntry {
+    pub block_id:           u64,
+    pub offset:             u64,
+}
+
+/// Typed ZDMP 2.0 metadata TLV.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ZdmpMetadata {
+    Hostname(String),
+    /// Seconds since the Unix epoch.
+    AcquisitionTime(u64),
+    ToolVersion(String),
+    CaseNotes(String),
+    Unknown(u16, Vec<u8>),
+}
+
+/// Container layouts this crate knows how to parse.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ZdmpVersion {
+    V10,
+    V20,
+}
+
+impl ZdmpVersion {
+    pub fn name(&self) -> &'static str {
+        match self {
+            ZdmpVersion::V10 => "1.0",
+            ZdmpVersion::V20 => "2.0",
+        }
+    }
+}
+
 /// ZDMP Block Header
 #[derive(Debug)]
 pub struct ZdmpFile {
     pub hdr:                ZdmpFileHdr,
+    pub version:            ZdmpVersion,
     pub block_count:        u64,
     pub file_size:          u64,
     pub uncompressed_size:  usize,
@@ -79,6 +160,58 @@ impl ZdmpFileHdr {
 
         Ok(hdr)
     }
+
+    /// Map the header version to a known layout.
+    ///
+    /// Unknown versions are rejected unless `force` is set, in which case
+    /// the file is parsed with the newest known layout (2.0) on a best-effort
+    /// basis.
+    pub fn parse_version(&self, force: bool) -> Result<ZdmpVersion> {
+        let version = self.version;
+
+        match version {
+            ZDMP_FILE_VERSION_10 => Ok(ZdmpVersion::V10),
+            ZDMP_FILE_VERSION_20 => Ok(ZdmpVersion::V20),
+            version if force => {
+                warn!("Unknown zdmp version 0x{:x}, parsing as 0x{:x}.",
+                    version, ZDMP_FILE_VERSION_20);
+                Ok(ZdmpVersion::V20)
+            },
+            version => Err(Error::UnsupportedVersion(version)),
+        }
+    }
+
+    pub fn version_name(&self) -> &'static str {
+        match self.version {
+            ZDMP_FILE_VERSION_10 => "1.0",
+            ZDMP_FILE_VERSION_20 => "2.0",
+            _ => "unknown",
+        }
+    }
+
+    pub fn data_type_name(&self) -> &'static str {
+        match self.data_type {
+            BLOCK_DATA_TYPE_NONE => "none",
+            BLOCK_DATA_TYPE_COMPRESSION => "compression",
+            BLOCK_DATA_TYPE_ENCRYPTION => "encryption",
+            _ => "unknown",
+        }
+    }
+
+    pub fn codec_name(&self) -> &'static str {
+        match self.compression_format {
+            COMPRESSION_FORMAT_LZNT1 => "LZNT1",
+            _ => "unknown",
+        }
+    }
+
+    /// Number of blocks needed to hold `file_size` uncompressed bytes.
+    pub fn expected_block_count(&self) -> u64 {
+        match self.block_size {
+            0 => 0,
+            block_size => self.file_size.div_ceil(block_size as u64),
+        }
+    }
 }
 
 
@@ -96,63 +229,285 @@ impl ZdmpBlockHdr {
     }
 }
 
+impl ZdmpFileHdrV2 {
+    pub fn new(mut rdr: impl Read) -> Result<Self> {
+        read_type!(&mut rdr, ZdmpFileHdrV2)
+    }
+}
+
+impl ZdmpBlockHdrV2 {
+    pub fn new(mut rdr: impl Read) -> Result<Self> {
+        let hdr = read_type!(&mut rdr, ZdmpBlockHdrV2)?;
+
+        if hdr.signature != ZDMP_BLOCK_V2_SIGNATURE {
+            return Err(Error::DumpParseError(
+                format!("Unexpected zdump block signature field: 0x{:x}",
+                    { hdr.signature })));
+        }
+
+        Ok(hdr)
+    }
+}
+
+impl ZdmpMetadata {
+    /// Parse the metadata TLVs of the header page.
+    pub fn parse(buf: &[u8]) -> Result<Vec<Self>> {
+        let mut metadata = Vec::new();
+        let mut pos = 0;
+
+        while pos + 4 <= buf.len() {
+            let tag = u16::from_le_bytes([buf[pos], buf[pos + 1]]);
+            let len = u16::from_le_bytes([buf[pos + 2], buf[pos + 3]]) as usize;
+            pos += 4;
+
+            if tag == ZDMP_META_END {
+                break;
+            }
+
+            let value = buf.get(pos..pos + len).ok_or_else(||
+                Error::DumpParseError(format!(
+                    "Metadata tag 0x{:x} overruns the header page", tag)))?;
+            pos += len;
+
+            let text = || String::from_utf8_lossy(value).into_owned();
+
+            metadata.push(match tag {
+                ZDMP_META_HOSTNAME => ZdmpMetadata::Hostname(text()),
+                ZDMP_META_ACQUISITION_TIME if len == 8 => {
+                    let mut time = [0u8; 8];
+                    time.copy_from_slice(value);
+                    ZdmpMetadata::AcquisitionTime(u64::from_le_bytes(time))
+                },
+                ZDMP_META_TOOL_VERSION => ZdmpMetadata::ToolVersion(text()),
+                ZDMP_META_CASE_NOTES => ZdmpMetadata::CaseNotes(text()),
+                _ => ZdmpMetadata::Unknown(tag, value.to_vec()),
+            });
+        }
+
+        Ok(metadata)
+    }
+
+    /// Append the TLV encoding of `self` to `out`.
+    pub fn encode(&self, out: &mut Vec<u8>) -> Result<()> {
+        let (tag, value) = match self {
+            ZdmpMetadata::Hostname(s) => (ZDMP_META_HOSTNAME, s.as_bytes().to_vec()),
+            ZdmpMetadata::AcquisitionTime(t) => (ZDMP_META_ACQUISITION_TIME, t.to_le_bytes().to_vec()),
+            ZdmpMetadata::ToolVersion(s) => (ZDMP_META_TOOL_VERSION, s.as_bytes().to_vec()),
+            ZdmpMetadata::CaseNotes(s) => (ZDMP_META_CASE_NOTES, s.as_bytes().to_vec()),
+            ZdmpMetadata::Unknown(tag, value) => (*tag, value.clone()),
+        };
+
+        let len = u16::try_from(value.len())?;
+        out.extend_from_slice(&tag.to_le_bytes());
+        out.extend_from_slice(&len.to_le_bytes());
+        out.extend_from_slice(&value);
+
+        Ok(())
+    }
+}
+
+impl fmt::Display for ZdmpMetadata {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ZdmpMetadata::Hostname(s) => write!(f, "hostname: {}", s),
+            ZdmpMetadata::AcquisitionTime(t) => write!(f, "acquisition time: {} (unix)", t),
+            ZdmpMetadata::ToolVersion(s) => write!(f, "tool version: {}", s),
+            ZdmpMetadata::CaseNotes(s) => write!(f, "case notes: {}", s),
+            ZdmpMetadata::Unknown(tag, value) => write!(f, "tag 0x{:x}: {} bytes", tag, value.len()),
+        }
+    }
+}
+
+/// Check the crc32 of a version 1.0 block payload and expand it to
+/// `block_size` bytes.
+pub fn decode_block(
+    zdmp_hdr: &ZdmpFileHdr,
+    block_hdr: &ZdmpBlockHdr,
+    data: &[u8],
+    out: &mut Vec<u8>
+) -> Result<()> {
+    if block_hdr.data_size > zdmp_hdr.block_size {
+        return Err(Error::DumpParseError(
+            format!("Unexpected zdump block size: 0x{:x}",
+                { block_hdr.data_size })));
+    }
+
+    // A block that did not shrink is stored as is.
+    let flags = if block_hdr.data_size == zdmp_hdr.block_size {
+        BLOCK_FLAG_RAW
+    } else {
+        BLOCK_FLAG_COMPRESSED
+    };
+
+    decode_payload(zdmp_hdr.block_size as usize, flags, block_hdr.crc32, data, out)
+}
+
+/// Check the crc32 of a block payload and expand it to `block_size` bytes
+/// according to its `BLOCK_FLAG_*` flags.
+pub fn decode_payload(
+    block_size: usize,
+    flags: u32,
+    crc32: u32,
+    data: &[u8],
+    out: &mut Vec<u8>
+) -> Result<()> {
+    let checksum = CRC32_IEEE.checksum(data);
+    if checksum != crc32 {
+        return Err(Error::DumpParseError(
+            format!("Incorrect crc32. 0x{:x} (expected 0x{:x})",
+                checksum, crc32)));
+    }
+
+    out.clear();
+
+    if flags & BLOCK_FLAG_ENCRYPTED != 0 {
+        return Err(Error::DumpParseError(
+            "Encrypted blocks are not supported.".to_string()));
+    }
+
+    if flags & BLOCK_FLAG_ZERO_RUN != 0 {
+        out.resize(block_size, 0);
+        return Ok(());
+    }
+
+    if flags & BLOCK_FLAG_COMPRESSED == 0 {
+        if data.len() != block_size {
+            return Err(Error::DumpParseError(
+                format!("Unexpected raw block size. 0x{:x} (expected 0x{:x})",
+                    data.len(), block_size)));
+        }
+
+        out.extend_from_slice(data);
+        return Ok(());
+    }
+
+    if let Err(e) = lzxpress::lznt1::decompress2(data, out) {
+        warn!("LZNT1 decompression error: {:?}", e);
+    }
+
+    if out.len() > block_size {
+        return Err(Error::DumpParseError(
+            format!("Incorrect uncompressed block size. 0x{:x} (expected 0x{:x})",
+                out.len(), block_size)));
+    }
+
+    if out.len() < block_size {
+        debug!("uncompressed.len():  0x{:x}", out.len());
+        // Padding for scenarios where the decompressed buffer is smaller.
+        out.resize(block_size, 0);
+    }
+
+    Ok(())
+}
+
 impl ZdmpFile {
     pub fn new(
         in_path: &Path,
         out_path: &Path,
-        silent_mode: bool
+        silent_mode: bool,
+        force: bool
     ) -> Result<Self> {
         info!("Parsing file...");
 
-        let start_time = Instant::now(); 
         let mut file = File::open(in_path)?;
 
-        let mut buf = vec![0; mem::size_of::<ZdmpFileHdr>()];
+        let zdmp_hdr = ZdmpFileHdr::new(&mut file)?;
+        trace_multi!("zdmp_hdr", zdmp_hdr);
 
-        file.seek(std::io::SeekFrom::Start(0))?;
-        file.read_exact(&mut buf)?;
-        let mut rdr = Cursor::new(buf);
+        let version = zdmp_hdr.parse_version(force)?;
 
-        let zdmp_hdr = ZdmpFileHdr::new(&mut rdr)?;
-        trace_multi!("zdmp_hdr", zdmp_hdr);
+        match version {
+            ZdmpVersion::V10 => Self::convert_v10(&mut file, zdmp_hdr, out_path, silent_mode),
+            ZdmpVersion::V20 => Self::convert_v20(file, out_path, silent_mode, force),
+        }
+    }
+
+    /// Decompress a version 2.0 record stream into `out_path`.
+    fn convert_v20(
+        file: File,
+        out_path: &Path,
+        silent_mode: bool,
+        force: bool
+    ) -> Result<Self> {
+        let start_time = Instant::now();
+
+        let mut reader = ZdmpReader::new(file, force)?;
+        for meta in &reader.metadata {
+            info!("Metadata: {}", meta);
+        }
+
+        let mut out_file = File::create(out_path)?;
+        let mut uncompressed = Vec::with_capacity(reader.block_size());
+        let mut uncompressed_size = 0;
+        let mut block_count = 0;
+
+        while let Some(rec) = reader.next_record()? {
+            debug!("Block #{} @ 0x{:x}, flags: 0x{:x}", rec.block_id, rec.offset, rec.flags);
+            reader.decode_record(&rec, &mut uncompressed)?;
 
-        let base = rdr.position();
-        trace_func!("base: 0x{:x}", base);
+            for _ in 0..rec.block_count {
+                // The last block is padded, `file_size` has the real length.
+                let left = reader.uncompressed_size().saturating_sub(uncompressed_size);
+                let len = uncompressed.len().min(left as usize);
+
+                if !silent_mode {
+                    out_file.write_all(&uncompressed[..len])?;
+                }
+
+                uncompressed_size += len as u64;
+                block_count += 1;
+            }
+        }
+
+        Ok(ZdmpFile { hdr: reader.hdr, version: ZdmpVersion::V20,
+            file_size: reader.hdr.file_size,
+            block_count,
+            uncompressed_size: uncompressed_size as usize,
+            start_time, finish_time: Instant::now()})
+    }
+
+    /// Decompress a version 1.0 block stream into `out_path`.
+    fn convert_v10(
+        file: &mut File,
+        zdmp_hdr: ZdmpFileHdr,
+        out_path: &Path,
+        silent_mode: bool
+    ) -> Result<Self> {
+        let start_time = Instant::now();
 
         if zdmp_hdr.data_type != BLOCK_DATA_TYPE_COMPRESSION {
             return Err(Error::DumpParseError(
-                format!("Unsupported dump file.")));  
+                "Unsupported dump file.".to_string()));
         }
 
         if zdmp_hdr.compression_format != COMPRESSION_FORMAT_LZNT1 {
             return Err(Error::DumpParseError(
-                format!("Unsupported compression algorithm.")));  
+                "Unsupported compression algorithm.".to_string()));
         }
 
         let mut block_offset: u64 = ZDMP_BLOCK_START_OFFSET;
         let mut uncompressed_size = 0;
         let mut block_id = 0;
 
-        let block_size = zdmp_hdr.block_size; 
-        let file_size = file.metadata().unwrap().len();
+        let block_size = zdmp_hdr.block_size;
+        let file_size = file.metadata()?.len();
         info!("hdr.block_size:      0x{:x}", block_size);
         info!("file_size:           0x{:x}", file_size);
         info!("zdmp_hdr.file_size:  0x{:x}", zdmp_hdr.file_size as usize);
 
         // Create an empty file if silent_mode is true.
-        let mut out_file = File::create(out_path).expect("Err: Unable to create file"); 
+        let mut out_file = File::create(out_path)?;
 
         let mut uncompressed: Vec<u8> = Vec::with_capacity(block_size as usize);
 
         while block_offset < file_size {
             info!("Block #{} @ 0x{:x}", block_id, block_offset);
             let mut block_hdr_buf = vec![0; mem::size_of::<ZdmpBlockHdr>()];
-            file.seek(std::io::SeekFrom::Start(block_offset))?;
+            file.seek(SeekFrom::Start(block_offset))?;
             if let Err(_val) = file.read_exact(&mut block_hdr_buf) {
-                println!("Error while reading block header #{} @ 0x{:x}. Is file corrupted?", block_id, block_offset);   
+                println!("Error while reading block header #{} @ 0x{:x}. Is file corrupted?", block_id, block_offset);
             }
-            rdr = Cursor::new(block_hdr_buf);
-            let zdmp_block = ZdmpBlockHdr::new(&mut rdr)?;
+            let zdmp_block = ZdmpBlockHdr::new(Cursor::new(block_hdr_buf))?;
 
             trace_multi!("zdmp_block", zdmp_block);
 
@@ -161,84 +516,32 @@ impl ZdmpFile {
                     format!("Unexpected zdump block size: 0x{:x}",
                         { zdmp_block.data_size })));
             }
-            
-            let data_size = zdmp_block.data_size; 
-            let crc32 = zdmp_block.crc32; 
+
+            let data_size = zdmp_block.data_size;
+            let crc32 = zdmp_block.crc32;
             trace!("[{}] block.data_size:     0x{:x}", block_id, data_size);
             trace!("[{}] block.crc32:         0x{:x}", block_id, crc32);
 
             let mut block_data_buf = vec![0; data_size as usize];
             if let Err(_val) = file.read_exact(&mut block_data_buf) {
-                /*
-                return Err(Error::DumpParseError(
-                    format!("Error while reading block @ 0x{:x}, 0x{:x} bytes, limit: 0x{:x}. Is file corrupted?",
-                    block_offset + mem::size_of::<ZdmpBlockHdr>() as u64,
-                    data_size,
-                    block_offset + mem::size_of::<ZdmpBlockHdr>() as u64 + data_size as u64));  
-                */
                 info!("Error while reading block @ 0x{:x}, 0x{:x} bytes, limit: 0x{:x}. Is file corrupted?",
                     block_offset + mem::size_of::<ZdmpBlockHdr>() as u64,
                     data_size,
-                    block_offset + mem::size_of::<ZdmpBlockHdr>() as u64 + data_size as u64);  
-                    
+                    block_offset + mem::size_of::<ZdmpBlockHdr>() as u64 + data_size as u64);
+
                 uncompressed_size += block_data_buf.len();
                 // this should not happen.
-                if silent_mode == false {
-                    let data_bytes: &[u8] = &block_data_buf;
-                    out_file.write_all(data_bytes).expect("Unable to write data");
+                if !silent_mode {
+                    out_file.write_all(&block_data_buf)?;
                 }
             } else {
-                let checksum = CRC32_IEEE.checksum(&block_data_buf);
-                trace!("[{}] crc32:               0x{:x}", block_id, checksum);
+                decode_block(&zdmp_hdr, &zdmp_block, &block_data_buf, &mut uncompressed)?;
 
-                if checksum != zdmp_block.crc32 {
-                    return Err(Error::DumpParseError(
-                        format!("Incorrect crc32. 0x{:x} (expected 0x{:x})",
-                            checksum,  crc32)));  
+                if !silent_mode {
+                    out_file.write_all(&uncompressed)?;
                 }
 
-                if zdmp_block.data_size != block_size {
-                    uncompressed.clear();
-                    match lzxpress::lznt1::decompress2(&block_data_buf, &mut uncompressed) {
-                        Err(e) => println!("{:?}", e),
-                        _ => ()
-                    };
-
-                    if uncompressed.len() != block_size as usize {
-                        info!("[{}] uncompressed.len():  0x{:x}", block_id, uncompressed.len());
-                    }
-
-                    if uncompressed.len() > block_size as usize {
-                        return Err(Error::DumpParseError(
-                            format!("Incorrect uncompressed block size. 0x{:x} (expected 0x{:x})",
-                                uncompressed.len(),  block_size)));  
-                    }
-
-                    if uncompressed.len() < block_size  as usize {
-                        // Padding for scenarios where the decompressed buffer is smaller.
-                        let bytes_left_to_copy = block_size as usize - uncompressed.len();
-                        for _n in 0..bytes_left_to_copy {
-                            uncompressed.push(0);
-                        }
-                        // panic!("yo");
-                    }
-
-                    assert_eq!(uncompressed.len(), block_size as usize);
-
-                    if silent_mode == false {
-                        let data_bytes: &[u8] = &uncompressed;       
-                        out_file.write_all(data_bytes).expect("Unable to write data");
-                    }
-                    
-                    uncompressed_size += uncompressed.len();
-                } else {
-                    uncompressed_size += block_data_buf.len();
-                    // Not compressed.
-                    if silent_mode == false {
-                        let data_bytes: &[u8] = &block_data_buf;
-                        out_file.write_all(data_bytes).expect("Unable to write data");
-                    }
-                }
+                uncompressed_size += uncompressed.len();
 
                 // TODO: Write every n-th data_bytes to reduce the number of disk I/O.
             }
@@ -250,9 +553,10 @@ impl ZdmpFile {
 
         let finish_time = Instant::now();
 
-        Ok(ZdmpFile { hdr: zdmp_hdr, file_size: zdmp_hdr.file_size, 
+        Ok(ZdmpFile { hdr: zdmp_hdr, version: ZdmpVersion::V10,
+            file_size: zdmp_hdr.file_size,
             block_count: block_id,
-            uncompressed_size: uncompressed_size,
-            start_time: start_time, finish_time: finish_time})
-    } 
-}
\ No newline at end of file
+            uncompressed_size,
+            start_time, finish_time})
+    }
+}